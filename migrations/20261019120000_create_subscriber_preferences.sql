@@ -0,0 +1,60 @@
+-- Every subscriber gets a secret token used to access their own
+-- preference center at `/preferences`
+ALTER TABLE subscriptions ADD COLUMN preferences_token TEXT NULL;
+ALTER TABLE subscriptions
+    ADD COLUMN delivery_frequency TEXT NOT NULL DEFAULT 'immediate';
+
+-- Backfill tokens for historical entries
+UPDATE subscriptions
+    SET preferences_token = lower(hex(randomblob(16)))
+    WHERE preferences_token IS NULL;
+
+CREATE UNIQUE INDEX subscriptions_preferences_token_idx
+    ON subscriptions (preferences_token);
+
+CREATE TABLE lists (
+    id TEXT PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE topics (
+    id TEXT PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE subscription_lists (
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id),
+    list_id TEXT NOT NULL
+        REFERENCES lists (id),
+    -- UTC, use chrono to configure timezone
+    subscribed_at TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, list_id)
+);
+
+CREATE TABLE subscription_topics (
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id),
+    topic_id TEXT NOT NULL
+        REFERENCES topics (id),
+    -- UTC, use chrono to configure timezone
+    subscribed_at TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, topic_id)
+);
+
+-- Audit trail of every change a subscriber makes to their own
+-- subscription, kept as proof of consent
+CREATE TABLE preference_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id),
+    field TEXT NOT NULL,
+    old_value TEXT NULL,
+    new_value TEXT NULL,
+    -- UTC, use chrono to configure timezone
+    changed_at TEXT NOT NULL
+);
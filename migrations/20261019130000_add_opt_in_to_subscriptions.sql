@@ -0,0 +1,8 @@
+-- Opt-in mode used when a subscriber signed up, kept for compliance.
+-- Historical entries all went through double opt-in.
+ALTER TABLE subscriptions
+    ADD COLUMN opt_in TEXT NOT NULL DEFAULT 'double';
+
+-- Per-list override of the global opt-in mode, `NULL` uses the global
+-- setting from `AppSettings`
+ALTER TABLE lists ADD COLUMN opt_in TEXT NULL;
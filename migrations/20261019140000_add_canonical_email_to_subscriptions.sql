@@ -0,0 +1,15 @@
+-- Canonical (lowercase, punycode domain) email used for uniqueness
+-- checks and lookups. `email` keeps the address as typed.
+ALTER TABLE subscriptions ADD COLUMN email_canonical TEXT NULL;
+
+-- Backfill historical entries. SQLite cannot punycode, so this is an
+-- approximation for internationalized domains. Duplicates that already
+-- exist keep `NULL` so only the oldest entry claims the address.
+UPDATE subscriptions
+    SET email_canonical = lower(trim(email))
+    WHERE rowid IN (
+        SELECT MIN(rowid) FROM subscriptions GROUP BY lower(trim(email))
+    );
+
+CREATE UNIQUE INDEX subscriptions_email_canonical_idx
+    ON subscriptions (email_canonical);
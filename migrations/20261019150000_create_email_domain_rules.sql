@@ -0,0 +1,8 @@
+-- Operator maintained allowlist and blocklist of email domains
+CREATE TABLE email_domain_rules (
+    domain TEXT PRIMARY KEY NOT NULL,
+    rule TEXT NOT NULL CHECK (rule IN ('allow', 'block')),
+    note TEXT NOT NULL DEFAULT '',
+    -- UTC, use chrono to configure timezone
+    created_at TEXT NOT NULL
+);
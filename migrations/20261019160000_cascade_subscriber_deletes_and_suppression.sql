@@ -0,0 +1,68 @@
+-- SQLite cannot alter a foreign key, rebuild every table referencing
+-- `subscriptions` so erasing a subscriber removes their rows too
+CREATE TABLE subscription_tokens_cascade (
+    subscription_token TEXT NOT NULL,
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscription_token)
+);
+INSERT INTO subscription_tokens_cascade (subscription_token, subscriber_id)
+    SELECT subscription_token, subscriber_id
+    FROM subscription_tokens;
+DROP TABLE subscription_tokens;
+ALTER TABLE subscription_tokens_cascade RENAME TO subscription_tokens;
+
+CREATE TABLE subscription_lists_cascade (
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id TEXT NOT NULL
+        REFERENCES lists (id),
+    -- UTC, use chrono to configure timezone
+    subscribed_at TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, list_id)
+);
+INSERT INTO subscription_lists_cascade (subscriber_id, list_id, subscribed_at)
+    SELECT subscriber_id, list_id, subscribed_at
+    FROM subscription_lists;
+DROP TABLE subscription_lists;
+ALTER TABLE subscription_lists_cascade RENAME TO subscription_lists;
+
+CREATE TABLE subscription_topics_cascade (
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    topic_id TEXT NOT NULL
+        REFERENCES topics (id),
+    -- UTC, use chrono to configure timezone
+    subscribed_at TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, topic_id)
+);
+INSERT INTO subscription_topics_cascade (subscriber_id, topic_id, subscribed_at)
+    SELECT subscriber_id, topic_id, subscribed_at
+    FROM subscription_topics;
+DROP TABLE subscription_topics;
+ALTER TABLE subscription_topics_cascade RENAME TO subscription_topics;
+
+CREATE TABLE preference_changes_cascade (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    field TEXT NOT NULL,
+    old_value TEXT NULL,
+    new_value TEXT NULL,
+    -- UTC, use chrono to configure timezone
+    changed_at TEXT NOT NULL
+);
+INSERT INTO preference_changes_cascade
+    (id, subscriber_id, field, old_value, new_value, changed_at)
+    SELECT id, subscriber_id, field, old_value, new_value, changed_at
+    FROM preference_changes;
+DROP TABLE preference_changes;
+ALTER TABLE preference_changes_cascade RENAME TO preference_changes;
+
+-- Erased subscribers, only a keyed hash of the canonical email is kept
+-- so the address cannot be signed up again by accident
+CREATE TABLE suppressed_emails (
+    email_hash TEXT PRIMARY KEY NOT NULL,
+    -- UTC, use chrono to configure timezone
+    suppressed_at TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Why we may email subscribers imported as confirmed, e.g., where and
+-- when they originally opted in
+ALTER TABLE subscriptions ADD COLUMN consent_note TEXT NULL;
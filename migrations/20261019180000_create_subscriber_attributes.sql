@@ -0,0 +1,20 @@
+-- Admin managed schema of custom subscriber attributes, e.g., company
+-- or plan, used for personalization and audience filters
+CREATE TABLE attribute_definitions (
+    key TEXT PRIMARY KEY NOT NULL,
+    label TEXT NOT NULL,
+    kind TEXT NOT NULL
+        CHECK (kind IN ('text', 'number', 'boolean', 'date')),
+    -- UTC, use chrono to configure timezone
+    created_at TEXT NOT NULL
+);
+
+-- Values are stored normalized as text, see `AttributeKind::normalize`
+CREATE TABLE subscriber_attributes (
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    key TEXT NOT NULL
+        REFERENCES attribute_definitions (key) ON DELETE CASCADE,
+    value TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, key)
+);
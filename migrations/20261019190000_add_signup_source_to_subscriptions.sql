@@ -0,0 +1,9 @@
+-- Where a subscription came from, to compare acquisition channels
+ALTER TABLE subscriptions ADD COLUMN referrer TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_source TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_medium TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_campaign TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_term TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_content TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN landing_page TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN form_id TEXT NULL;
@@ -0,0 +1,2 @@
+-- Tokens issued before this migration have no timestamp and never expire
+ALTER TABLE subscription_tokens ADD COLUMN created_at TEXT NULL;
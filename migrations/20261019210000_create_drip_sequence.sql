@@ -0,0 +1,20 @@
+-- Welcome drip sequence, one step per delay after confirmation
+CREATE TABLE drip_steps (
+    delay_days INTEGER NOT NULL CHECK (delay_days >= 0),
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (delay_days)
+);
+
+-- Where each confirmed subscriber is in the sequence
+CREATE TABLE drip_enrollments (
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    enrolled_at TEXT NOT NULL,
+    last_step_delay INTEGER NULL,
+    last_sent_at TEXT NULL,
+    stopped_at TEXT NULL,
+    PRIMARY KEY (subscriber_id)
+);
@@ -0,0 +1,43 @@
+-- Rebuilding `subscriptions` to add a CHECK constraint would cascade
+-- deletes into every table referencing it, as migrations run with
+-- foreign keys on. Enforce the same check with triggers instead.
+CREATE TRIGGER subscriptions_status_check_insert
+BEFORE INSERT ON subscriptions
+WHEN NEW.status NOT IN (
+    'pending_confirmation', 'confirmed', 'unsubscribed', 'bounced',
+    'complained', 'erased'
+)
+BEGIN
+    SELECT RAISE(ABORT, 'CHECK constraint failed: subscriptions.status');
+END;
+
+CREATE TRIGGER subscriptions_status_check_update
+BEFORE UPDATE OF status ON subscriptions
+WHEN NEW.status NOT IN (
+    'pending_confirmation', 'confirmed', 'unsubscribed', 'bounced',
+    'complained', 'erased'
+)
+BEGIN
+    SELECT RAISE(ABORT, 'CHECK constraint failed: subscriptions.status');
+END;
+
+ALTER TABLE subscriptions ADD COLUMN status_changed_at TEXT NULL;
+UPDATE subscriptions SET status_changed_at = subscribed_at;
+
+-- Every status change, kept after erasure without any contact details
+CREATE TABLE subscriber_status_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscriber_id TEXT NOT NULL,
+    from_status TEXT NULL,
+    to_status TEXT NOT NULL CHECK (to_status IN (
+        'pending_confirmation', 'confirmed', 'unsubscribed', 'bounced',
+        'complained', 'erased'
+    )),
+    changed_at TEXT NOT NULL
+);
+CREATE INDEX subscriber_status_history_subscriber_idx
+    ON subscriber_status_history (subscriber_id);
+
+INSERT INTO subscriber_status_history (subscriber_id, to_status, changed_at)
+SELECT id, status, coalesce(subscribed_at, datetime('now'))
+FROM subscriptions;
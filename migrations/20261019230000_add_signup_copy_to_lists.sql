@@ -0,0 +1,3 @@
+-- Copy shown on the list's signup page, defaults to its name and description
+ALTER TABLE lists ADD COLUMN signup_heading TEXT NULL;
+ALTER TABLE lists ADD COLUMN signup_intro TEXT NULL;
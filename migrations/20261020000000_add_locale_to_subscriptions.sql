@@ -0,0 +1,2 @@
+-- Language the subscriber reads our emails and pages in
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1,32 @@
+-- Engagement with newsletter issues, used to find inactive subscribers
+ALTER TABLE subscriptions
+    ADD COLUMN issues_since_engagement INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN last_engaged_at TEXT NULL;
+-- Set while waiting on an answer to the re-engagement email
+ALTER TABLE subscriptions ADD COLUMN reengagement_sent_at TEXT NULL;
+-- Pending subscribers are only reminded once
+ALTER TABLE subscriptions ADD COLUMN confirmation_reminder_sent_at TEXT NULL;
+
+CREATE TABLE engagement_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('open', 'click', 'keep')),
+    -- Destination of a click
+    url TEXT NULL,
+    occurred_at TEXT NOT NULL
+);
+CREATE INDEX engagement_events_subscriber_id
+    ON engagement_events (subscriber_id);
+
+-- One row per run of the pruning job, shown in the admin area
+CREATE TABLE pruning_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    pending_reminded INTEGER NOT NULL,
+    pending_deleted INTEGER NOT NULL,
+    reengagement_sent INTEGER NOT NULL,
+    suppressed INTEGER NOT NULL,
+    failed INTEGER NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Every newsletter issue published, kept for the admin area
+CREATE TABLE newsletter_issues (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    -- JSON object of locale to `{ title, content }` variants
+    translations TEXT NOT NULL DEFAULT '{}',
+    -- JSON object of the attribute values recipients must match
+    audience TEXT NOT NULL DEFAULT '{}',
+    author_id TEXT NOT NULL REFERENCES users (user_id),
+    -- Checked by `domain::IssueStatus`
+    status TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    published_at TEXT NULL
+);
+CREATE INDEX newsletter_issues_created_at ON newsletter_issues (created_at);
@@ -0,0 +1,21 @@
+-- One row per recipient of a newsletter issue, drained by the delivery
+-- worker
+CREATE TABLE issue_delivery_queue (
+    newsletter_issue_id TEXT NOT NULL
+        REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    subscriber_id TEXT NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- Checked by `domain::DeliveryStatus`
+    status TEXT NOT NULL DEFAULT 'queued',
+    -- Set while a worker is sending, claims older than the timeout are
+    -- picked up again
+    claimed_at TEXT NULL,
+    -- Outcome of the last attempt
+    error TEXT NULL,
+    finished_at TEXT NULL,
+    enqueued_at TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+CREATE INDEX issue_delivery_queue_status ON issue_delivery_queue (status);
+CREATE INDEX issue_delivery_queue_subscriber_id
+    ON issue_delivery_queue (subscriber_id);
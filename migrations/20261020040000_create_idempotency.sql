@@ -0,0 +1,13 @@
+-- Saved responses of `POST /newsletters`, replayed for retried requests
+CREATE TABLE idempotency (
+    user_id TEXT NOT NULL REFERENCES users (user_id),
+    idempotency_key TEXT NOT NULL,
+    -- NULL while the first request is still being processed
+    response_status_code INTEGER NULL,
+    -- JSON array of `[name, value bytes]` pairs
+    response_headers TEXT NULL,
+    response_body BLOB NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
+CREATE INDEX idempotency_created_at ON idempotency (created_at);
@@ -0,0 +1,5 @@
+-- Transient delivery failures are retried with a backoff
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_attempts INTEGER NOT NULL DEFAULT 0;
+-- Queued rows are not claimed before this time
+ALTER TABLE issue_delivery_queue ADD COLUMN execute_after TEXT NULL;
@@ -0,0 +1,4 @@
+-- UTC time a scheduled issue is due, NULL for issues sent straight away
+ALTER TABLE newsletter_issues ADD COLUMN send_at TEXT NULL;
+CREATE INDEX newsletter_issues_status_send_at
+    ON newsletter_issues (status, send_at);
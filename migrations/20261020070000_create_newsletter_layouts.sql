@@ -0,0 +1,13 @@
+-- Reusable header and footer wrapped around the body of an issue
+CREATE TABLE newsletter_layouts(
+    name TEXT NOT NULL PRIMARY KEY,
+    html_header TEXT NOT NULL,
+    html_footer TEXT NOT NULL,
+    text_header TEXT NOT NULL,
+    text_footer TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+ALTER TABLE newsletter_issues
+    ADD COLUMN layout TEXT NULL REFERENCES newsletter_layouts (name);
@@ -0,0 +1,6 @@
+-- Existing subscribers confirm before joining another list or coming
+-- back after unsubscribing, the token remembers what it confirms
+ALTER TABLE subscription_tokens ADD COLUMN list_id TEXT NULL
+    REFERENCES lists (id) ON DELETE CASCADE;
+ALTER TABLE subscription_tokens ADD COLUMN resubscribe BOOLEAN NOT NULL
+    DEFAULT FALSE;
@@ -0,0 +1,59 @@
+//! src/attributes.rs
+//!
+//! Storage of custom subscriber attributes and their schema, see
+//! `domain::AttributeSchema` for validation.
+
+use crate::domain::{AttributeDefinition, AttributeKind, AttributeSchema};
+use anyhow::Context;
+use sqlx::{Executor, Sqlite, Transaction};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Get attribute schema", skip(executor))]
+pub async fn get_attribute_schema<'c>(
+    executor: impl Executor<'c, Database = Sqlite>,
+) -> Result<AttributeSchema, anyhow::Error> {
+    let definitions = sqlx::query!(
+        r#"SELECT key AS "key!", label, kind FROM attribute_definitions
+        ORDER BY key"#
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to query attribute definitions.")?
+    .into_iter()
+    .map(|r| {
+        Ok(AttributeDefinition {
+            key: r.key,
+            label: r.label,
+            kind: AttributeKind::parse(&r.kind)
+                .map_err(|e| anyhow::anyhow!(e))?,
+        })
+    })
+    .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    Ok(AttributeSchema::new(definitions))
+}
+
+/// Store already validated attributes, replacing previous values
+#[tracing::instrument(name = "Set subscriber attributes", skip(transaction))]
+pub async fn set_subscriber_attributes(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    attributes: &BTreeMap<String, String>,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    for (key, value) in attributes {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_attributes (subscriber_id, key, value)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (subscriber_id, key) DO UPDATE SET value = excluded.value
+            "#,
+            subscriber_id,
+            key,
+            value,
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(())
+}
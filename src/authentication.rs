@@ -1,6 +1,12 @@
 use crate::telemetry::spawn_blocking_with_tracing;
 use anyhow::Context;
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use base64::Engine;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::SqlitePool;
 
@@ -17,6 +23,147 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
+/// Authenticated user, available to admin handlers as an `Extension`
+#[derive(Clone, Copy, Debug)]
+pub struct UserId(pub uuid::Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub fn basic_authentication(
+    headers: &HeaderMap,
+) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimiter
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| {
+            anyhow::anyhow!("A username must be provided in 'Basic' auth.")
+        })?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| {
+            anyhow::anyhow!("A password must be provided in 'Basic' auth.")
+        })?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
+
+/// Middleware guarding the admin area with `Basic` authentication
+///
+/// On success the `UserId` is added to the request extensions.
+pub async fn require_admin(
+    Extension(pool): Extension<SqlitePool>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let unauthorized = || {
+        let mut resp = (StatusCode::UNAUTHORIZED).into_response();
+        let header_value =
+            HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+        resp.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, header_value);
+        resp
+    };
+
+    let credentials = match basic_authentication(request.headers()) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Rejected admin request.");
+            return unauthorized();
+        }
+    };
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            request.extensions_mut().insert(UserId(user_id));
+            next.run(request).await
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Rejected admin request.");
+            unauthorized()
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!(error.cause_chain = ?e, "Admin authentication error.");
+            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+/// Middleware rejecting state-changing requests made from another site
+///
+/// Browsers attach cached `Basic` credentials to cross-site form
+/// submissions too, so a third-party page could otherwise act as a
+/// signed-in admin. Browsers say where such a request comes from with
+/// `Sec-Fetch-Site`, `Origin` or `Referer`; requests carrying none of
+/// them do not come from a browser and are let through.
+pub async fn reject_cross_site_requests(
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method();
+    if method == Method::GET || method == Method::HEAD {
+        return next.run(request).await;
+    }
+    let headers = request.headers();
+    let value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let host = value("Host");
+    let same_site = match value("Sec-Fetch-Site") {
+        Some(site) => site == "same-origin" || site == "none",
+        None => match value("Origin").or(value("Referer")) {
+            Some(source) => is_same_origin(source, host),
+            None => true,
+        },
+    };
+    if !same_site {
+        tracing::warn!(
+            ?method,
+            uri = %request.uri(),
+            "Rejected a cross-site request."
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            "Cross-site requests are not allowed.",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether the `Origin` or `Referer` value points at `host`
+fn is_same_origin(source: &str, host: Option<&str>) -> bool {
+    let Ok(url) = url::Url::parse(source) else {
+        return false;
+    };
+    let authority = match (url.host_str(), url.port()) {
+        (Some(h), Some(port)) => format!("{h}:{port}"),
+        (Some(h), None) => h.to_string(),
+        (None, _) => return false,
+    };
+    host == Some(authority.as_str())
+}
+
 #[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
@@ -98,3 +245,19 @@ fn verify_password_hash(
         .context("Invalid password.")
         .map_err(AuthError::InvalidCredentials)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_same_origin;
+
+    #[test]
+    fn only_the_same_host_and_port_is_the_same_origin() {
+        let host = Some("127.0.0.1:8000");
+        assert!(is_same_origin("http://127.0.0.1:8000", host));
+        assert!(is_same_origin("http://127.0.0.1:8000/admin/issues", host));
+        assert!(!is_same_origin("http://127.0.0.1:9000", host));
+        assert!(!is_same_origin("https://evil.example.com", host));
+        assert!(!is_same_origin("null", host));
+        assert!(!is_same_origin("http://127.0.0.1:8000", None));
+    }
+}
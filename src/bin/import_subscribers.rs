@@ -0,0 +1,116 @@
+// Copyright (c) 2024 David Kalliecharan
+//
+// SPDX-License-Identifier: BSD-2-Clause
+
+//! Import subscribers from a CSV file
+//!
+//! ```text
+//! import_subscribers <file.csv> [--mode dry_run|pending|confirmed]
+//!     [--name-column name] [--email-column email] [--consent-note NOTE]
+//!     [--attribute COLUMN=KEY]...
+//! ```
+//!
+//! Uses the same settings as the server, see `APP_ENV`. Defaults to a
+//! dry run so nothing is stored until asked for.
+
+use std::process::ExitCode;
+use zero2prod_axum::{
+    import::{import_subscribers, ImportMode, ImportOptions},
+    settings::read_settings_file,
+    startup::{get_connection_pool, HmacSecret},
+    telemetry::{get_subscriber, init_subscriber},
+};
+
+const USAGE: &str = "Usage: import_subscribers <file.csv> \
+    [--mode dry_run|pending|confirmed] [--name-column COLUMN] \
+    [--email-column COLUMN] [--consent-note NOTE] [--attribute COLUMN=KEY]...";
+
+fn parse_args() -> Result<(String, ImportOptions), String> {
+    let mut path = None;
+    let mut options = ImportOptions {
+        mode: ImportMode::DryRun,
+        name_column: "name".into(),
+        email_column: "email".into(),
+        attribute_columns: vec![],
+        consent_note: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value."));
+        match arg.as_str() {
+            "--mode" => options.mode = ImportMode::parse(&value()?)?,
+            "--name-column" => options.name_column = value()?,
+            "--email-column" => options.email_column = value()?,
+            "--attribute" => options
+                .attribute_columns
+                .push(ImportOptions::parse_attribute_column(&value()?)?),
+            "--consent-note" => options.consent_note = Some(value()?),
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(format!("Unexpected argument {arg}.\n{USAGE}")),
+        }
+    }
+    Ok((path.ok_or(USAGE)?, options))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let subscriber = get_subscriber(
+        "import_subscribers".into(),
+        "warn".into(),
+        std::io::stderr,
+    );
+    init_subscriber(subscriber);
+
+    let (path, options) = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let settings = read_settings_file().expect("Failed to read settings file.");
+    let base_url = settings
+        .normalized_base_url()
+        .expect("Invalid base url!")
+        .to_string();
+    let pool = get_connection_pool(&settings.database).await;
+    let email_client = settings.email_client.client();
+    let secret = HmacSecret(settings.hmac_secret.clone());
+
+    let report = match import_subscribers(
+        &pool,
+        &email_client,
+        &base_url,
+        &secret,
+        file,
+        &options,
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for error in &report.errors {
+        println!("line {}: {}", error.line, error.message);
+    }
+    let verb = if options.mode == ImportMode::DryRun {
+        "Would import"
+    } else {
+        "Imported"
+    };
+    println!("{verb} {} of {} rows.", report.imported, report.rows);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,426 @@
+//! src/bot_protection.rs
+//!
+//! Layered protection for the public subscription form. Every check is
+//! cheap and local, apart from the CAPTCHA which is verified last. The
+//! data export form shares its rate limiting.
+
+use crate::settings::{BotProtectionSettings, CaptchaSettings};
+use crate::startup::HmacSecret;
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Name of the hidden form field only bots fill in
+pub const HONEYPOT_FIELD: &str = "website";
+
+/// Form tokens older than this are considered replayed
+const MAX_FORM_TOKEN_AGE_SECONDS: i64 = 24 * 60 * 60;
+
+/// Why a subscription attempt was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionReason {
+    Honeypot,
+    MissingFormToken,
+    InvalidFormToken,
+    SubmittedTooFast,
+    IpRateLimited,
+    DomainRateLimited,
+    CaptchaFailed,
+}
+
+impl RejectionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::Honeypot => "honeypot field was filled in",
+            RejectionReason::MissingFormToken => "form token is missing",
+            RejectionReason::InvalidFormToken => "form token is invalid",
+            RejectionReason::SubmittedTooFast => "form was submitted too fast",
+            RejectionReason::IpRateLimited => "too many attempts from this IP",
+            RejectionReason::DomainRateLimited => {
+                "too many attempts for this email domain"
+            }
+            RejectionReason::CaptchaFailed => "CAPTCHA verification failed",
+        }
+    }
+}
+
+impl std::fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Verifies the response of a CAPTCHA widget
+///
+/// Implement this trait to plug in another provider.
+#[async_trait::async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    async fn verify(
+        &self,
+        response: Option<&str>,
+        remote_ip: Option<IpAddr>,
+    ) -> Result<bool, anyhow::Error>;
+}
+
+/// Accepts every submission, used when no CAPTCHA is configured
+pub struct DisabledCaptcha;
+
+#[async_trait::async_trait]
+impl CaptchaVerifier for DisabledCaptcha {
+    async fn verify(
+        &self,
+        _response: Option<&str>,
+        _remote_ip: Option<IpAddr>,
+    ) -> Result<bool, anyhow::Error> {
+        Ok(true)
+    }
+}
+
+/// Verifies against a `siteverify` style endpoint, as used by both
+/// hCaptcha and reCAPTCHA
+pub struct HttpCaptchaVerifier {
+    http_client: reqwest::Client,
+    verify_url: String,
+    secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl HttpCaptchaVerifier {
+    pub fn new(verify_url: String, secret: Secret<String>) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            verify_url,
+            secret,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CaptchaVerifier for HttpCaptchaVerifier {
+    async fn verify(
+        &self,
+        response: Option<&str>,
+        remote_ip: Option<IpAddr>,
+    ) -> Result<bool, anyhow::Error> {
+        let Some(response) = response.filter(|r| !r.is_empty()) else {
+            return Ok(false);
+        };
+        let remote_ip = remote_ip.map(|ip| ip.to_string()).unwrap_or_default();
+        let outcome: SiteVerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[
+                ("secret", self.secret.expose_secret().as_str()),
+                ("response", response),
+                ("remoteip", remote_ip.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(outcome.success)
+    }
+}
+
+/// Idle keys are forgotten once every this many checks
+const SWEEP_EVERY_CHECKS: u64 = 1024;
+
+/// Sliding window rate limiter keyed by an arbitrary string
+pub struct RateLimiter {
+    max_requests: usize,
+    window: Duration,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+    checks: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: usize, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            hits: Mutex::new(HashMap::new()),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    /// Record an attempt for `key`, returns `false` if the limit is hit
+    pub fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+        // Forget idle keys so the map cannot grow without bound, but not
+        // on every check as it walks all of them
+        if self.checks.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY_CHECKS
+            == SWEEP_EVERY_CHECKS - 1
+        {
+            hits.retain(|_, h| {
+                h.back()
+                    .is_some_and(|t| now.duration_since(*t) < self.window)
+            });
+        }
+
+        let entry = hits.entry(key.to_string()).or_default();
+        while entry
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.window)
+        {
+            entry.pop_front();
+        }
+        if entry.len() >= self.max_requests {
+            return false;
+        }
+        entry.push_back(now);
+        true
+    }
+}
+
+/// Shared state for all bot protection checks
+pub struct BotProtection {
+    settings: BotProtectionSettings,
+    ip_limiter: RateLimiter,
+    domain_limiter: RateLimiter,
+    data_export_limiter: RateLimiter,
+    captcha: Arc<dyn CaptchaVerifier>,
+}
+
+impl BotProtection {
+    pub fn new(settings: BotProtectionSettings) -> Self {
+        let captcha: Arc<dyn CaptchaVerifier> = match &settings.captcha {
+            CaptchaSettings::Disabled => Arc::new(DisabledCaptcha),
+            CaptchaSettings::Http { verify_url, secret } => Arc::new(
+                HttpCaptchaVerifier::new(verify_url.clone(), secret.clone()),
+            ),
+        };
+        Self::with_captcha(settings, captcha)
+    }
+
+    pub fn with_captcha(
+        settings: BotProtectionSettings,
+        captcha: Arc<dyn CaptchaVerifier>,
+    ) -> Self {
+        let window = Duration::from_secs(settings.rate_limit_window_seconds);
+        Self {
+            ip_limiter: RateLimiter::new(settings.max_signups_per_ip, window),
+            domain_limiter: RateLimiter::new(
+                settings.max_signups_per_domain,
+                window,
+            ),
+            data_export_limiter: RateLimiter::new(
+                settings.max_data_exports_per_email,
+                window,
+            ),
+            settings,
+            captcha,
+        }
+    }
+
+    /// The address of the client, taken from `X-Forwarded-For` only if
+    /// we are configured to trust it. Clients can send the header
+    /// themselves, so only the rightmost entry, appended by our reverse
+    /// proxy, is used.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+        if self.settings.trust_x_forwarded_for {
+            let forwarded = headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .next_back()
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit(',').next())
+                .and_then(|v| v.trim().parse().ok());
+            if let Some(ip) = forwarded {
+                return ip;
+            }
+        }
+        peer.ip()
+    }
+
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), RejectionReason> {
+        if self.ip_limiter.check(&ip.to_string()) {
+            Ok(())
+        } else {
+            Err(RejectionReason::IpRateLimited)
+        }
+    }
+
+    /// Whether another data export email may be sent to the address
+    /// behind `canonical_email`
+    pub fn check_data_export(&self, canonical_email: &str) -> bool {
+        self.data_export_limiter.check(canonical_email)
+    }
+
+    pub fn check_honeypot(
+        &self,
+        honeypot: Option<&str>,
+    ) -> Result<(), RejectionReason> {
+        match honeypot {
+            Some(value) if !value.is_empty() => Err(RejectionReason::Honeypot),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_form_token(
+        &self,
+        form_token: Option<&str>,
+        secret: &HmacSecret,
+    ) -> Result<(), RejectionReason> {
+        let Some(form_token) = form_token.filter(|t| !t.is_empty()) else {
+            return if self.settings.require_form_token {
+                Err(RejectionReason::MissingFormToken)
+            } else {
+                Ok(())
+            };
+        };
+        let issued_at = verify_form_token(form_token, secret)
+            .ok_or(RejectionReason::InvalidFormToken)?;
+        let age = chrono::Utc::now().timestamp() - issued_at;
+        if !(0..=MAX_FORM_TOKEN_AGE_SECONDS).contains(&age) {
+            return Err(RejectionReason::InvalidFormToken);
+        }
+        if age < self.settings.min_submit_seconds {
+            return Err(RejectionReason::SubmittedTooFast);
+        }
+        Ok(())
+    }
+
+    pub fn check_domain(&self, domain: &str) -> Result<(), RejectionReason> {
+        if self.domain_limiter.check(&domain.to_lowercase()) {
+            Ok(())
+        } else {
+            Err(RejectionReason::DomainRateLimited)
+        }
+    }
+
+    pub async fn check_captcha(
+        &self,
+        response: Option<&str>,
+        remote_ip: IpAddr,
+    ) -> Result<Result<(), RejectionReason>, anyhow::Error> {
+        if self.captcha.verify(response, Some(remote_ip)).await? {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(RejectionReason::CaptchaFailed))
+        }
+    }
+}
+
+fn form_token_tag(issued_at: i64, secret: &HmacSecret) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(
+        secret.0.expose_secret().as_bytes(),
+    )
+    .unwrap();
+    mac.update(format!("form_issued_at={issued_at}").as_bytes());
+    mac
+}
+
+/// Sign the time a form was rendered, returns `<timestamp>.<hex tag>`
+pub fn issue_form_token(issued_at: i64, secret: &HmacSecret) -> String {
+    let tag = form_token_tag(issued_at, secret).finalize().into_bytes();
+    format!("{issued_at}.{tag:x}")
+}
+
+/// Returns the signed timestamp if the token is authentic
+fn verify_form_token(form_token: &str, secret: &HmacSecret) -> Option<i64> {
+    let (issued_at, tag) = form_token.split_once('.')?;
+    let issued_at: i64 = issued_at.parse().ok()?;
+    let tag = hex::decode(tag).ok()?;
+    form_token_tag(issued_at, secret).verify_slice(&tag).ok()?;
+    Some(issued_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        issue_form_token, verify_form_token, BotProtection, RateLimiter,
+        SWEEP_EVERY_CHECKS,
+    };
+    use crate::settings::{BotProtectionSettings, CaptchaSettings};
+    use crate::startup::HmacSecret;
+    use axum::http::HeaderMap;
+    use claims::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("a-very-secret-key".to_string()))
+    }
+
+    #[test]
+    fn issued_form_tokens_are_verified() {
+        let token = issue_form_token(1_700_000_000, &secret());
+        assert_some_eq!(verify_form_token(&token, &secret()), 1_700_000_000);
+    }
+
+    #[test]
+    fn tampered_form_tokens_are_rejected() {
+        let token = issue_form_token(1_700_000_000, &secret());
+        let (_, tag) = token.split_once('.').unwrap();
+        let tampered = format!("1600000000.{tag}");
+        assert_none!(verify_form_token(&tampered, &secret()));
+        assert_none!(verify_form_token("garbage", &secret()));
+    }
+
+    #[test]
+    fn rate_limiter_blocks_after_the_limit_per_key() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check("a"));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        assert!(limiter.check("b"));
+    }
+
+    #[test]
+    fn rate_limiter_forgets_attempts_outside_the_window() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10));
+        assert!(limiter.check("a"));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.check("a"));
+    }
+
+    #[test]
+    fn rate_limiter_sweeps_idle_keys_periodically() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(50));
+        assert!(limiter.check("idle"));
+        std::thread::sleep(Duration::from_millis(100));
+        for i in 1..SWEEP_EVERY_CHECKS {
+            limiter.check(&i.to_string());
+        }
+        assert!(!limiter.hits.lock().unwrap().contains_key("idle"));
+    }
+
+    #[test]
+    fn only_the_proxy_entry_of_x_forwarded_for_is_trusted() {
+        let protection = BotProtection::new(BotProtectionSettings {
+            require_form_token: true,
+            min_submit_seconds: 3,
+            max_signups_per_ip: 20,
+            max_signups_per_domain: 100,
+            max_data_exports_per_email: 1,
+            rate_limit_window_seconds: 3600,
+            trust_x_forwarded_for: true,
+            captcha: CaptchaSettings::Disabled,
+        });
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers
+            .insert("X-Forwarded-For", "1.2.3.4, 203.0.113.7".parse().unwrap());
+
+        let ip = protection.client_ip(&headers, peer);
+
+        assert_eq!(ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(protection.client_ip(&HeaderMap::new(), peer), peer.ip());
+    }
+}
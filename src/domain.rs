@@ -1,7 +1,25 @@
+mod delivery_frequency;
+mod delivery_status;
+mod issue_status;
+mod locale;
 mod new_subscriber;
+mod opt_in;
+mod signup_source;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_status;
 
+pub use delivery_frequency::DeliveryFrequency;
+pub use delivery_status::DeliveryStatus;
+pub use issue_status::IssueStatus;
+pub use locale::Locale;
 pub use new_subscriber::NewSubscriber;
+pub use opt_in::OptIn;
+pub use signup_source::SignupSource;
+pub use subscriber_attributes::{
+    AttributeDefinition, AttributeKind, AttributeSchema,
+};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_status::SubscriberStatus;
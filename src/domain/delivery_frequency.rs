@@ -0,0 +1,71 @@
+/// How often a subscriber wants to receive our newsletter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryFrequency {
+    Immediate,
+    Daily,
+    Weekly,
+}
+
+impl DeliveryFrequency {
+    pub const ALL: [DeliveryFrequency; 3] = [
+        DeliveryFrequency::Immediate,
+        DeliveryFrequency::Daily,
+        DeliveryFrequency::Weekly,
+    ];
+
+    pub fn parse(s: &str) -> Result<DeliveryFrequency, String> {
+        match s.trim().to_lowercase().as_str() {
+            "immediate" => Ok(Self::Immediate),
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            other => Err(format!(
+                "\"{}\" is not a valid delivery frequency. \
+                Use either `immediate`, `daily` or `weekly`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryFrequency::Immediate => "immediate",
+            DeliveryFrequency::Daily => "daily",
+            DeliveryFrequency::Weekly => "weekly",
+        }
+    }
+}
+
+impl AsRef<str> for DeliveryFrequency {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryFrequency;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_frequencies_are_parsed_successfully() {
+        for frequency in DeliveryFrequency::ALL {
+            assert_ok_eq!(
+                DeliveryFrequency::parse(frequency.as_str()),
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn frequencies_are_case_insensitive() {
+        assert_ok_eq!(
+            DeliveryFrequency::parse(" Weekly "),
+            DeliveryFrequency::Weekly
+        );
+    }
+
+    #[test]
+    fn unknown_frequency_is_rejected() {
+        assert_err!(DeliveryFrequency::parse("hourly"));
+    }
+}
@@ -0,0 +1,71 @@
+/// Where a newsletter issue is in its publication
+///
+/// * `Draft`: still being written, can be edited and test-sent
+/// * `Scheduled`: waiting for its send time
+/// * `Cancelled`: scheduled, then called off before being sent
+/// * `Publishing`: queued, the delivery worker is sending it
+/// * `Published`: every recipient was handled
+/// * `Failed`: every recipient was handled and none could be sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    Cancelled,
+    Publishing,
+    Published,
+    Failed,
+}
+
+impl IssueStatus {
+    pub const ALL: [IssueStatus; 6] = [
+        IssueStatus::Draft,
+        IssueStatus::Scheduled,
+        IssueStatus::Cancelled,
+        IssueStatus::Publishing,
+        IssueStatus::Published,
+        IssueStatus::Failed,
+    ];
+
+    pub fn parse(s: &str) -> Result<IssueStatus, String> {
+        let s = s.trim().to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("\"{}\" is not a valid issue status.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Cancelled => "cancelled",
+            IssueStatus::Publishing => "publishing",
+            IssueStatus::Published => "published",
+            IssueStatus::Failed => "failed",
+        }
+    }
+}
+
+impl std::fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueStatus;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn every_status_round_trips_through_its_name() {
+        for status in IssueStatus::ALL {
+            assert_ok_eq!(IssueStatus::parse(status.as_str()), status);
+        }
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert_err!(IssueStatus::parse("sent"));
+    }
+}
@@ -0,0 +1,114 @@
+/// Language a subscriber reads our emails and pages in
+///
+/// Only locales with a translation catalog in `src/i18n` are listed,
+/// `En` is the fallback for everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+    Es,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 3] = [Locale::En, Locale::Fr, Locale::Es];
+
+    pub fn parse(s: &str) -> Result<Locale, String> {
+        let s = s.trim().to_lowercase();
+        // Regional variants share their language's catalog, e.g., `fr-CA`
+        let language = s.split(['-', '_']).next().unwrap_or_default();
+        Self::ALL
+            .into_iter()
+            .find(|l| l.as_str() == language)
+            .ok_or_else(|| format!("\"{}\" is not a supported locale.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+            Locale::Es => "es",
+        }
+    }
+
+    /// Name of the language in that language, for pickers
+    pub fn native_name(&self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::Fr => "Français",
+            Locale::Es => "Español",
+        }
+    }
+
+    /// Best supported locale for an `Accept-Language` header
+    ///
+    /// Entries are tried by decreasing quality, falling back to `En`.
+    pub fn negotiate(accept_language: &str) -> Locale {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equal qualities keep the order they were sent in
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| Locale::parse(tag).ok())
+            .unwrap_or_default()
+    }
+}
+
+impl AsRef<str> for Locale {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Locale;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn regional_variants_use_their_language() {
+        assert_ok_eq!(Locale::parse("fr-CA"), Locale::Fr);
+        assert_ok_eq!(Locale::parse("ES_mx"), Locale::Es);
+    }
+
+    #[test]
+    fn unsupported_locales_are_rejected() {
+        assert_err!(Locale::parse("de"));
+        assert_err!(Locale::parse(""));
+    }
+
+    #[test]
+    fn the_highest_quality_supported_language_wins() {
+        assert_eq!(
+            Locale::negotiate("de-DE, es;q=0.7, fr;q=0.9, en;q=0.8"),
+            Locale::Fr
+        );
+    }
+
+    #[test]
+    fn order_breaks_ties() {
+        assert_eq!(Locale::negotiate("es, fr"), Locale::Es);
+    }
+
+    #[test]
+    fn refused_languages_are_skipped() {
+        assert_eq!(Locale::negotiate("fr;q=0, es;q=0.1"), Locale::Es);
+    }
+
+    #[test]
+    fn unknown_or_garbage_headers_fall_back_to_english() {
+        assert_eq!(Locale::negotiate("de, ja"), Locale::En);
+        assert_eq!(Locale::negotiate(";;;q=x,"), Locale::En);
+        assert_eq!(Locale::negotiate(""), Locale::En);
+    }
+}
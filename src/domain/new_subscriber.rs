@@ -1,7 +1,11 @@
+use crate::domain::locale::Locale;
+use crate::domain::signup_source::SignupSource;
 use crate::domain::subscriber_email::SubscriberEmail;
 use crate::domain::subscriber_name::SubscriberName;
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub source: SignupSource,
+    pub locale: Locale,
 }
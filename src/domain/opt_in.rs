@@ -0,0 +1,50 @@
+use serde::Deserialize;
+
+/// How a new subscription is confirmed
+///
+/// * `Single`: the subscription is confirmed as soon as it is submitted
+/// * `Double`: the subscriber must click the link in a confirmation email
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OptIn {
+    Single,
+    Double,
+}
+
+impl OptIn {
+    pub fn parse(s: &str) -> Result<OptIn, String> {
+        match s.trim().to_lowercase().as_str() {
+            "single" => Ok(Self::Single),
+            "double" => Ok(Self::Double),
+            other => Err(format!(
+                "\"{}\" is not a valid opt-in mode. \
+                Use either `single` or `double`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptIn::Single => "single",
+            OptIn::Double => "double",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OptIn;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_modes_are_parsed_successfully() {
+        assert_ok_eq!(OptIn::parse("single"), OptIn::Single);
+        assert_ok_eq!(OptIn::parse("Double"), OptIn::Double);
+    }
+
+    #[test]
+    fn unknown_mode_is_rejected() {
+        assert_err!(OptIn::parse("triple"));
+    }
+}
@@ -0,0 +1,117 @@
+use url::Url;
+
+/// Longest value we keep for any attribution field
+const MAX_LENGTH: usize = 512;
+
+/// Where a subscription came from
+///
+/// Every field is optional, blank values are dropped and long ones
+/// truncated as they come straight from the browser.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignupSource {
+    /// Page the visitor came from before landing on ours
+    pub referrer: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+    /// Page holding the signup form
+    pub landing_page: Option<String>,
+    /// Identifies which of our forms was used, e.g., `footer`
+    pub form_id: Option<String>,
+}
+
+fn clean(value: Option<String>) -> Option<String> {
+    let value = value?;
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.chars().take(MAX_LENGTH).collect())
+}
+
+impl SignupSource {
+    pub fn new(
+        referrer: Option<String>,
+        utm: [Option<String>; 5],
+        landing_page: Option<String>,
+        form_id: Option<String>,
+    ) -> Self {
+        let [utm_source, utm_medium, utm_campaign, utm_term, utm_content] =
+            utm.map(clean);
+        let mut source = Self {
+            referrer: clean(referrer),
+            utm_source,
+            utm_medium,
+            utm_campaign,
+            utm_term,
+            utm_content,
+            landing_page: clean(landing_page),
+            form_id: clean(form_id),
+        };
+        source.fill_utm_from_landing_page();
+        source
+    }
+
+    /// Campaign links carry their UTM parameters on the landing page,
+    /// use those when the form did not pass them along
+    fn fill_utm_from_landing_page(&mut self) {
+        let Some(url) = self
+            .landing_page
+            .as_deref()
+            .and_then(|p| Url::parse(p).ok())
+        else {
+            return;
+        };
+        for (key, value) in url.query_pairs() {
+            let field = match key.as_ref() {
+                "utm_source" => &mut self.utm_source,
+                "utm_medium" => &mut self.utm_medium,
+                "utm_campaign" => &mut self.utm_campaign,
+                "utm_term" => &mut self.utm_term,
+                "utm_content" => &mut self.utm_content,
+                _ => continue,
+            };
+            if field.is_none() {
+                *field = clean(Some(value.into_owned()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SignupSource;
+
+    #[test]
+    fn blank_values_are_dropped_and_long_ones_truncated() {
+        let source = SignupSource::new(
+            Some("  ".into()),
+            [Some("x".repeat(600)), None, None, None, None],
+            None,
+            Some(" footer ".into()),
+        );
+        assert_eq!(source.referrer, None);
+        assert_eq!(source.utm_source.unwrap().len(), 512);
+        assert_eq!(source.form_id.as_deref(), Some("footer"));
+    }
+
+    #[test]
+    fn utm_parameters_are_read_from_the_landing_page() {
+        let source = SignupSource::new(
+            None,
+            [None, Some("social".into()), None, None, None],
+            Some(
+                "https://example.com/blog?utm_source=mastodon\
+                &utm_medium=email&utm_campaign=launch"
+                    .into(),
+            ),
+            None,
+        );
+        assert_eq!(source.utm_source.as_deref(), Some("mastodon"));
+        // Explicit values win
+        assert_eq!(source.utm_medium.as_deref(), Some("social"));
+        assert_eq!(source.utm_campaign.as_deref(), Some("launch"));
+    }
+}
@@ -0,0 +1,235 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Type of a custom subscriber attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    Text,
+    Number,
+    Boolean,
+    Date,
+}
+
+impl AttributeKind {
+    pub const ALL: [AttributeKind; 4] = [
+        AttributeKind::Text,
+        AttributeKind::Number,
+        AttributeKind::Boolean,
+        AttributeKind::Date,
+    ];
+
+    pub fn parse(s: &str) -> Result<AttributeKind, String> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "number" => Ok(Self::Number),
+            "boolean" => Ok(Self::Boolean),
+            "date" => Ok(Self::Date),
+            other => Err(format!(
+                "\"{}\" is not a valid attribute type. \
+                Use either `text`, `number`, `boolean` or `date`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeKind::Text => "text",
+            AttributeKind::Number => "number",
+            AttributeKind::Boolean => "boolean",
+            AttributeKind::Date => "date",
+        }
+    }
+
+    /// Validate a value and return the text form it is stored as
+    ///
+    /// Strings are accepted for every kind, as CSV and HTML forms have
+    /// no other types, e.g., `"42"` is a valid number.
+    pub fn normalize(&self, value: &Value) -> Result<String, String> {
+        let invalid = || format!("{} is not a valid {}.", value, self.as_str());
+        match (self, value) {
+            (AttributeKind::Text, Value::String(s)) => {
+                let s = s.trim();
+                if s.chars().count() > 256 {
+                    return Err("Text attributes are limited to 256 \
+                        characters."
+                        .into());
+                }
+                Ok(s.to_string())
+            }
+            (AttributeKind::Number, Value::Number(n)) => Ok(n.to_string()),
+            (AttributeKind::Number, Value::String(s)) => s
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(|n| n.to_string())
+                .ok_or_else(invalid),
+            (AttributeKind::Boolean, Value::Bool(b)) => Ok(b.to_string()),
+            (AttributeKind::Boolean, Value::String(s)) => {
+                match s.trim().to_lowercase().as_str() {
+                    "true" | "yes" | "1" => Ok("true".into()),
+                    "false" | "no" | "0" => Ok("false".into()),
+                    _ => Err(invalid()),
+                }
+            }
+            (AttributeKind::Date, Value::String(s)) => {
+                chrono::NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+                    .map(|d| d.to_string())
+                    .map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A custom attribute subscribers may have
+#[derive(Debug, Clone)]
+pub struct AttributeDefinition {
+    pub key: String,
+    pub label: String,
+    pub kind: AttributeKind,
+}
+
+impl AttributeDefinition {
+    /// Keys are used in templates, so keep them to lowercase ASCII
+    /// identifiers
+    pub fn parse_key(s: &str) -> Result<String, String> {
+        let key = s.trim().to_lowercase();
+        let valid = key.len() <= 64
+            && key.starts_with(|c: char| c.is_ascii_lowercase())
+            && key.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+            });
+        if valid {
+            Ok(key)
+        } else {
+            Err(format!(
+                "\"{}\" is not a valid attribute key. Use up to 64 lowercase \
+                letters, digits or underscores, starting with a letter.",
+                s
+            ))
+        }
+    }
+}
+
+/// Every attribute currently defined by the admins
+#[derive(Debug, Clone, Default)]
+pub struct AttributeSchema {
+    definitions: BTreeMap<String, AttributeDefinition>,
+}
+
+impl AttributeSchema {
+    pub fn new(definitions: Vec<AttributeDefinition>) -> Self {
+        Self {
+            definitions: definitions
+                .into_iter()
+                .map(|d| (d.key.clone(), d))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&AttributeDefinition> {
+        self.definitions.get(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.definitions.keys().map(String::as_str)
+    }
+
+    /// Normalized values keyed by attribute, attributes not in the
+    /// schema are rejected
+    pub fn validate(
+        &self,
+        values: &HashMap<String, Value>,
+    ) -> Result<BTreeMap<String, String>, String> {
+        let mut validated = BTreeMap::new();
+        for (key, value) in values {
+            let definition = self.get(key).ok_or_else(|| {
+                format!("\"{}\" is not a known attribute.", key)
+            })?;
+            // An explicit null leaves the attribute unset
+            if value.is_null() {
+                continue;
+            }
+            let value = definition
+                .kind
+                .normalize(value)
+                .map_err(|e| format!("{}: {}", key, e))?;
+            validated.insert(key.clone(), value);
+        }
+        Ok(validated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributeDefinition, AttributeKind, AttributeSchema};
+    use claims::{assert_err, assert_ok_eq};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new(vec![
+            AttributeDefinition {
+                key: "company".into(),
+                label: "Company".into(),
+                kind: AttributeKind::Text,
+            },
+            AttributeDefinition {
+                key: "seats".into(),
+                label: "Seats".into(),
+                kind: AttributeKind::Number,
+            },
+        ])
+    }
+
+    #[test]
+    fn values_are_normalized_by_kind() {
+        assert_ok_eq!(AttributeKind::Number.normalize(&json!(" 42 ")), "42");
+        assert_ok_eq!(AttributeKind::Number.normalize(&json!(2.5)), "2.5");
+        assert_ok_eq!(AttributeKind::Boolean.normalize(&json!("Yes")), "true");
+        assert_ok_eq!(AttributeKind::Boolean.normalize(&json!(false)), "false");
+        assert_ok_eq!(
+            AttributeKind::Date.normalize(&json!("2024-02-29")),
+            "2024-02-29"
+        );
+    }
+
+    #[test]
+    fn values_of_the_wrong_kind_are_rejected() {
+        assert_err!(AttributeKind::Number.normalize(&json!("many")));
+        assert_err!(AttributeKind::Number.normalize(&json!("NaN")));
+        assert_err!(AttributeKind::Boolean.normalize(&json!("maybe")));
+        assert_err!(AttributeKind::Date.normalize(&json!("2023-02-29")));
+        assert_err!(AttributeKind::Text.normalize(&json!(["a"])));
+    }
+
+    #[test]
+    fn attributes_not_in_the_schema_are_rejected() {
+        let values = HashMap::from([("plan".to_string(), json!("pro"))]);
+        assert_err!(schema().validate(&values));
+    }
+
+    #[test]
+    fn known_attributes_are_validated() {
+        let values = HashMap::from([
+            ("company".to_string(), json!(" ACME ")),
+            ("seats".to_string(), json!("10")),
+        ]);
+        let validated = schema().validate(&values).unwrap();
+        assert_eq!(validated["company"], "ACME");
+        assert_eq!(validated["seats"], "10");
+
+        let values = HashMap::from([("seats".to_string(), json!("ten"))]);
+        assert_err!(schema().validate(&values));
+    }
+
+    #[test]
+    fn keys_must_be_identifiers() {
+        assert_ok_eq!(AttributeDefinition::parse_key(" Plan_2 "), "plan_2");
+        assert_err!(AttributeDefinition::parse_key("2plan"));
+        assert_err!(AttributeDefinition::parse_key("first name"));
+        assert_err!(AttributeDefinition::parse_key(""));
+    }
+}
@@ -1,21 +1,61 @@
+use idna::AsciiDenyList;
 use validator::ValidateEmail;
 
+/// A validated subscriber email
+///
+/// Keeps the address as the subscriber typed it (minus surrounding
+/// whitespace) for display, and a canonical form used for uniqueness
+/// checks and lookups. The canonical form is all lowercase with the
+/// domain converted to ASCII, i.e., IDNA punycode, so `Foo@Example.COM`
+/// and `foo@example.com` are the same subscriber.
 #[derive(Debug, Clone)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    display: String,
+    canonical: String,
+}
 
 impl SubscriberEmail {
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if s.validate_email() {
-            Ok(Self(s))
+        let invalid = || format!("{} is not a valid subscriber email.", s);
+
+        let display = s.trim();
+        let (local, domain) = display.rsplit_once('@').ok_or_else(invalid)?;
+        if local.is_empty() || domain.is_empty() {
+            return Err(invalid());
+        }
+        // Also lowercases and applies the other UTS #46 mappings
+        let domain =
+            idna::domain_to_ascii_cow(domain.as_bytes(), AsciiDenyList::STD3)
+                .map_err(|_| invalid())?;
+        let canonical = format!("{}@{}", local.to_lowercase(), domain);
+
+        if canonical.validate_email() {
+            Ok(Self {
+                display: display.to_string(),
+                canonical,
+            })
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            Err(invalid())
         }
     }
+
+    /// Lowercase address with an ASCII domain, use for comparisons
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// The ASCII part after the `@`
+    pub fn domain(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map(|(_, d)| d)
+            .unwrap_or_default()
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.display
     }
 }
 
@@ -44,6 +84,23 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email =
+            SubscriberEmail::parse("  Foo@Example.COM \n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Foo@Example.COM");
+        assert_eq!(email.canonical(), "foo@example.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("reader@Bücher.example".to_string())
+            .unwrap();
+        assert_eq!(email.as_ref(), "reader@Bücher.example");
+        assert_eq!(email.canonical(), "reader@xn--bcher-kva.example");
+        assert_eq!(email.domain(), "xn--bcher-kva.example");
+    }
+
     proptest! {
         #[test]
         fn valid_emails_are_parsed_successfully(
@@ -52,5 +109,33 @@ mod tests {
             let valid_email_ref = valid_email.as_ref();
             assert_ok!(SubscriberEmail::parse(valid_email_ref.to_string()));
         }
+
+        #[test]
+        fn canonical_form_is_stable(
+            valid_email in any::<ValidEmailFixture>()
+        ) {
+            let email =
+                SubscriberEmail::parse(valid_email.as_ref().to_string())
+                    .unwrap();
+            let reparsed =
+                SubscriberEmail::parse(email.canonical().to_string()).unwrap();
+            prop_assert_eq!(email.canonical(), reparsed.canonical());
+        }
+
+        #[test]
+        fn case_and_whitespace_variants_share_a_canonical_form(
+            valid_email in any::<ValidEmailFixture>(),
+            padding in "[ \t]{0,3}",
+        ) {
+            let original =
+                SubscriberEmail::parse(valid_email.as_ref().to_string())
+                    .unwrap();
+            let variant = SubscriberEmail::parse(format!(
+                "{padding}{}{padding}",
+                valid_email.as_ref().to_uppercase()
+            ))
+            .unwrap();
+            prop_assert_eq!(original.canonical(), variant.canonical());
+        }
     }
 }
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a subscriber is in their lifecycle
+///
+/// * `PendingConfirmation`: signed up, waiting on the confirmation link
+/// * `Confirmed`: receives newsletters
+/// * `Unsubscribed`: opted out, can opt back in
+/// * `Bounced`: their mailbox rejected our emails
+/// * `Complained`: marked our email as spam, only erasure follows
+/// * `Erased`: deleted on request, only kept in the status history
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+    Erased,
+}
+
+impl SubscriberStatus {
+    pub const ALL: [SubscriberStatus; 6] = [
+        SubscriberStatus::PendingConfirmation,
+        SubscriberStatus::Confirmed,
+        SubscriberStatus::Unsubscribed,
+        SubscriberStatus::Bounced,
+        SubscriberStatus::Complained,
+        SubscriberStatus::Erased,
+    ];
+
+    pub fn parse(s: &str) -> Result<SubscriberStatus, String> {
+        let s = s.trim().to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("\"{}\" is not a valid status.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+            SubscriberStatus::Bounced => "bounced",
+            SubscriberStatus::Complained => "complained",
+            SubscriberStatus::Erased => "erased",
+        }
+    }
+
+    /// Whether the lifecycle allows moving from `self` to `next`
+    ///
+    /// Staying in the same state is not a transition.
+    pub fn can_transition_to(&self, next: SubscriberStatus) -> bool {
+        use SubscriberStatus::*;
+        match (self, next) {
+            (Erased, _) => false,
+            (_, Erased) => true,
+            (PendingConfirmation, Confirmed | Unsubscribed | Bounced) => true,
+            (Confirmed, Unsubscribed | Bounced | Complained) => true,
+            (Unsubscribed, Confirmed | Bounced | Complained) => true,
+            (Bounced, Confirmed | Unsubscribed | Complained) => true,
+            _ => false,
+        }
+    }
+
+    /// Validate a transition, returning the new status
+    pub fn transition_to(
+        &self,
+        next: SubscriberStatus,
+    ) -> Result<SubscriberStatus, String> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(format!(
+                "A subscriber cannot go from {} to {}.",
+                self.as_str(),
+                next.as_str()
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriberStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberStatus::{self, *};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn every_status_round_trips_through_its_name() {
+        for status in SubscriberStatus::ALL {
+            assert_ok_eq!(SubscriberStatus::parse(status.as_str()), status);
+        }
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert_err!(SubscriberStatus::parse("deleted"));
+    }
+
+    #[test]
+    fn pending_subscribers_can_confirm() {
+        assert_ok_eq!(PendingConfirmation.transition_to(Confirmed), Confirmed);
+    }
+
+    #[test]
+    fn unsubscribed_subscribers_can_opt_back_in() {
+        assert!(Unsubscribed.can_transition_to(Confirmed));
+    }
+
+    #[test]
+    fn complaints_are_final_until_erasure() {
+        for status in SubscriberStatus::ALL {
+            assert_eq!(Complained.can_transition_to(status), status == Erased);
+        }
+    }
+
+    #[test]
+    fn erased_is_terminal() {
+        for status in SubscriberStatus::ALL {
+            assert_err!(Erased.transition_to(status));
+        }
+    }
+
+    #[test]
+    fn staying_in_the_same_state_is_not_a_transition() {
+        for status in SubscriberStatus::ALL {
+            assert!(!status.can_transition_to(status));
+        }
+    }
+
+    #[test]
+    fn anything_but_erased_can_be_erased() {
+        for status in SubscriberStatus::ALL {
+            assert_eq!(status.can_transition_to(Erased), status != Erased);
+        }
+    }
+}
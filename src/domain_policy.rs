@@ -0,0 +1,203 @@
+//! src/domain_policy.rs
+//!
+//! Decides which email domains may sign up. Operator rules stored in
+//! the database take precedence over the list of disposable domains.
+
+use crate::settings::DomainPolicySettings;
+use anyhow::Context;
+use idna::AsciiDenyList;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str =
+    include_str!("domain_policy/disposable_domains.txt");
+
+/// Why a domain is not allowed to sign up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainRejection {
+    Blocklisted,
+    Disposable,
+}
+
+impl DomainRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainRejection::Blocklisted => "blocklist",
+            DomainRejection::Disposable => "disposable",
+        }
+    }
+}
+
+/// Operator maintained rule for a domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainRule {
+    Allow,
+    Block,
+}
+
+impl DomainRule {
+    pub fn parse(s: &str) -> Result<DomainRule, String> {
+        match s.trim().to_lowercase().as_str() {
+            "allow" => Ok(Self::Allow),
+            "block" => Ok(Self::Block),
+            other => Err(format!(
+                "\"{}\" is not a valid domain rule. \
+                Use either `allow` or `block`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainRule::Allow => "allow",
+            DomainRule::Block => "block",
+        }
+    }
+}
+
+/// Lowercase ASCII (punycode) form of a domain, as used by
+/// `SubscriberEmail::domain`
+pub fn normalize_domain(domain: &str) -> Result<String, String> {
+    let domain = domain.trim().trim_start_matches('@');
+    if domain.is_empty() {
+        return Err("A domain must be provided.".into());
+    }
+    idna::domain_to_ascii_cow(domain.as_bytes(), AsciiDenyList::STD3)
+        .map(|d| d.into_owned())
+        .map_err(|_| format!("{} is not a valid domain.", domain))
+}
+
+fn parse_domain_list(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| normalize_domain(l).ok())
+        .collect()
+}
+
+/// `mail.example.com` yields `mail.example.com` and `example.com`, so a
+/// rule for a domain also covers its subdomains
+fn domain_and_parents(domain: &str) -> Vec<&str> {
+    let mut candidates = vec![domain];
+    let mut rest = domain;
+    while let Some((_, parent)) = rest.split_once('.') {
+        if parent.contains('.') {
+            candidates.push(parent);
+        }
+        rest = parent;
+    }
+    candidates
+}
+
+pub struct DomainPolicy {
+    settings: DomainPolicySettings,
+    disposable: RwLock<HashSet<String>>,
+}
+
+impl DomainPolicy {
+    pub fn new(settings: DomainPolicySettings) -> Result<Self, std::io::Error> {
+        let policy = Self {
+            settings,
+            disposable: RwLock::new(HashSet::new()),
+        };
+        policy.reload()?;
+        Ok(policy)
+    }
+
+    /// Re-read the disposable domains, from the configured file if any,
+    /// otherwise from the list bundled with the binary
+    pub fn reload(&self) -> Result<usize, std::io::Error> {
+        let domains = match &self.settings.disposable_domains_path {
+            Some(path) => parse_domain_list(&std::fs::read_to_string(path)?),
+            None => parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS),
+        };
+        let count = domains.len();
+        *self.disposable.write().unwrap() = domains;
+        Ok(count)
+    }
+
+    pub fn disposable_count(&self) -> usize {
+        self.disposable.read().unwrap().len()
+    }
+
+    fn is_disposable(&self, domain: &str) -> bool {
+        let disposable = self.disposable.read().unwrap();
+        domain_and_parents(domain)
+            .iter()
+            .any(|d| disposable.contains(*d))
+    }
+
+    /// `domain` must already be normalized, e.g., `SubscriberEmail::domain`
+    #[tracing::instrument(name = "Check email domain policy", skip(self, pool))]
+    pub async fn check(
+        &self,
+        pool: &SqlitePool,
+        domain: &str,
+    ) -> Result<Result<(), DomainRejection>, anyhow::Error> {
+        let candidates = domain_and_parents(domain);
+        let mut rules = Vec::new();
+        for candidate in &candidates {
+            let rule = sqlx::query!(
+                r#"SELECT rule FROM email_domain_rules WHERE domain = $1"#,
+                candidate,
+            )
+            .fetch_optional(pool)
+            .await
+            .context("Failed to query email domain rules.")?;
+            if let Some(rule) = rule {
+                rules.push(
+                    DomainRule::parse(&rule.rule)
+                        .map_err(|e| anyhow::anyhow!(e))?,
+                );
+            }
+        }
+
+        // An explicit allow always wins, e.g., to let a disposable
+        // domain through
+        if rules.contains(&DomainRule::Allow) {
+            return Ok(Ok(()));
+        }
+        if rules.contains(&DomainRule::Block) {
+            return Ok(Err(DomainRejection::Blocklisted));
+        }
+        if self.settings.block_disposable && self.is_disposable(domain) {
+            return Ok(Err(DomainRejection::Disposable));
+        }
+        Ok(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{domain_and_parents, normalize_domain, parse_domain_list};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn bundled_list_is_parsed_without_comments() {
+        let domains = parse_domain_list(super::BUNDLED_DISPOSABLE_DOMAINS);
+        assert!(domains.contains("mailinator.com"));
+        assert!(domains.iter().all(|d| !d.starts_with('#')));
+    }
+
+    #[test]
+    fn domains_are_normalized_to_lowercase_ascii() {
+        assert_ok_eq!(normalize_domain(" @Example.COM "), "example.com");
+        assert_ok_eq!(
+            normalize_domain("Bücher.example"),
+            "xn--bcher-kva.example"
+        );
+        assert_err!(normalize_domain(""));
+    }
+
+    #[test]
+    fn parent_domains_are_candidates_but_not_bare_tlds() {
+        assert_eq!(
+            domain_and_parents("a.mail.example.com"),
+            vec!["a.mail.example.com", "mail.example.com", "example.com"]
+        );
+        assert_eq!(domain_and_parents("example.com"), vec!["example.com"]);
+    }
+}
@@ -0,0 +1,171 @@
+//! src/drip.rs
+//!
+//! Welcome drip sequence. Subscribers are enrolled when they become
+//! confirmed and receive one step at a time, in order of delay, once
+//! the step's delay since enrollment has passed. Unsubscribing stops the
+//! sequence for good.
+
+use crate::domain::{Locale, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::i18n::translate_with;
+use crate::routes::get_current_utc_timestamp;
+use anyhow::Context;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Start the welcome sequence for a newly confirmed subscriber
+///
+/// Enrolling twice keeps the original enrollment. Subscribers who were
+/// just sent the welcome email skip the day 0 step, it would greet them
+/// a second time.
+#[tracing::instrument(name = "Enroll subscriber in drip sequence", skip_all)]
+pub async fn enroll_subscriber(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    welcomed: bool,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let enrolled_at = get_current_utc_timestamp();
+    let last_step_delay = welcomed.then_some(0i64);
+    sqlx::query!(
+        r#"
+        INSERT OR IGNORE INTO drip_enrollments
+            (subscriber_id, enrolled_at, last_step_delay)
+        VALUES ($1, $2, $3)
+        "#,
+        subscriber_id,
+        enrolled_at,
+        last_step_delay,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Send the next step to every subscriber who has one due
+///
+/// Returns how many emails were sent. Failed deliveries are retried on
+/// the next run.
+#[tracing::instrument(name = "Send due drip emails", skip_all)]
+pub async fn send_due_drip_emails(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<usize, anyhow::Error> {
+    let now = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        UPDATE drip_enrollments SET stopped_at = $1
+        WHERE stopped_at IS NULL AND subscriber_id IN
+            (SELECT id FROM subscriptions WHERE status <> 'confirmed')
+        "#,
+        now,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to stop drip sequences of unsubscribed subscribers.")?;
+
+    let due = sqlx::query!(
+        r#"
+        SELECT e.subscriber_id, s.email, s.locale, s.preferences_token,
+            st.delay_days AS "delay_days!: i64", st.subject, st.html_content,
+            st.text_content
+        FROM drip_enrollments e
+        JOIN subscriptions s ON s.id = e.subscriber_id
+        JOIN drip_steps st ON st.delay_days = (
+            SELECT min(delay_days) FROM drip_steps
+            WHERE delay_days > coalesce(e.last_step_delay, -1)
+        )
+        WHERE e.stopped_at IS NULL
+            AND datetime(e.enrolled_at, '+' || st.delay_days || ' days')
+                <= datetime($1)
+        "#,
+        now,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query due drip steps.")?;
+
+    let mut sent = 0;
+    for step in due {
+        let (recipient, locale) = match SubscriberEmail::parse(step.email)
+            .and_then(|e| Ok((e, Locale::parse(&step.locale)?)))
+        {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                tracing::warn!(
+                    error,
+                    "Skipping a drip step, the stored details are invalid."
+                );
+                continue;
+            }
+        };
+        let preferences_link = format!(
+            "{}preferences?token={}",
+            base_url,
+            step.preferences_token.unwrap_or_default()
+        );
+        let args = [("link", preferences_link.as_str())];
+        let html_content = format!(
+            "{}{}",
+            step.html_content,
+            translate_with(locale, "drip_email.footer_html", &args)
+        );
+        let text_content = format!(
+            "{}\n\n{}",
+            step.text_content,
+            translate_with(locale, "drip_email.footer_text", &args)
+        );
+        if let Err(error) = email_client
+            .send_email(&recipient, &step.subject, &html_content, &text_content)
+            .await
+        {
+            tracing::error!(
+                error = ?error,
+                "Failed to send a drip email, retrying on the next run."
+            );
+            continue;
+        }
+
+        let sent_at = get_current_utc_timestamp();
+        sqlx::query!(
+            r#"
+            UPDATE drip_enrollments
+            SET last_step_delay = $2, last_sent_at = $3
+            WHERE subscriber_id = $1
+            "#,
+            step.subscriber_id,
+            step.delay_days,
+            sent_at,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record a sent drip step.")?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Periodically send due drip emails, never returns
+pub async fn run_drip_scheduler(
+    pool: SqlitePool,
+    email_client: EmailClient,
+    base_url: String,
+    poll_interval: Duration,
+) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + poll_interval,
+        poll_interval,
+    );
+    loop {
+        interval.tick().await;
+        match send_due_drip_emails(&pool, &email_client, &base_url).await {
+            Ok(0) => {}
+            Ok(sent) => tracing::info!(sent, "Sent drip emails."),
+            Err(error) => {
+                tracing::error!(error = ?error, "Failed to run the drip scheduler.")
+            }
+        }
+    }
+}
@@ -1,6 +1,102 @@
 use crate::domain::SubscriberEmail;
-use reqwest::Client;
+use crate::routes::error_chain_fmt;
+use rand::Rng;
+use reqwest::{header, Client, StatusCode};
 use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+/// Why an email could not be sent
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    /// Worth trying again later: timeouts, server errors and rate limits
+    #[error("Transient failure sending an email")]
+    Transient {
+        #[source]
+        source: reqwest::Error,
+        /// How long the email provider asked us to wait
+        retry_after: Option<Duration>,
+    },
+    /// Would fail the same way again, e.g. an invalid recipient
+    #[error("Permanent failure sending an email")]
+    Permanent(#[source] reqwest::Error),
+}
+
+impl EmailError {
+    fn classify(
+        source: reqwest::Error,
+        status: Option<StatusCode>,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        match status {
+            Some(status)
+                if status.is_client_error()
+                    && status != StatusCode::REQUEST_TIMEOUT
+                    && status != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                EmailError::Permanent(source)
+            }
+            _ => EmailError::Transient {
+                source,
+                retry_after,
+            },
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, EmailError::Transient { .. })
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            EmailError::Transient { retry_after, .. } => *retry_after,
+            EmailError::Permanent(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// When to try sending an email again after a transient failure
+///
+/// The delay doubles with every attempt, capped at `max_delay`, and is
+/// drawn at random below that bound so retries spread out. A
+/// `Retry-After` from the email provider takes precedence, capped at
+/// `max_delay` as well.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following `attempts` failed ones, `None`
+    /// once they are used up
+    pub fn next_delay(
+        &self,
+        attempts: u32,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        if let Some(retry_after) = retry_after {
+            return Some(retry_after.min(self.max_delay));
+        }
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_delay);
+        let millis = exponential.as_millis() as u64;
+        Some(Duration::from_millis(
+            rand::thread_rng().gen_range(0..=millis),
+        ))
+    }
+}
 
 pub struct EmailClient {
     http_client: Client,
@@ -31,7 +127,7 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<(), EmailError> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
@@ -40,7 +136,8 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
-        self.http_client
+        let response = self
+            .http_client
             .post(&url)
             .header(
                 "X-Postmark-Server-Token",
@@ -48,8 +145,23 @@ impl EmailClient {
             )
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            // No response at all, e.g. a timeout
+            .map_err(|e| EmailError::classify(e, None, None))?;
+        if let Err(error) = response.error_for_status_ref() {
+            // Only the delay in seconds form is supported
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(EmailError::classify(
+                error,
+                Some(response.status()),
+                retry_after,
+            ));
+        }
         Ok(())
     }
 }
@@ -67,7 +179,7 @@ struct SendEmailRequest<'a> {
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, RetryPolicy};
     use crate::test_utils::test_utils::ValidEmailFixture;
     use claims::{assert_err, assert_ok};
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -212,4 +324,121 @@ mod tests {
 
         assert_err!(outcome);
     }
+
+    #[tokio::test]
+    async fn server_errors_are_transient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_transient_and_carry_the_delay() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "120"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let error = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap_err();
+
+        assert!(error.is_transient());
+        assert_eq!(
+            error.retry_after(),
+            Some(std::time::Duration::from_secs(120))
+        );
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_permanent() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(!outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_transient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_secs(180)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(outcome.unwrap_err().is_transient());
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: std::time::Duration::from_secs(10),
+            max_delay: std::time::Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn backoff_grows_up_to_its_cap() {
+        for attempts in 1..5 {
+            let bound = std::time::Duration::from_secs(
+                (10 * 2u64.pow(attempts - 1)).min(60),
+            );
+            for _ in 0..100 {
+                assert!(policy().next_delay(attempts, None).unwrap() <= bound);
+            }
+        }
+    }
+
+    #[test]
+    fn retry_after_takes_precedence() {
+        let retry_after = std::time::Duration::from_secs(30);
+        assert_eq!(
+            policy().next_delay(1, Some(retry_after)),
+            Some(retry_after)
+        );
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let retry_after = std::time::Duration::from_secs(u64::MAX);
+        assert_eq!(
+            policy().next_delay(1, Some(retry_after)),
+            Some(std::time::Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn attempts_run_out() {
+        assert_eq!(policy().next_delay(5, None), None);
+    }
 }
@@ -0,0 +1,257 @@
+//! src/engagement.rs
+//!
+//! Opens and clicks on newsletter issues. Every issue delivered to a
+//! subscriber bumps `issues_since_engagement`, any open or click resets
+//! it. Subscribers are identified by their `preferences_token`, and click
+//! links carry a signature of their destination so the redirect cannot be
+//! pointed anywhere else.
+
+use crate::domain::Locale;
+use crate::routes::get_current_utc_timestamp;
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use htmlescape::{decode_html, encode_minimal};
+use secrecy::ExposeSecret;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngagementKind {
+    Open,
+    Click,
+    /// Answered a re-engagement email
+    Keep,
+}
+
+impl EngagementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EngagementKind::Open => "open",
+            EngagementKind::Click => "click",
+            EngagementKind::Keep => "keep",
+        }
+    }
+}
+
+/// Subscriber behind a tracking token
+pub struct Engaged {
+    pub subscriber_id: Uuid,
+    pub locale: Locale,
+}
+
+fn click_tag(url: &str, secret: &HmacSecret) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(
+        secret.0.expose_secret().as_bytes(),
+    )
+    .unwrap();
+    mac.update(format!("click:url={url}").as_bytes());
+    mac
+}
+
+/// Whether `tag` is our signature of `url`
+pub fn verify_click(url: &str, tag: &str, secret: &HmacSecret) -> bool {
+    hex::decode(tag)
+        .map(|tag| click_tag(url, secret).verify_slice(&tag).is_ok())
+        .unwrap_or(false)
+}
+
+fn click_link(
+    base_url: &str,
+    token: &str,
+    url: &str,
+    secret: &HmacSecret,
+) -> String {
+    let tag = click_tag(url, secret).finalize().into_bytes();
+    format!(
+        "{}engagement/click?token={}&url={}&tag={tag:x}",
+        base_url,
+        urlencoding::encode(token),
+        urlencoding::encode(url),
+    )
+}
+
+/// Route the web links of an issue through the click redirect and add an
+/// open tracking pixel
+pub fn track_issue_html(
+    html: &str,
+    base_url: &str,
+    token: &str,
+    secret: &HmacSecret,
+) -> String {
+    const HREF: &str = "href=\"";
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(HREF) {
+        let value_start = start + HREF.len();
+        let Some(length) = rest[value_start..].find('"') else {
+            break;
+        };
+        output.push_str(&rest[..value_start]);
+        let value = &rest[value_start..value_start + length];
+        let url = decode_html(value).unwrap_or_else(|_| value.to_string());
+        if url.starts_with("http://") || url.starts_with("https://") {
+            output.push_str(&encode_minimal(&click_link(
+                base_url, token, &url, secret,
+            )));
+        } else {
+            // `mailto:`, anchors and the like are left alone
+            output.push_str(value);
+        }
+        rest = &rest[value_start + length..];
+    }
+    output.push_str(rest);
+    output.push_str(&format!(
+        r#"<img src="{}" width="1" height="1" alt="">"#,
+        encode_minimal(&format!(
+            "{}engagement/open?token={}",
+            base_url,
+            urlencoding::encode(token)
+        ))
+    ));
+    output
+}
+
+/// Count an issue delivered to a subscriber
+#[tracing::instrument(name = "Record issue sent", skip(pool))]
+pub async fn record_issue_sent(
+    pool: &SqlitePool,
+    subscriber_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET issues_since_engagement = issues_since_engagement + 1
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Reset the inactivity of the subscriber behind `token`, `None` if the
+/// token is unknown
+#[tracing::instrument(name = "Record engagement", skip(pool, token))]
+pub async fn record_engagement(
+    pool: &SqlitePool,
+    token: &str,
+    kind: EngagementKind,
+    url: Option<&str>,
+) -> Result<Option<Engaged>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    let Some(subscriber) = sqlx::query!(
+        r#"SELECT id AS "id!", locale FROM subscriptions
+        WHERE preferences_token = $1"#,
+        token,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to query subscriber by tracking token.")?
+    else {
+        return Ok(None);
+    };
+
+    let now = get_current_utc_timestamp();
+    let kind_str = kind.as_str();
+    sqlx::query!(
+        r#"
+        INSERT INTO engagement_events (subscriber_id, kind, url, occurred_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        subscriber.id,
+        kind_str,
+        url,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the engagement event.")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET issues_since_engagement = 0, last_engaged_at = $1,
+            reengagement_sent_at = NULL
+        WHERE id = $2
+        "#,
+        now,
+        subscriber.id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to reset subscriber inactivity.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record engagement.")?;
+
+    Ok(Some(Engaged {
+        subscriber_id: Uuid::parse_str(&subscriber.id)
+            .context("Failed to parse stored subscriber id.")?,
+        locale: Locale::parse(&subscriber.locale)
+            .map_err(|e| anyhow::anyhow!(e))?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{track_issue_html, verify_click};
+    use crate::startup::HmacSecret;
+    use secrecy::Secret;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("a-very-secret-key".to_string()))
+    }
+
+    #[test]
+    fn web_links_go_through_the_click_redirect() {
+        let html = track_issue_html(
+            r#"<a href="https://example.com/?a=1&amp;b=2">read</a>"#,
+            "http://127.0.0.1/",
+            "token",
+            &secret(),
+        );
+        assert!(html.starts_with(
+            r#"<a href="http://127.0.0.1/engagement/click?token=token&amp;url=https%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2&amp;tag="#
+        ));
+        assert!(html.contains(
+            r#"<img src="http://127.0.0.1/engagement/open?token=token""#
+        ));
+    }
+
+    #[test]
+    fn other_links_are_left_alone() {
+        let html = track_issue_html(
+            r##"<a href="mailto:a@example.com">a</a><a href="#top">top</a>"##,
+            "http://127.0.0.1/",
+            "token",
+            &secret(),
+        );
+        assert!(html.starts_with(
+            r##"<a href="mailto:a@example.com">a</a><a href="#top">top</a><img"##
+        ));
+    }
+
+    #[test]
+    fn click_tags_are_bound_to_their_url() {
+        let html = track_issue_html(
+            r#"<a href="https://example.com">x</a>"#,
+            "http://127.0.0.1/",
+            "token",
+            &secret(),
+        );
+        let tag = html
+            .split("tag=")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        assert!(verify_click("https://example.com", tag, &secret()));
+        assert!(!verify_click("https://evil.example.com", tag, &secret()));
+    }
+}
@@ -0,0 +1,150 @@
+//! src/erasure.rs
+//!
+//! Right to erasure. Removing a subscriber deletes every row that refers
+//! to them; only a keyed hash of their email is kept on a suppression
+//! list so the address is not signed up again by accident.
+
+use crate::domain::{SubscriberEmail, SubscriberStatus};
+use crate::lifecycle::change_status;
+use crate::routes::get_current_utc_timestamp;
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+/// Hex encoded HMAC-SHA256 of the canonical email
+///
+/// The HMAC secret acts as the salt, rotating it makes existing
+/// suppressions unmatchable.
+pub fn suppression_hash(
+    email: &SubscriberEmail,
+    secret: &HmacSecret,
+) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(
+        secret.0.expose_secret().as_bytes(),
+    )
+    .unwrap();
+    mac.update(b"suppression:");
+    mac.update(email.canonical().as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+#[tracing::instrument(name = "Check email suppression", skip_all)]
+pub async fn is_suppressed(
+    transaction: &mut Transaction<'_, Sqlite>,
+    email: &SubscriberEmail,
+    secret: &HmacSecret,
+) -> Result<bool, sqlx::Error> {
+    let email_hash = suppression_hash(email, secret);
+    let row = sqlx::query!(
+        r#"SELECT email_hash FROM suppressed_emails WHERE email_hash = $1"#,
+        email_hash,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.is_some())
+}
+
+/// Delete a subscriber and everything referring to them in a single
+/// transaction, returns `false` if there was no such subscriber
+///
+/// Tables referencing `subscriptions` must use `ON DELETE CASCADE`.
+/// `subscriber_status_history` keeps a single `erased` entry.
+#[tracing::instrument(name = "Erase subscriber", skip(pool, secret))]
+pub async fn erase_subscriber(
+    pool: &SqlitePool,
+    subscriber_id: Uuid,
+    secret: &HmacSecret,
+) -> Result<bool, anyhow::Error> {
+    let id = subscriber_id.to_string();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+
+    let Some(subscriber) =
+        sqlx::query!(r#"SELECT email FROM subscriptions WHERE id = $1"#, id)
+            .fetch_optional(&mut *transaction)
+            .await
+            .context("Failed to query the subscriber to erase.")?
+    else {
+        return Ok(false);
+    };
+    let email = SubscriberEmail::parse(subscriber.email)
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    let email_hash = suppression_hash(&email, secret);
+    let suppressed_at = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        INSERT OR IGNORE INTO suppressed_emails (email_hash, suppressed_at)
+        VALUES ($1, $2)
+        "#,
+        email_hash,
+        suppressed_at,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to add the email to the suppression list.")?;
+
+    // Only the erasure itself is kept in the status history
+    change_status(&mut transaction, subscriber_id, SubscriberStatus::Erased)
+        .await
+        .context("Failed to mark the subscriber as erased.")?;
+    sqlx::query!(
+        r#"
+        DELETE FROM subscriber_status_history
+        WHERE subscriber_id = $1 AND to_status <> 'erased'
+        "#,
+        id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the status history.")?;
+
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the subscriber.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    tracing::info!("Subscriber erased.");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suppression_hash;
+    use crate::domain::SubscriberEmail;
+    use crate::startup::HmacSecret;
+    use secrecy::Secret;
+
+    fn secret(s: &str) -> HmacSecret {
+        HmacSecret(Secret::new(s.to_string()))
+    }
+
+    #[test]
+    fn hash_is_shared_by_equivalent_addresses() {
+        let a = SubscriberEmail::parse("Ursula@Example.COM".into()).unwrap();
+        let b = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        assert_eq!(
+            suppression_hash(&a, &secret("s")),
+            suppression_hash(&b, &secret("s"))
+        );
+    }
+
+    #[test]
+    fn hash_depends_on_the_secret_and_hides_the_address() {
+        let email =
+            SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        let hash = suppression_hash(&email, &secret("one"));
+        assert_ne!(hash, suppression_hash(&email, &secret("two")));
+        assert!(!hash.contains("ursula"));
+        assert_eq!(hash.len(), 64);
+    }
+}
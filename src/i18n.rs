@@ -0,0 +1,151 @@
+//! src/i18n.rs
+//!
+//! Translation catalogs for subscriber facing emails and pages. Catalogs
+//! are TOML files in `src/i18n`, embedded in the binary and keyed by
+//! `section.key`. Keys missing from a catalog fall back to English.
+
+use crate::domain::Locale;
+use axum::http::{header, HeaderMap};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+type Catalog = HashMap<String, String>;
+
+fn catalog_source(locale: Locale) -> &'static str {
+    match locale {
+        Locale::En => include_str!("i18n/en.toml"),
+        Locale::Fr => include_str!("i18n/fr.toml"),
+        Locale::Es => include_str!("i18n/es.toml"),
+    }
+}
+
+fn catalogs() -> &'static HashMap<&'static str, Catalog> {
+    static CATALOGS: OnceLock<HashMap<&'static str, Catalog>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        Locale::ALL
+            .into_iter()
+            .map(|locale| {
+                let table: toml::Table =
+                    catalog_source(locale).parse().unwrap_or_else(|e| {
+                        panic!("Invalid {} catalog: {}", locale.as_str(), e)
+                    });
+                let mut catalog = Catalog::new();
+                flatten("", &table, &mut catalog);
+                (locale.as_str(), catalog)
+            })
+            .collect()
+    })
+}
+
+fn flatten(prefix: &str, table: &toml::Table, catalog: &mut Catalog) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            toml::Value::Table(table) => flatten(&key, table, catalog),
+            toml::Value::String(s) => {
+                catalog.insert(key, s.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Text for `key` in `locale`, falling back to English, then to the key
+/// itself so a missing translation is visible rather than fatal
+pub fn translate(locale: Locale, key: &str) -> &'static str {
+    let catalogs = catalogs();
+    [locale, Locale::En]
+        .into_iter()
+        .find_map(|l| catalogs.get(l.as_str())?.get(key))
+        .map(String::as_str)
+        .unwrap_or_else(|| missing_key(key))
+}
+
+/// `key` itself as a `'static` string, leaked only the first time it is
+/// missing
+fn missing_key(key: &str) -> &'static str {
+    static MISSING: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut missing = MISSING
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(key) = missing.get(key) {
+        return key;
+    }
+    tracing::warn!(key, "Missing translation.");
+    let key: &'static str = Box::leak(key.to_string().into_boxed_str());
+    missing.insert(key);
+    key
+}
+
+/// Same as `translate`, replacing each `{name}` with its value
+pub fn translate_with(
+    locale: Locale,
+    key: &str,
+    args: &[(&str, &str)],
+) -> String {
+    args.iter()
+        .fold(translate(locale, key).to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// Locale negotiated from a request's `Accept-Language`, for visitors we
+/// know nothing else about
+pub fn request_locale(headers: &HeaderMap) -> Locale {
+    headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .map(Locale::negotiate)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{catalogs, translate, translate_with};
+    use crate::domain::Locale;
+
+    #[test]
+    fn every_catalog_translates_every_english_key() {
+        let catalogs = catalogs();
+        let english = &catalogs["en"];
+        for locale in Locale::ALL {
+            let catalog = &catalogs[locale.as_str()];
+            for key in english.keys() {
+                assert!(
+                    catalog.contains_key(key),
+                    "{} is missing {}",
+                    locale.as_str(),
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let text = translate_with(
+            Locale::Fr,
+            "preferences.managing",
+            &[("email", "ursula@example.com")],
+        );
+        assert!(text.contains("ursula@example.com"));
+        assert!(!text.contains("{email}"));
+    }
+
+    #[test]
+    fn unknown_keys_are_returned_as_is() {
+        assert_eq!(translate(Locale::Es, "nope.missing"), "nope.missing");
+    }
+
+    #[test]
+    fn unknown_keys_are_leaked_once() {
+        let first = translate(Locale::Fr, "nope.again");
+        let second = translate(Locale::En, "nope.again");
+        assert!(std::ptr::eq(first, second));
+    }
+}
@@ -0,0 +1,243 @@
+//! src/idempotency.rs
+//!
+//! Idempotency keys for `POST /newsletters`. The first request with a key
+//! reserves it, its response is saved once it is done, and retries with
+//! the same key get that response back instead of publishing the issue
+//! again. Keys are scoped to the user sending them and expire after
+//! `IdempotencySettings::key_ttl_hours`.
+
+use crate::routes::get_current_utc_timestamp;
+use crate::settings::IdempotencySettings;
+use anyhow::Context;
+use axum::body::{to_bytes, Body};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::Response;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+/// Value of the `Idempotency-Key` header
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    const MAX_LENGTH: usize = 50;
+
+    /// The key sent with a request, if any
+    pub fn from_headers(
+        headers: &HeaderMap,
+    ) -> Result<Option<IdempotencyKey>, String> {
+        let Some(value) = headers.get("Idempotency-Key") else {
+            return Ok(None);
+        };
+        let key = value
+            .to_str()
+            .map_err(|_| "The idempotency key must be visible ASCII.")?;
+        IdempotencyKey::parse(key.to_string()).map(Some)
+    }
+
+    pub fn parse(s: String) -> Result<IdempotencyKey, String> {
+        if s.trim().is_empty() {
+            return Err("The idempotency key cannot be empty.".into());
+        }
+        if s.len() > Self::MAX_LENGTH {
+            return Err(format!(
+                "The idempotency key must be at most {} characters long.",
+                Self::MAX_LENGTH
+            ));
+        }
+        Ok(IdempotencyKey(s))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+pub enum NextAction {
+    /// The key is now reserved for this request
+    StartProcessing,
+    /// A request with this key already completed
+    ReturnSavedResponse(Response),
+    /// A request with this key is still being processed
+    InFlight,
+}
+
+/// Reserve `key` for a new request, or find out what happened to the
+/// previous one
+#[tracing::instrument(
+    name = "Try processing an idempotent request",
+    skip(pool, settings)
+)]
+pub async fn try_processing(
+    pool: &SqlitePool,
+    key: &IdempotencyKey,
+    user_id: Uuid,
+    settings: &IdempotencySettings,
+) -> Result<NextAction, anyhow::Error> {
+    let user_id = user_id.to_string();
+    let now = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE datetime(created_at, '+' || $1 || ' hours') <= datetime($2)
+        "#,
+        settings.key_ttl_hours,
+        now,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired idempotency keys.")?;
+
+    let key = key.as_ref();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        key,
+        now,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reserve the idempotency key.")?
+    .rows_affected();
+    if inserted > 0 {
+        return Ok(NextAction::StartProcessing);
+    }
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT response_status_code, response_headers, response_body
+        FROM idempotency WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        key,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query the saved response.")?;
+    let Some(saved) = saved else {
+        // Expired and deleted by a concurrent request in between
+        return Ok(NextAction::InFlight);
+    };
+    let (Some(status), Some(headers), Some(body)) = (
+        saved.response_status_code,
+        saved.response_headers,
+        saved.response_body,
+    ) else {
+        return Ok(NextAction::InFlight);
+    };
+
+    let status = StatusCode::from_u16(status as u16)
+        .context("Invalid saved response status code.")?;
+    let headers: Vec<(String, Vec<u8>)> = serde_json::from_str(&headers)
+        .context("Invalid saved response headers.")?;
+    let mut response = Response::builder().status(status);
+    for (name, value) in headers {
+        response = response.header(
+            HeaderName::try_from(name)
+                .context("Invalid saved response header name.")?,
+            HeaderValue::from_bytes(&value)
+                .context("Invalid saved response header value.")?,
+        );
+    }
+    let response = response
+        .body(Body::from(body))
+        .context("Failed to rebuild the saved response.")?;
+    Ok(NextAction::ReturnSavedResponse(response))
+}
+
+/// Store the response to a request holding `key`, returning it so it can
+/// be sent
+#[tracing::instrument(
+    name = "Save an idempotent response",
+    skip(pool, response)
+)]
+pub async fn save_response(
+    pool: &SqlitePool,
+    key: &IdempotencyKey,
+    user_id: Uuid,
+    response: Response,
+) -> Result<Response, anyhow::Error> {
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .context("Failed to read the response body.")?;
+    let status = parts.status.as_u16() as i64;
+    let headers: Vec<(&str, &[u8])> = parts
+        .headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_bytes()))
+        .collect();
+    let headers = serde_json::to_string(&headers)?;
+    let user_id = user_id.to_string();
+    let key_str = key.as_ref();
+    let body_bytes = body.as_ref();
+    sqlx::query!(
+        r#"
+        UPDATE idempotency
+        SET response_status_code = $1, response_headers = $2,
+            response_body = $3
+        WHERE user_id = $4 AND idempotency_key = $5
+        "#,
+        status,
+        headers,
+        body_bytes,
+        user_id,
+        key_str,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to save the idempotent response.")?;
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Give up a reserved key after a failed request, so it can be retried
+#[tracing::instrument(name = "Release an idempotency key", skip(pool))]
+pub async fn release(
+    pool: &SqlitePool,
+    key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let user_id = user_id.to_string();
+    let key = key.as_ref();
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+            AND response_status_code IS NULL
+        "#,
+        user_id,
+        key,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to release the idempotency key.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn an_empty_key_is_rejected() {
+        assert_err!(IdempotencyKey::parse("  ".into()));
+    }
+
+    #[test]
+    fn keys_are_at_most_50_characters_long() {
+        assert_ok!(IdempotencyKey::parse("a".repeat(50)));
+        assert_err!(IdempotencyKey::parse("a".repeat(51)));
+    }
+
+    #[test]
+    fn a_uuid_is_a_valid_key() {
+        assert_ok!(IdempotencyKey::parse(uuid::Uuid::new_v4().to_string()));
+    }
+}
@@ -0,0 +1,465 @@
+//! src/import.rs
+//!
+//! Bulk import of subscribers from CSV, shared by the admin upload and
+//! the `import_subscribers` binary.
+
+use crate::attributes::{get_attribute_schema, set_subscriber_attributes};
+use crate::domain::{
+    AttributeSchema, Locale, NewSubscriber, OptIn, SignupSource,
+    SubscriberEmail, SubscriberName,
+};
+use crate::email_client::EmailClient;
+use crate::erasure::is_suppressed;
+use crate::routes::{
+    error_chain_fmt, generate_subscription_token, get_subscriber_by_email,
+    insert_subscriber, send_confirmation_email, store_token,
+};
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+
+/// `form_id` of imported subscribers, so they show up as their own
+/// source
+pub const IMPORT_FORM_ID: &str = "csv_import";
+
+/// What to do with the imported subscribers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Already opted in elsewhere, requires a consent note
+    Confirmed,
+    /// Stored as pending and sent a confirmation email
+    Pending,
+    /// Validate every row without storing or sending anything
+    DryRun,
+}
+
+impl ImportMode {
+    pub fn parse(s: &str) -> Result<ImportMode, String> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "confirmed" => Ok(Self::Confirmed),
+            "pending" => Ok(Self::Pending),
+            "dry_run" => Ok(Self::DryRun),
+            other => Err(format!(
+                "\"{}\" is not a valid import mode. \
+                Use either `confirmed`, `pending` or `dry_run`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportMode::Confirmed => "confirmed",
+            ImportMode::Pending => "pending",
+            ImportMode::DryRun => "dry_run",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub mode: ImportMode,
+    /// Header of the column holding the name
+    pub name_column: String,
+    /// Header of the column holding the email
+    pub email_column: String,
+    /// Column headers and the attribute key each one maps to
+    pub attribute_columns: Vec<(String, String)>,
+    pub consent_note: Option<String>,
+}
+
+impl ImportOptions {
+    /// Parse a `column=key` attribute mapping
+    pub fn parse_attribute_column(s: &str) -> Result<(String, String), String> {
+        let (column, key) = s
+            .rsplit_once('=')
+            .filter(|(c, k)| !c.trim().is_empty() && !k.trim().is_empty())
+            .ok_or_else(|| {
+                format!("\"{}\" is not a valid `column=key` mapping.", s)
+            })?;
+        Ok((column.trim().to_string(), key.trim().to_lowercase()))
+    }
+
+    fn consent_note(&self) -> Result<Option<&str>, ImportError> {
+        let note = self
+            .consent_note
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty());
+        if self.mode == ImportMode::Confirmed && note.is_none() {
+            return Err(ImportError::ValidationError(
+                "Importing as confirmed requires a consent note.".into(),
+            ));
+        }
+        Ok(note)
+    }
+}
+
+/// A row that was not imported, `line` is 1-based and counts the header
+#[derive(Debug)]
+pub struct RowError {
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+fn column_index(
+    headers: &csv::StringRecord,
+    column: &str,
+) -> Result<usize, ImportError> {
+    headers
+        .iter()
+        .position(|h| h.trim().eq_ignore_ascii_case(column.trim()))
+        .ok_or_else(|| {
+            ImportError::ValidationError(format!(
+                "The CSV has no \"{}\" column.",
+                column
+            ))
+        })
+}
+
+struct ImportRow {
+    subscriber: NewSubscriber,
+    attributes: BTreeMap<String, String>,
+}
+
+/// A CSV line number with its subscriber, or why it cannot be one
+type ParsedRow = (u64, Result<ImportRow, String>);
+
+fn parse_rows(
+    csv: impl std::io::Read,
+    options: &ImportOptions,
+    schema: &AttributeSchema,
+) -> Result<Vec<ParsedRow>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(csv);
+    let headers = reader.headers().map_err(|e| {
+        ImportError::ValidationError(format!("Failed to read the CSV: {}", e))
+    })?;
+    let name_index = column_index(headers, &options.name_column)?;
+    let email_index = column_index(headers, &options.email_column)?;
+    // Optional, subscribers without one read English
+    let locale_index = headers
+        .iter()
+        .position(|h| h.trim().eq_ignore_ascii_case("locale"));
+    let attribute_indexes = options
+        .attribute_columns
+        .iter()
+        .map(|(column, key)| {
+            let definition = schema.get(key).ok_or_else(|| {
+                ImportError::ValidationError(format!(
+                    "\"{}\" is not a known attribute.",
+                    key
+                ))
+            })?;
+            Ok((column_index(headers, column)?, definition))
+        })
+        .collect::<Result<Vec<_>, ImportError>>()?;
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let (line, parsed) = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line());
+                let field = |i| record.get(i).unwrap_or_default().to_string();
+                let parsed = (|| {
+                    let name = SubscriberName::parse(field(name_index))?;
+                    let email = SubscriberEmail::parse(field(email_index))?;
+                    let locale = match locale_index.map(field) {
+                        Some(l) if !l.trim().is_empty() => Locale::parse(&l)?,
+                        _ => Locale::default(),
+                    };
+                    let mut attributes = BTreeMap::new();
+                    for (index, definition) in &attribute_indexes {
+                        let value = field(*index);
+                        // Blank cells leave the attribute unset
+                        if value.trim().is_empty() {
+                            continue;
+                        }
+                        let value = definition
+                            .kind
+                            .normalize(&serde_json::Value::String(value))
+                            .map_err(|e| {
+                                format!("{}: {}", definition.key, e)
+                            })?;
+                        attributes.insert(definition.key.clone(), value);
+                    }
+                    Ok(ImportRow {
+                        subscriber: NewSubscriber {
+                            email,
+                            name,
+                            locale,
+                            source: SignupSource {
+                                form_id: Some(IMPORT_FORM_ID.into()),
+                                ..Default::default()
+                            },
+                        },
+                        attributes,
+                    })
+                })();
+                (line, parsed)
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                (line, Err(format!("Malformed CSV row: {}", e)))
+            }
+        };
+        rows.push((line, parsed));
+    }
+    Ok(rows)
+}
+
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(pool, email_client, base_url, secret, csv),
+    fields(mode = options.mode.as_str())
+)]
+pub async fn import_subscribers(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+    secret: &HmacSecret,
+    csv: impl std::io::Read,
+    options: &ImportOptions,
+) -> Result<ImportReport, ImportError> {
+    let consent_note = options.consent_note()?;
+    let schema = get_attribute_schema(pool).await?;
+    let rows = parse_rows(csv, options, &schema)?;
+    let opt_in = match options.mode {
+        ImportMode::Confirmed => OptIn::Single,
+        ImportMode::Pending | ImportMode::DryRun => OptIn::Double,
+    };
+
+    let mut report = ImportReport {
+        rows: rows.len(),
+        ..Default::default()
+    };
+    let mut confirmations = Vec::new();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    for (line, parsed) in rows {
+        let ImportRow {
+            subscriber: new_subscriber,
+            attributes,
+        } = match parsed {
+            Ok(row) => row,
+            Err(message) => {
+                report.errors.push(RowError { line, message });
+                continue;
+            }
+        };
+        let email = new_subscriber.email.as_ref().to_string();
+        if is_suppressed(&mut transaction, &new_subscriber.email, secret)
+            .await
+            .context("Failed to check the suppression list.")?
+        {
+            report.errors.push(RowError {
+                line,
+                message: format!(
+                    "{} was erased and cannot be imported.",
+                    email
+                ),
+            });
+            continue;
+        }
+        // Also catches the same address appearing twice in the file
+        if get_subscriber_by_email(
+            &mut transaction,
+            new_subscriber.email.canonical(),
+        )
+        .await
+        .context("Failed to look up an existing subscriber.")?
+        .is_some()
+        {
+            report.errors.push(RowError {
+                line,
+                message: format!("{} is already subscribed.", email),
+            });
+            continue;
+        }
+
+        let subscriber_id =
+            insert_subscriber(&mut transaction, &new_subscriber, opt_in)
+                .await
+                .context("Failed to insert imported subscriber.")?;
+        set_subscriber_attributes(&mut transaction, subscriber_id, &attributes)
+            .await
+            .context("Failed to store imported attributes.")?;
+        if let Some(note) = consent_note {
+            let id = subscriber_id.to_string();
+            sqlx::query!(
+                r#"UPDATE subscriptions SET consent_note = $1 WHERE id = $2"#,
+                note,
+                id,
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to store the consent note.")?;
+        }
+        if options.mode == ImportMode::Pending {
+            let subscription_token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("Failed to store a confirmation token.")?;
+            confirmations.push((line, new_subscriber, subscription_token));
+        }
+        report.imported += 1;
+    }
+
+    if options.mode == ImportMode::DryRun {
+        transaction
+            .rollback()
+            .await
+            .context("Failed to roll back the dry run.")?;
+        return Ok(report);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")?;
+
+    for (line, new_subscriber, subscription_token) in confirmations {
+        let email = new_subscriber.email.as_ref().to_string();
+        if let Err(e) = send_confirmation_email(
+            email_client,
+            new_subscriber,
+            base_url,
+            &subscription_token,
+        )
+        .await
+        {
+            tracing::warn!(error = ?e, line, "Failed to send a confirmation.");
+            report.errors.push(RowError {
+                line,
+                message: format!(
+                    "{} was imported, but the confirmation email failed.",
+                    email
+                ),
+            });
+        }
+    }
+    tracing::info!(
+        rows = report.rows,
+        imported = report.imported,
+        "Imported subscribers."
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_rows, ImportMode, ImportOptions};
+    use crate::domain::{AttributeDefinition, AttributeKind, AttributeSchema};
+    use claims::{assert_err, assert_ok, assert_ok_eq};
+
+    fn options(mode: ImportMode) -> ImportOptions {
+        ImportOptions {
+            mode,
+            name_column: "Full Name".into(),
+            email_column: "E-mail".into(),
+            attribute_columns: vec![],
+            consent_note: None,
+        }
+    }
+
+    #[test]
+    fn columns_are_mapped_by_header_and_rows_validated() {
+        let csv = "id,full name,e-mail\n\
+            1,Ursula,ursula@example.com\n\
+            2,Bad,not-an-email\n\
+            3,,empty@example.com\n";
+        let rows = parse_rows(
+            csv.as_bytes(),
+            &options(ImportMode::DryRun),
+            &AttributeSchema::default(),
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].0, 2);
+        assert!(rows[0].1.is_ok());
+        assert!(rows[1].1.is_err());
+        assert!(rows[2].1.is_err());
+    }
+
+    #[test]
+    fn a_missing_column_is_rejected() {
+        let csv = "name,email\nUrsula,ursula@example.com\n";
+        assert!(parse_rows(
+            csv.as_bytes(),
+            &options(ImportMode::DryRun),
+            &AttributeSchema::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn confirmed_imports_require_a_consent_note() {
+        assert_err!(options(ImportMode::Confirmed).consent_note());
+        let mut with_note = options(ImportMode::Confirmed);
+        with_note.consent_note = Some("Signed up at the 2024 meetup".into());
+        assert_ok!(with_note.consent_note());
+        assert_ok!(options(ImportMode::Pending).consent_note());
+    }
+
+    #[test]
+    fn modes_are_parsed() {
+        assert_eq!(ImportMode::parse("Dry-Run").unwrap(), ImportMode::DryRun);
+        assert_err!(ImportMode::parse("maybe"));
+    }
+
+    #[test]
+    fn mapped_columns_become_validated_attributes() {
+        let schema = AttributeSchema::new(vec![AttributeDefinition {
+            key: "seats".into(),
+            label: "Seats".into(),
+            kind: AttributeKind::Number,
+        }]);
+        let mut options = options(ImportMode::DryRun);
+        options.attribute_columns = vec![("Seat Count".into(), "seats".into())];
+        let csv = "full name,e-mail,seat count\n\
+            Ursula,ursula@example.com,12\n\
+            Octavia,octavia@example.com,\n\
+            Iain,iain@example.com,lots\n";
+
+        let rows = parse_rows(csv.as_bytes(), &options, &schema).unwrap();
+
+        assert_eq!(rows[0].1.as_ref().unwrap().attributes["seats"], "12");
+        assert!(rows[1].1.as_ref().unwrap().attributes.is_empty());
+        assert!(rows[2].1.is_err());
+
+        options.attribute_columns = vec![("Seat Count".into(), "plan".into())];
+        assert!(parse_rows(csv.as_bytes(), &options, &schema).is_err());
+    }
+
+    #[test]
+    fn attribute_mappings_are_parsed() {
+        assert_ok_eq!(
+            ImportOptions::parse_attribute_column("Company Name = Company"),
+            ("Company Name".to_string(), "company".to_string())
+        );
+        assert_err!(ImportOptions::parse_attribute_column("company"));
+    }
+}
@@ -0,0 +1,459 @@
+//! src/issue_delivery_worker.rs
+//!
+//! Background delivery of newsletter issues. Publishing an issue queues
+//! one row per recipient in `issue_delivery_queue`, the worker claims
+//! them one at a time and records the outcome of every send. A claim is
+//! a single `UPDATE`, so several workers never send to the same
+//! recipient, and claims left behind by a crashed worker are picked up
+//! again once they time out. Transient email failures put the recipient
+//! back in the queue after a backoff, permanent ones fail it for good.
+
+use crate::attributes::get_attribute_schema;
+use crate::domain::{DeliveryStatus, IssueStatus, Locale, SubscriberEmail};
+use crate::email_client::{EmailClient, EmailError};
+use crate::engagement::{record_issue_sent, track_issue_html};
+use crate::routes::{get_current_utc_timestamp, Translation};
+use crate::settings::DeliverySettings;
+use crate::startup::HmacSecret;
+use crate::templates::{get_layout, IssueTemplate, TemplateContext};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+enum DeliveryOutcome {
+    Sent,
+    /// Not sent, with the reason why
+    Skipped(String),
+    Failed(EmailError),
+    /// The templates of the issue failed, sending again would not help
+    Unrenderable(String),
+    /// Could not get as far as sending, e.g. the stored issue is invalid
+    Unexpected(anyhow::Error),
+}
+
+/// An issue and one of its recipients, ready to be rendered for them
+pub(crate) struct IssueForSubscriber {
+    pub email: String,
+    pub status: String,
+    pub preferences_token: Option<String>,
+    /// In the subscriber's locale when the issue was translated to it
+    pub template: IssueTemplate,
+    pub context: TemplateContext,
+}
+
+/// Load an issue with everything its templates need for a subscriber
+pub(crate) async fn load_issue_for_subscriber(
+    pool: &SqlitePool,
+    base_url: &str,
+    issue_id: &str,
+    subscriber_id: &str,
+) -> Result<IssueForSubscriber, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT i.title, i.html_content, i.text_content, i.translations,
+            i.layout, s.email, s.name, s.locale, s.status,
+            s.preferences_token,
+            (SELECT json_group_object(a.key, a.value)
+                FROM subscriber_attributes a
+                WHERE a.subscriber_id = s.id) AS "attributes!: String"
+        FROM newsletter_issues i, subscriptions s
+        WHERE i.id = $1 AND s.id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to query the issue and its recipient.")?;
+
+    let mut translations: HashMap<String, Translation> =
+        serde_json::from_str(&record.translations)
+            .context("Invalid stored issue translations.")?;
+    let translation = Locale::parse(&record.locale)
+        .ok()
+        .and_then(|locale| translations.remove(locale.as_str()));
+    let (title, html, text) = match translation {
+        Some(t) => (t.title, t.content.html, t.content.text),
+        None => (record.title, record.html_content, record.text_content),
+    };
+    let layout = match &record.layout {
+        Some(name) => Some(
+            get_layout(pool, name)
+                .await?
+                .context("The layout of the issue does not exist.")?,
+        ),
+        None => None,
+    };
+    let token = record.preferences_token.as_deref().unwrap_or_default();
+    let context = TemplateContext::new(
+        record.name,
+        &get_attribute_schema(pool).await?,
+        serde_json::from_str(&record.attributes)
+            .context("Invalid stored subscriber attributes.")?,
+        format!(
+            "{}preferences?token={}",
+            base_url,
+            urlencoding::encode(token)
+        ),
+        format!(
+            "{}issues/{}?token={}",
+            base_url,
+            issue_id,
+            urlencoding::encode(token)
+        ),
+    );
+    Ok(IssueForSubscriber {
+        email: record.email,
+        status: record.status,
+        preferences_token: record.preferences_token,
+        template: IssueTemplate {
+            title,
+            html,
+            text,
+            layout,
+        },
+        context,
+    })
+}
+
+/// Queue an issue for every subscriber in `subscriber_ids`
+#[tracing::instrument(name = "Enqueue delivery tasks", skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    issue_id: Uuid,
+    subscriber_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    let issue_id = issue_id.to_string();
+    let enqueued_at = get_current_utc_timestamp();
+    for subscriber_id in subscriber_ids {
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_delivery_queue
+                (newsletter_issue_id, subscriber_id, enqueued_at)
+            VALUES ($1, $2, $3)
+            "#,
+            issue_id,
+            subscriber_id,
+            enqueued_at,
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Send the issue to the next queued recipient, if any
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_id=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+    secret: &HmacSecret,
+    settings: &DeliverySettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let claimed_at = get_current_utc_timestamp();
+    // An idle worker must not take the write lock, SQLite fails other
+    // transactions upgrading to a write instead of waiting on it
+    let claimable = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM issue_delivery_queue
+            WHERE (status = 'queued'
+                    AND (execute_after IS NULL
+                        OR datetime(execute_after) <= datetime($1)))
+                OR (status = 'sending'
+                    AND datetime(claimed_at, '+' || $2 || ' seconds')
+                        <= datetime($1))
+        ) AS "claimable!: bool"
+        "#,
+        claimed_at,
+        settings.claim_timeout_seconds,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look for delivery tasks.")?
+    .claimable;
+    if !claimable {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let Some(task) = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue SET status = 'sending', claimed_at = $1
+        WHERE rowid = (
+            SELECT rowid FROM issue_delivery_queue
+            WHERE (status = 'queued'
+                    AND (execute_after IS NULL
+                        OR datetime(execute_after) <= datetime($1)))
+                OR (status = 'sending'
+                    AND datetime(claimed_at, '+' || $2 || ' seconds')
+                        <= datetime($1))
+            ORDER BY enqueued_at
+            LIMIT 1)
+        RETURNING newsletter_issue_id, subscriber_id, n_attempts
+        "#,
+        claimed_at,
+        settings.claim_timeout_seconds,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to claim a delivery task.")?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    tracing::Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(&task.newsletter_issue_id),
+        )
+        .record(
+            "subscriber_id",
+            tracing::field::display(&task.subscriber_id),
+        );
+
+    let attempts = task.n_attempts as u32 + 1;
+    let outcome = deliver(
+        pool,
+        email_client,
+        base_url,
+        secret,
+        &task.newsletter_issue_id,
+        &task.subscriber_id,
+    )
+    .await
+    .unwrap_or_else(DeliveryOutcome::Unexpected);
+    let sent = matches!(outcome, DeliveryOutcome::Sent);
+    let retry_at = |retry_after| {
+        settings
+            .retry_policy()
+            .next_delay(attempts, retry_after)
+            // A delay too far out to represent is as good as none
+            .and_then(|delay| chrono::Duration::from_std(delay).ok())
+            .and_then(|delay| Utc::now().checked_add_signed(delay))
+            .map(|retry_at| retry_at.format("%Y-%m-%d %H:%M:%S").to_string())
+    };
+    let mut execute_after = None;
+    let (status, error) = match outcome {
+        DeliveryOutcome::Sent => (DeliveryStatus::Sent, None),
+        DeliveryOutcome::Skipped(reason) => {
+            (DeliveryStatus::Skipped, Some(reason))
+        }
+        DeliveryOutcome::Unrenderable(reason) => {
+            (DeliveryStatus::Failed, Some(reason))
+        }
+        DeliveryOutcome::Failed(error) => {
+            let reason = match std::error::Error::source(&error) {
+                Some(source) => format!("{error}: {source}"),
+                None => error.to_string(),
+            };
+            execute_after = error
+                .is_transient()
+                .then(|| retry_at(error.retry_after()))
+                .flatten();
+            match execute_after {
+                Some(_) => (DeliveryStatus::Queued, Some(reason)),
+                None => (DeliveryStatus::Failed, Some(reason)),
+            }
+        }
+        DeliveryOutcome::Unexpected(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Failed to prepare an issue for delivery."
+            );
+            execute_after = retry_at(None);
+            let reason = format!("{error:#}");
+            match execute_after {
+                Some(_) => (DeliveryStatus::Queued, Some(reason)),
+                None => (DeliveryStatus::Failed, Some(reason)),
+            }
+        }
+    };
+    let status_str = status.as_str();
+    let finished_at =
+        (status != DeliveryStatus::Queued).then(get_current_utc_timestamp);
+    let attempts = attempts as i64;
+    // A worker whose claim timed out must not overwrite the outcome of
+    // the one that took over
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET status = $1, error = $2, finished_at = $3, n_attempts = $4,
+            execute_after = $5
+        WHERE newsletter_issue_id = $6 AND subscriber_id = $7
+            AND claimed_at = $8
+        "#,
+        status_str,
+        error,
+        finished_at,
+        attempts,
+        execute_after,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        claimed_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the delivery outcome.")?;
+    // The email is out, failing to count it must not send it again
+    if sent {
+        if let Err(error) = record_issue_sent(pool, &task.subscriber_id).await {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Failed to count a delivered issue."
+            );
+        }
+    }
+
+    let issue_id = Uuid::parse_str(&task.newsletter_issue_id)
+        .context("Failed to parse stored newsletter issue id.")?;
+    complete_issue_if_delivered(pool, issue_id).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Send an issue to one subscriber
+///
+/// An error is an unexpected failure before sending, retried like a
+/// transient one.
+async fn deliver(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+    secret: &HmacSecret,
+    issue_id: &str,
+    subscriber_id: &str,
+) -> Result<DeliveryOutcome, anyhow::Error> {
+    let issue =
+        load_issue_for_subscriber(pool, base_url, issue_id, subscriber_id)
+            .await?;
+
+    // They may have left since the issue was queued
+    if issue.status != "confirmed" {
+        return Ok(DeliveryOutcome::Skipped(
+            "The subscriber is no longer confirmed.".into(),
+        ));
+    }
+    // An invalid locale already fell back to the default one
+    let email = match SubscriberEmail::parse(issue.email) {
+        Ok(email) => email,
+        Err(error) => {
+            tracing::warn!(
+                error,
+                "Skipping a confirmed subscriber. Their stored email is \
+                invalid"
+            );
+            return Ok(DeliveryOutcome::Skipped(error));
+        }
+    };
+    // Checked when the issue was saved, the attribute schema may have
+    // changed since
+    let rendered = match issue.template.render(&issue.context) {
+        Ok(rendered) => rendered,
+        Err(error) => {
+            tracing::error!(error, "Failed to render an issue.");
+            return Ok(DeliveryOutcome::Unrenderable(error));
+        }
+    };
+
+    let mut html = rendered.html;
+    if let Some(token) = &issue.preferences_token {
+        html = track_issue_html(&html, base_url, token, secret);
+    }
+    if let Err(error) = email_client
+        .send_email(&email, &rendered.title, &html, &rendered.text)
+        .await
+    {
+        tracing::error!(
+            error.cause_chain = ?error,
+            error.message = %error,
+            "Failed to deliver issue to a confirmed subscriber."
+        );
+        return Ok(DeliveryOutcome::Failed(error));
+    }
+    Ok(DeliveryOutcome::Sent)
+}
+
+/// Mark an issue published, or failed if nobody could be sent to, once
+/// every recipient was handled
+#[tracing::instrument(name = "Complete a delivered issue", skip(pool))]
+pub async fn complete_issue_if_delivered(
+    pool: &SqlitePool,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let issue_id = issue_id.to_string();
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            count(*) FILTER (WHERE status IN ('queued', 'sending'))
+                AS "pending!: i64",
+            count(*) FILTER (WHERE status = 'sent') AS "sent!: i64",
+            count(*) FILTER (WHERE status = 'failed') AS "failed!: i64"
+        FROM issue_delivery_queue WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the deliveries of an issue.")?;
+    if counts.pending > 0 {
+        return Ok(());
+    }
+
+    let (status, published_at) = if counts.sent == 0 && counts.failed > 0 {
+        (IssueStatus::Failed, None)
+    } else {
+        (IssueStatus::Published, Some(get_current_utc_timestamp()))
+    };
+    let status = status.as_str();
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET status = $1, published_at = $2
+        WHERE id = $3 AND status = 'publishing'
+        "#,
+        status,
+        published_at,
+        issue_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the newsletter issue status.")?;
+    Ok(())
+}
+
+/// Drain the delivery queue, never returns
+pub async fn run_worker_until_stopped(
+    pool: SqlitePool,
+    email_client: EmailClient,
+    base_url: String,
+    secret: HmacSecret,
+    settings: DeliverySettings,
+) {
+    loop {
+        match try_execute_task(
+            &pool,
+            &email_client,
+            &base_url,
+            &secret,
+            &settings,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            // Errors are already logged by `try_execute_task`
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => {
+                tokio::time::sleep(settings.poll_interval()).await
+            }
+        }
+    }
+}
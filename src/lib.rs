@@ -16,11 +16,27 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause
 
+pub mod attributes;
 pub mod authentication;
+pub mod bot_protection;
 pub mod domain;
+pub mod domain_policy;
+pub mod drip;
 pub mod email_client;
+pub mod engagement;
+pub mod erasure;
+pub mod i18n;
+pub mod idempotency;
+pub mod import;
+pub mod issue_delivery_worker;
+pub mod lifecycle;
+pub mod markdown;
+pub mod metrics;
+pub mod newsletter_issues;
+pub mod pruning;
 pub mod routes;
 pub mod settings;
 pub mod startup;
 pub mod telemetry;
+pub mod templates;
 pub mod test_utils;
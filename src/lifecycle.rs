@@ -0,0 +1,117 @@
+//! src/lifecycle.rs
+//!
+//! Subscriber status changes. Every write to `subscriptions.status` goes
+//! through here so the transition is validated against
+//! `SubscriberStatus` and recorded in `subscriber_status_history`.
+
+use crate::domain::SubscriberStatus;
+use crate::routes::{error_chain_fmt, get_current_utc_timestamp};
+use anyhow::Context;
+use sqlx::{Sqlite, Transaction};
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum StatusChangeError {
+    #[error("{0}")]
+    InvalidTransition(String),
+    #[error("No subscriber with this id.")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for StatusChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "Get subscriber status", skip(transaction))]
+pub async fn get_status(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberStatus>, anyhow::Error> {
+    let id = subscriber_id.to_string();
+    let row =
+        sqlx::query!(r#"SELECT status FROM subscriptions WHERE id = $1"#, id)
+            .fetch_optional(&mut **transaction)
+            .await
+            .context("Failed to query subscriber status.")?;
+    row.map(|r| SubscriberStatus::parse(&r.status))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Start the history of a subscriber that was just inserted
+#[tracing::instrument(
+    name = "Record initial subscriber status",
+    skip(transaction)
+)]
+pub async fn record_initial_status(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    status: SubscriberStatus,
+) -> Result<(), sqlx::Error> {
+    record_history(transaction, subscriber_id, None, status).await
+}
+
+/// Move a subscriber to `next`, returning the status they left
+#[tracing::instrument(name = "Change subscriber status", skip(transaction))]
+pub async fn change_status(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    next: SubscriberStatus,
+) -> Result<SubscriberStatus, StatusChangeError> {
+    let current = get_status(transaction, subscriber_id)
+        .await?
+        .ok_or(StatusChangeError::UnknownSubscriber)?;
+    current
+        .transition_to(next)
+        .map_err(StatusChangeError::InvalidTransition)?;
+
+    let id = subscriber_id.to_string();
+    let status = next.as_str();
+    let changed_at = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $1, status_changed_at = $2
+        WHERE id = $3
+        "#,
+        status,
+        changed_at,
+        id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update subscriber status.")?;
+    record_history(transaction, subscriber_id, Some(current), next)
+        .await
+        .context("Failed to record the status change.")?;
+    Ok(current)
+}
+
+async fn record_history(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    from: Option<SubscriberStatus>,
+    to: SubscriberStatus,
+) -> Result<(), sqlx::Error> {
+    let id = subscriber_id.to_string();
+    let from = from.map(|s| s.as_str());
+    let to = to.as_str();
+    let changed_at = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_status_history
+            (subscriber_id, from_status, to_status, changed_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        id,
+        from,
+        to,
+        changed_at,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,353 @@
+//! src/markdown.rs
+//!
+//! Markdown authoring for newsletter issues. The same source is rendered
+//! to sanitized HTML and to plain text for the text part of the email,
+//! where headings are underlined, paragraphs are wrapped and links are
+//! listed as numbered footnotes after the body.
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+
+/// Width plain text paragraphs are wrapped at
+const TEXT_WIDTH: usize = 72;
+
+/// Render Markdown to HTML, dropping anything unsafe such as scripts
+pub fn to_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(markdown));
+    restore_placeholders(&ammonia::clean(&html))
+}
+
+/// Undo the percent-encoding of `{{ ... }}` placeholders in link targets,
+/// so `[Unsubscribe]({{unsubscribe_url}})` is still filled in when the
+/// issue is rendered for each subscriber
+///
+/// This runs after sanitizing, so only variable names are restored and
+/// anything else is left encoded.
+fn restore_placeholders(html: &str) -> String {
+    const OPEN: &str = "%7B%7B";
+    const CLOSE: &str = "%7D%7D";
+    let mut restored = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(OPEN) {
+        restored.push_str(&rest[..start]);
+        rest = &rest[start + OPEN.len()..];
+        let name = rest
+            .find(CLOSE)
+            .and_then(|len| {
+                urlencoding::decode(&rest[..len])
+                    .ok()
+                    .map(|name| (len, name))
+            })
+            .filter(|(_, name)| is_variable(name));
+        match name {
+            Some((len, name)) => {
+                restored.push_str("{{");
+                restored.push_str(&name);
+                restored.push_str("}}");
+                rest = &rest[len + CLOSE.len()..];
+            }
+            None => restored.push_str(OPEN),
+        }
+    }
+    restored.push_str(rest);
+    restored
+}
+
+/// Whether `name` is a template variable like ` subscriber.name `
+fn is_variable(name: &str) -> bool {
+    !name.trim().is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ' '))
+}
+
+/// Render Markdown to wrapped plain text
+pub fn to_text(markdown: &str) -> String {
+    let mut renderer = TextRenderer::default();
+    for event in Parser::new(markdown) {
+        renderer.event(event);
+    }
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    output: String,
+    /// Text of the block being written
+    inline: String,
+    /// Targets of the links being written
+    open_links: Vec<String>,
+    /// Footnotes, numbered from 1
+    links: Vec<String>,
+    /// Next number of every list being written, `None` when unordered
+    lists: Vec<Option<u64>>,
+    /// Marker of the list item whose first block is not written yet
+    item_marker: Option<String>,
+    quote_depth: usize,
+    code_block: Option<String>,
+    last_was_item: bool,
+}
+
+impl TextRenderer {
+    fn event(&mut self, event: Event<'_>) {
+        if let Some(code) = &mut self.code_block {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => self.flush_code_block(),
+                _ => {}
+            }
+            return;
+        }
+        match event {
+            Event::Start(Tag::Heading { .. }) => self.flush(),
+            Event::End(TagEnd::Heading(level)) => self.flush_heading(level),
+            Event::End(TagEnd::Paragraph) => self.flush(),
+            Event::Start(Tag::List(start)) => {
+                // The text of a tight item comes before its nested list
+                self.flush();
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.last_was_item = false;
+                }
+            }
+            Event::Start(Tag::Item) => {
+                self.flush();
+                self.item_marker = self.next_item_marker();
+            }
+            Event::End(TagEnd::Item) => {
+                self.flush();
+                self.item_marker = None;
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.flush();
+                self.quote_depth += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.flush();
+                self.quote_depth -= 1;
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.flush();
+                self.code_block = Some(String::new());
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => {
+                self.open_links.push(dest_url.into_string())
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                self.close_link()
+            }
+            Event::Text(text) | Event::Code(text) => {
+                self.inline.push_str(&text)
+            }
+            Event::SoftBreak => self.inline.push(' '),
+            Event::HardBreak => self.inline.push('\n'),
+            Event::Rule => {
+                self.flush();
+                self.push_block("* * *".into(), false);
+            }
+            _ => {}
+        }
+    }
+
+    fn close_link(&mut self) {
+        let Some(url) = self.open_links.pop() else {
+            return;
+        };
+        // Bare links are readable as they are
+        if self.inline.ends_with(&url) {
+            return;
+        }
+        let number = match self.links.iter().position(|l| *l == url) {
+            Some(i) => i + 1,
+            None => {
+                self.links.push(url);
+                self.links.len()
+            }
+        };
+        self.inline.push_str(&format!(" [{number}]"));
+    }
+
+    /// Prefix of the lines of the next block: quote marks, then the
+    /// indentation of enclosing list items
+    fn indent(&self) -> String {
+        let mut indent = "> ".repeat(self.quote_depth);
+        for _ in 0..self.lists.len().saturating_sub(1) {
+            indent.push_str("   ");
+        }
+        indent
+    }
+
+    /// Write the pending text as a wrapped block
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.inline);
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let indent = self.indent();
+        let (first, rest) = match self.item_marker.take() {
+            Some(marker) => {
+                let rest = format!("{indent}{}", " ".repeat(marker.len()));
+                (format!("{indent}{marker}"), rest)
+            }
+            None if !self.lists.is_empty() => {
+                // Further paragraphs of an item line up with its text
+                let rest = format!("{indent}   ");
+                (rest.clone(), rest)
+            }
+            None => (indent.clone(), indent),
+        };
+        let in_item = !self.lists.is_empty();
+        let options = textwrap::Options::new(TEXT_WIDTH)
+            .initial_indent(&first)
+            .subsequent_indent(&rest);
+        self.push_block(textwrap::fill(text, options), in_item);
+    }
+
+    fn next_item_marker(&mut self) -> Option<String> {
+        match self.lists.last_mut()? {
+            Some(number) => {
+                *number += 1;
+                Some(format!("{}. ", *number - 1))
+            }
+            None => Some("- ".into()),
+        }
+    }
+
+    fn flush_heading(&mut self, level: HeadingLevel) {
+        let text = std::mem::take(&mut self.inline);
+        let text = text.trim();
+        let underline = if level == HeadingLevel::H1 { "=" } else { "-" };
+        let indent = self.indent();
+        let block = format!(
+            "{indent}{text}\n{indent}{}",
+            underline.repeat(text.chars().count())
+        );
+        self.push_block(block, false);
+    }
+
+    fn flush_code_block(&mut self) {
+        let Some(code) = self.code_block.take() else {
+            return;
+        };
+        let indent = format!("{}    ", self.indent());
+        let block = code
+            .trim_end()
+            .lines()
+            .map(|line| format!("{indent}{line}").trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.push_block(block, false);
+    }
+
+    /// Append a block, separated by a blank line unless it continues a
+    /// list
+    fn push_block(&mut self, block: String, is_item: bool) {
+        if !self.output.is_empty() {
+            self.output.push('\n');
+            if !(is_item && self.last_was_item) {
+                self.output.push('\n');
+            }
+        }
+        self.output.push_str(&block);
+        self.last_was_item = is_item;
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        if !self.links.is_empty() {
+            let footnotes = self
+                .links
+                .iter()
+                .enumerate()
+                .map(|(i, url)| format!("[{}] {}", i + 1, url))
+                .collect::<Vec<_>>()
+                .join("\n");
+            self.push_block(footnotes, false);
+        }
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_html, to_text};
+
+    #[test]
+    fn html_is_sanitized() {
+        let html = to_html("# Hi\n\n*there*\n\n<script>alert(1)</script>");
+        assert!(html.contains("<h1>Hi</h1>"));
+        assert!(html.contains("<em>there</em>"));
+        assert!(!html.contains("script"));
+    }
+
+    #[test]
+    fn headings_are_underlined() {
+        assert_eq!(
+            to_text("# News\n\n## Events\n\nSoon."),
+            "News\n====\n\nEvents\n------\n\nSoon."
+        );
+    }
+
+    #[test]
+    fn links_become_footnotes() {
+        assert_eq!(
+            to_text(
+                "Read [the post](https://example.com/a) and \
+                [more](https://example.com/a), or <https://example.com/b>."
+            ),
+            "Read the post [1] and more [1], or https://example.com/b.\n\n\
+            [1] https://example.com/a"
+        );
+    }
+
+    #[test]
+    fn paragraphs_are_wrapped() {
+        let text = to_text(&"word ".repeat(30));
+        assert!(text.lines().count() > 1);
+        assert!(text.lines().all(|line| line.len() <= 72));
+    }
+
+    #[test]
+    fn lists_keep_their_markers() {
+        assert_eq!(
+            to_text("Agenda:\n\n1. Tea\n2. Talks\n   - Short\n\n- Done"),
+            "Agenda:\n\n1. Tea\n2. Talks\n   - Short\n\n- Done"
+        );
+    }
+
+    #[test]
+    fn placeholders_are_kept() {
+        assert_eq!(to_text("Hi {{ name }}"), "Hi {{ name }}");
+        assert_eq!(to_html("Hi {{ name }}"), "<p>Hi {{ name }}</p>\n");
+    }
+
+    #[test]
+    fn placeholders_in_link_targets_are_kept() {
+        assert_eq!(
+            to_html(
+                "[Leave]({{unsubscribe_url}}) or [read](<{{ web_view_url }}>)"
+            ),
+            "<p><a href=\"{{unsubscribe_url}}\" rel=\"noopener noreferrer\">\
+            Leave</a> or <a href=\"{{ web_view_url }}\" \
+            rel=\"noopener noreferrer\">read</a></p>\n"
+        );
+        assert_eq!(
+            to_html("[Search](https://example.com/?q=%7B%7D)"),
+            "<p><a href=\"https://example.com/?q=%7B%7D\" \
+            rel=\"noopener noreferrer\">Search</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn only_variable_names_are_restored_in_link_targets() {
+        let html = to_html("[x](<{{ a\"onclick=\"b }}>) [z]({{name}})");
+        assert!(html.contains("href=\"%7B%7B"), "{html}");
+        assert!(!html.contains("{{ a"), "{html}");
+        assert!(html.contains("href=\"{{name}}\""), "{html}");
+    }
+}
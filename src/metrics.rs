@@ -0,0 +1,68 @@
+//! src/metrics.rs
+//!
+//! Minimal in-process counters, rendered in the Prometheus text format
+//! by `routes::metrics`.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct Metrics {
+    // Keyed by metric name, then by the rendered label set
+    counters: Mutex<BTreeMap<String, BTreeMap<String, u64>>>,
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{k}=\"{}\"", v.replace('"', "\\\"")))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(labels)
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut output = String::new();
+        for (name, series) in counters.iter() {
+            output.push_str(&format!("# TYPE {name} counter\n"));
+            for (labels, value) in series {
+                if labels.is_empty() {
+                    output.push_str(&format!("{name} {value}\n"));
+                } else {
+                    output.push_str(&format!("{name}{{{labels}}} {value}\n"));
+                }
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn counters_are_rendered_per_label_set() {
+        let metrics = Metrics::default();
+        metrics.increment("blocked_total", &[("reason", "disposable")]);
+        metrics.increment("blocked_total", &[("reason", "disposable")]);
+        metrics.increment("blocked_total", &[("reason", "blocklist")]);
+        metrics.increment("plain_total", &[]);
+
+        assert_eq!(
+            metrics.render(),
+            "# TYPE blocked_total counter\n\
+            blocked_total{reason=\"blocklist\"} 1\n\
+            blocked_total{reason=\"disposable\"} 2\n\
+            # TYPE plain_total counter\n\
+            plain_total 1\n"
+        );
+    }
+}
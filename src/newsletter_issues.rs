@@ -0,0 +1,500 @@
+//! src/newsletter_issues.rs
+//!
+//! Creating and scheduling newsletter issues. Issues are stored first and
+//! queued for their audience once due: straight away, or when
+//! `run_issue_scheduler` finds their `send_at` has passed. The schedule
+//! lives in `newsletter_issues`, so it survives restarts, and the audience
+//! is only resolved when the issue is queued. Until then an issue can be
+//! rescheduled or cancelled. Drafts are kept out of the schedule entirely
+//! until they are published. Templates are checked whenever an issue is
+//! saved, see `templates`.
+
+use crate::attributes::get_attribute_schema;
+use crate::domain::IssueStatus;
+use crate::issue_delivery_worker::{
+    complete_issue_if_delivered, enqueue_delivery_tasks,
+};
+use crate::routes::{
+    error_chain_fmt, get_current_utc_timestamp, Content, Translation,
+};
+use crate::templates::{get_layout, IssueTemplate, TemplateContext};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum IssueError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// An issue as written by its author
+pub struct NewIssue<'a> {
+    pub title: &'a str,
+    pub content: &'a Content,
+    /// Keyed by canonical locale name
+    pub translations: BTreeMap<&'a str, &'a Translation>,
+    /// Attribute values recipients must match
+    pub audience: BTreeMap<String, String>,
+    /// Sent straight away when `None` or in the past
+    pub send_at: Option<DateTime<Utc>>,
+    /// Kept for editing instead of being scheduled
+    pub draft: bool,
+    /// Name of the layout wrapped around the content
+    pub layout: Option<&'a str>,
+}
+
+/// Parse a send time given as RFC 3339, e.g. `2026-10-20T09:00:00+02:00`
+pub fn parse_send_at(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s.trim())
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| {
+            format!(
+                "\"{}\" is not a valid send time. Use a date, time and \
+                timezone such as 2026-10-20T09:00:00+02:00.",
+                s
+            )
+        })
+}
+
+fn format_timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn is_due(send_at: Option<DateTime<Utc>>) -> bool {
+    // `Option::is_none_or` needs a newer toolchain than the release image
+    match send_at {
+        Some(t) => t <= Utc::now(),
+        None => true,
+    }
+}
+
+/// Render the content and every translation for a sample recipient, so
+/// mistakes are reported now rather than when sending
+async fn validate_templates(
+    pool: &SqlitePool,
+    title: &str,
+    content: &Content,
+    translations: &BTreeMap<&str, &Translation>,
+    layout: Option<&str>,
+) -> Result<(), IssueError> {
+    let layout = match layout {
+        Some(name) => Some(get_layout(pool, name).await?.ok_or_else(|| {
+            IssueError::ValidationError(format!(
+                "\"{}\" is not a known layout.",
+                name
+            ))
+        })?),
+        None => None,
+    };
+    let context = TemplateContext::sample(&get_attribute_schema(pool).await?);
+    let variants = std::iter::once(("", title, content)).chain(
+        translations
+            .iter()
+            .map(|(locale, t)| (*locale, t.title.as_str(), &t.content)),
+    );
+    for (locale, title, content) in variants {
+        let template = IssueTemplate {
+            title: title.into(),
+            html: content.html.clone(),
+            text: content.text.clone(),
+            layout: layout.clone(),
+        };
+        template.render(&context).map_err(|e| {
+            IssueError::ValidationError(if locale.is_empty() {
+                e
+            } else {
+                format!("{} ({} translation)", e, locale)
+            })
+        })?;
+    }
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: String,
+    attributes: HashMap<String, String>,
+}
+
+impl ConfirmedSubscriber {
+    fn in_audience(&self, audience: &BTreeMap<String, String>) -> bool {
+        audience
+            .iter()
+            .all(|(key, value)| self.attributes.get(key) == Some(value))
+    }
+}
+
+/// Store an issue, queueing it right away unless it is a draft or
+/// scheduled later
+#[tracing::instrument(name = "Create a newsletter issue", skip(pool, issue))]
+pub async fn create_issue(
+    pool: &SqlitePool,
+    issue: &NewIssue<'_>,
+    author_id: Uuid,
+) -> Result<Uuid, IssueError> {
+    validate_templates(
+        pool,
+        issue.title,
+        issue.content,
+        &issue.translations,
+        issue.layout,
+    )
+    .await?;
+    let issue_id = Uuid::new_v4();
+    let issue_id_str = issue_id.to_string();
+    let translations = serde_json::to_string(&issue.translations)
+        .context("Failed to serialize the issue translations.")?;
+    let audience = serde_json::to_string(&issue.audience)
+        .context("Failed to serialize the issue audience.")?;
+    let author_id = author_id.to_string();
+    let due = !issue.draft && is_due(issue.send_at);
+    let send_at = issue.send_at.map(format_timestamp);
+    // Issues due now go through the same path as scheduled ones
+    let status = if issue.draft {
+        IssueStatus::Draft
+    } else {
+        IssueStatus::Scheduled
+    }
+    .as_str();
+    let created_at = get_current_utc_timestamp();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (id, title, html_content, text_content, translations, audience,
+            author_id, status, created_at, send_at, layout)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        issue_id_str,
+        issue.title,
+        issue.content.html,
+        issue.content.text,
+        translations,
+        audience,
+        author_id,
+        status,
+        created_at,
+        send_at,
+        issue.layout,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the newsletter issue.")?;
+    if due {
+        enqueue_issue(&mut transaction, issue_id).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store an issue.")?;
+    if due {
+        // Nothing to wait for when the audience is empty
+        complete_issue_if_delivered(pool, issue_id).await?;
+    }
+    Ok(issue_id)
+}
+
+/// Replace the content, layout and send time of a draft, `false` if it
+/// is not a draft anymore
+#[tracing::instrument(name = "Update a draft issue", skip(pool, content))]
+pub async fn update_draft(
+    pool: &SqlitePool,
+    issue_id: Uuid,
+    title: &str,
+    content: &Content,
+    layout: Option<&str>,
+    send_at: Option<DateTime<Utc>>,
+) -> Result<bool, IssueError> {
+    let issue_id = issue_id.to_string();
+    let Some(issue) = sqlx::query!(
+        "SELECT translations FROM newsletter_issues WHERE id = $1",
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query the draft.")?
+    else {
+        return Ok(false);
+    };
+    let translations: BTreeMap<String, Translation> =
+        serde_json::from_str(&issue.translations)
+            .context("Invalid stored issue translations.")?;
+    let translations =
+        translations.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    validate_templates(pool, title, content, &translations, layout).await?;
+
+    let send_at = send_at.map(format_timestamp);
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $1, html_content = $2, text_content = $3, layout = $4,
+            send_at = $5
+        WHERE id = $6 AND status = 'draft'
+        "#,
+        title,
+        content.html,
+        content.text,
+        layout,
+        send_at,
+        issue_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the draft.")?;
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Schedule a draft, queueing it right away unless its send time is
+/// still ahead, `false` if it is not a draft anymore
+#[tracing::instrument(name = "Publish a draft issue", skip(pool))]
+pub async fn publish_draft(
+    pool: &SqlitePool,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let issue_id_str = issue_id.to_string();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    let Some(issue) = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET status = 'scheduled'
+        WHERE id = $1 AND status = 'draft'
+        RETURNING send_at
+        "#,
+        issue_id_str,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to schedule the draft.")?
+    else {
+        return Ok(false);
+    };
+    let send_at = issue
+        .send_at
+        .map(|t| {
+            NaiveDateTime::parse_from_str(&t, "%Y-%m-%d %H:%M:%S")
+                .map(|t| t.and_utc())
+        })
+        .transpose()
+        .context("Invalid stored send time.")?;
+    let due = is_due(send_at);
+    if due {
+        enqueue_issue(&mut transaction, issue_id).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a draft.")?;
+    if due {
+        complete_issue_if_delivered(pool, issue_id).await?;
+    }
+    Ok(true)
+}
+
+/// Queue a scheduled issue for every confirmed subscriber in its
+/// audience, `false` if it is not scheduled anymore
+async fn enqueue_issue(
+    transaction: &mut Transaction<'_, Sqlite>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let issue_id_str = issue_id.to_string();
+    let Some(issue) = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET status = 'publishing'
+        WHERE id = $1 AND status = 'scheduled'
+        RETURNING audience
+        "#,
+        issue_id_str,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to start publishing the issue.")?
+    else {
+        return Ok(false);
+    };
+    let audience: BTreeMap<String, String> =
+        serde_json::from_str(&issue.audience)
+            .context("Invalid stored issue audience.")?;
+
+    let recipients: Vec<String> = get_confirmed_subscribers(transaction)
+        .await
+        .context("Unable to query confirmed subscribers")?
+        .into_iter()
+        .filter(|subscriber| subscriber.in_audience(&audience))
+        .map(|subscriber| subscriber.id)
+        .collect();
+    enqueue_delivery_tasks(transaction, issue_id, &recipients)
+        .await
+        .context("Failed to enqueue the newsletter issue.")?;
+    tracing::info!(
+        recipients = recipients.len(),
+        "Newsletter issue queued for delivery."
+    );
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Get confirmed subscribers", skip_all)]
+async fn get_confirmed_subscribers(
+    transaction: &mut Transaction<'_, Sqlite>,
+) -> Result<Vec<ConfirmedSubscriber>, anyhow::Error> {
+    sqlx::query!(
+        r#"
+        SELECT s.id AS "id!",
+            (SELECT json_group_object(a.key, a.value)
+                FROM subscriber_attributes a
+                WHERE a.subscriber_id = s.id) AS "attributes!: String"
+        FROM subscriptions s WHERE s.status = 'confirmed'
+        "#
+    )
+    .fetch_all(&mut **transaction)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(ConfirmedSubscriber {
+            id: r.id,
+            attributes: serde_json::from_str(&r.attributes)
+                .context("Invalid stored subscriber attributes.")?,
+        })
+    })
+    .collect()
+}
+
+/// Queue every scheduled issue whose send time has passed, returning how
+/// many were
+#[tracing::instrument(name = "Enqueue due issues", skip_all)]
+pub async fn enqueue_due_issues(
+    pool: &SqlitePool,
+) -> Result<usize, anyhow::Error> {
+    let now = get_current_utc_timestamp();
+    let due = sqlx::query!(
+        r#"
+        SELECT id AS "id!" FROM newsletter_issues
+        WHERE status = 'scheduled' AND datetime(send_at) <= datetime($1)
+        ORDER BY send_at
+        "#,
+        now,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query due issues.")?;
+
+    let mut enqueued = 0;
+    for issue in due {
+        let issue_id = Uuid::parse_str(&issue.id)
+            .context("Failed to parse stored newsletter issue id.")?;
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Sqlite connection from the pool.")?;
+        // Cancelled in the meantime otherwise
+        if enqueue_issue(&mut transaction, issue_id).await? {
+            enqueued += 1;
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to queue an issue.")?;
+        complete_issue_if_delivered(pool, issue_id).await?;
+    }
+    Ok(enqueued)
+}
+
+/// Move a scheduled issue to another time, `false` if it is not
+/// scheduled anymore
+#[tracing::instrument(name = "Reschedule an issue", skip(pool))]
+pub async fn reschedule_issue(
+    pool: &SqlitePool,
+    issue_id: Uuid,
+    send_at: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let issue_id = issue_id.to_string();
+    let send_at = format_timestamp(send_at);
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET send_at = $1
+        WHERE id = $2 AND status = 'scheduled'
+        "#,
+        send_at,
+        issue_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reschedule the issue.")?;
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Call off a scheduled issue, `false` if it is not scheduled anymore
+#[tracing::instrument(name = "Cancel an issue", skip(pool))]
+pub async fn cancel_issue(
+    pool: &SqlitePool,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let issue_id = issue_id.to_string();
+    let status = IssueStatus::Cancelled.as_str();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET status = $1
+        WHERE id = $2 AND status = 'scheduled'
+        "#,
+        status,
+        issue_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to cancel the issue.")?;
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Periodically queue issues that are due, never returns
+pub async fn run_issue_scheduler(pool: SqlitePool, poll_interval: Duration) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + poll_interval,
+        poll_interval,
+    );
+    loop {
+        interval.tick().await;
+        match enqueue_due_issues(&pool).await {
+            Ok(0) => {}
+            Ok(enqueued) => tracing::info!(enqueued, "Queued due issues."),
+            Err(error) => {
+                tracing::error!(error = ?error, "Failed to queue due issues.")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_send_at;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn send_times_are_converted_to_utc() {
+        assert_ok_eq!(
+            parse_send_at("2026-10-20T09:30:00+02:00"),
+            Utc.with_ymd_and_hms(2026, 10, 20, 7, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn send_times_need_a_timezone() {
+        assert_err!(parse_send_at("2026-10-20T09:30:00"));
+    }
+}
@@ -0,0 +1,387 @@
+//! src/pruning.rs
+//!
+//! Periodic clean up of the subscriber base. Signups still pending past
+//! their maximum age are reminded once or deleted, confirmed subscribers
+//! who stopped opening issues are asked whether they want to stay, and
+//! unsubscribed if they do not answer in time. Every run is stored in
+//! `pruning_runs` for the admin area.
+
+use crate::domain::{
+    Locale, NewSubscriber, SignupSource, SubscriberEmail, SubscriberName,
+    SubscriberStatus,
+};
+use crate::email_client::EmailClient;
+use crate::i18n::{translate, translate_with};
+use crate::lifecycle::change_status;
+use crate::routes::{
+    generate_subscription_token, get_current_utc_timestamp,
+    send_confirmation_email, store_token,
+};
+use crate::settings::{PendingAction, PruningSettings};
+use anyhow::Context;
+use sqlx::SqlitePool;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// What a single run did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PruningReport {
+    pub pending_reminded: i64,
+    pub pending_deleted: i64,
+    pub reengagement_sent: i64,
+    pub suppressed: i64,
+    /// Emails that could not be sent, retried on the next run
+    pub failed: i64,
+}
+
+/// Run every pruning step once and store the report
+#[tracing::instrument(name = "Prune subscribers", skip_all)]
+pub async fn run_pruning(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+    settings: &PruningSettings,
+) -> Result<PruningReport, anyhow::Error> {
+    let started_at = get_current_utc_timestamp();
+    let mut report = PruningReport::default();
+
+    match settings.pending_action {
+        PendingAction::Remind => {
+            remind_stale_pending(
+                pool,
+                email_client,
+                base_url,
+                settings,
+                &mut report,
+            )
+            .await?
+        }
+        PendingAction::Delete => {
+            delete_stale_pending(pool, settings, &mut report).await?
+        }
+    }
+    // Those asked on a previous run go first, so a subscriber always
+    // gets the full grace period
+    suppress_unresponsive(pool, settings, &mut report).await?;
+    send_reengagement_emails(
+        pool,
+        email_client,
+        base_url,
+        settings,
+        &mut report,
+    )
+    .await?;
+
+    let finished_at = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        INSERT INTO pruning_runs
+            (started_at, finished_at, pending_reminded, pending_deleted,
+            reengagement_sent, suppressed, failed)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        started_at,
+        finished_at,
+        report.pending_reminded,
+        report.pending_deleted,
+        report.reengagement_sent,
+        report.suppressed,
+        report.failed,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the pruning report.")?;
+    Ok(report)
+}
+
+async fn remind_stale_pending(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+    settings: &PruningSettings,
+    report: &mut PruningReport,
+) -> Result<(), anyhow::Error> {
+    let now = get_current_utc_timestamp();
+    let stale = sqlx::query!(
+        r#"
+        SELECT id AS "id!", email, name, locale FROM subscriptions
+        WHERE status = 'pending_confirmation'
+            AND confirmation_reminder_sent_at IS NULL
+            AND datetime(subscribed_at, '+' || $1 || ' days')
+                <= datetime($2)
+        "#,
+        settings.pending_max_age_days,
+        now,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query stale pending subscribers.")?;
+
+    for subscriber in stale {
+        let parsed = (|| {
+            Ok::<_, String>(NewSubscriber {
+                email: SubscriberEmail::parse(subscriber.email)?,
+                name: SubscriberName::parse(subscriber.name)?,
+                source: SignupSource::default(),
+                locale: Locale::parse(&subscriber.locale)?,
+            })
+        })();
+        let new_subscriber = match parsed {
+            Ok(new_subscriber) => new_subscriber,
+            Err(error) => {
+                tracing::warn!(
+                    error,
+                    "Skipping a confirmation reminder, the stored details \
+                    are invalid."
+                );
+                continue;
+            }
+        };
+        let subscriber_id = Uuid::parse_str(&subscriber.id)
+            .context("Failed to parse stored subscriber id.")?;
+
+        let subscription_token = generate_subscription_token();
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Sqlite connection from the pool.")?;
+        store_token(&mut transaction, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store the reminder confirmation token.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store a token.")?;
+        if let Err(error) = send_confirmation_email(
+            email_client,
+            new_subscriber,
+            base_url,
+            &subscription_token,
+        )
+        .await
+        {
+            tracing::error!(
+                error = ?error,
+                "Failed to send a confirmation reminder, retrying on the \
+                next run."
+            );
+            report.failed += 1;
+            continue;
+        }
+        let sent_at = get_current_utc_timestamp();
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions SET confirmation_reminder_sent_at = $1
+            WHERE id = $2
+            "#,
+            sent_at,
+            subscriber.id,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record a confirmation reminder.")?;
+        report.pending_reminded += 1;
+    }
+    Ok(())
+}
+
+async fn delete_stale_pending(
+    pool: &SqlitePool,
+    settings: &PruningSettings,
+    report: &mut PruningReport,
+) -> Result<(), anyhow::Error> {
+    let now = get_current_utc_timestamp();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    // Never confirmed, so nothing about them is worth keeping, not even
+    // their status history
+    sqlx::query!(
+        r#"
+        DELETE FROM subscriber_status_history WHERE subscriber_id IN (
+            SELECT id FROM subscriptions
+            WHERE status = 'pending_confirmation'
+                AND datetime(subscribed_at, '+' || $1 || ' days')
+                    <= datetime($2))
+        "#,
+        settings.pending_max_age_days,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the status history of stale signups.")?;
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE status = 'pending_confirmation'
+            AND datetime(subscribed_at, '+' || $1 || ' days') <= datetime($2)
+        "#,
+        settings.pending_max_age_days,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete stale pending subscribers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete signups.")?;
+    report.pending_deleted += deleted.rows_affected() as i64;
+    Ok(())
+}
+
+async fn send_reengagement_emails(
+    pool: &SqlitePool,
+    email_client: &EmailClient,
+    base_url: &str,
+    settings: &PruningSettings,
+    report: &mut PruningReport,
+) -> Result<(), anyhow::Error> {
+    let inactive = sqlx::query!(
+        r#"
+        SELECT id AS "id!", email, locale,
+            preferences_token AS "preferences_token!"
+        FROM subscriptions
+        WHERE status = 'confirmed'
+            AND issues_since_engagement >= $1
+            AND reengagement_sent_at IS NULL
+            AND preferences_token IS NOT NULL
+        "#,
+        settings.inactive_after_issues,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query inactive subscribers.")?;
+
+    let days = settings.reengagement_grace_days.to_string();
+    for subscriber in inactive {
+        let (recipient, locale) = match SubscriberEmail::parse(subscriber.email)
+            .and_then(|e| Ok((e, Locale::parse(&subscriber.locale)?)))
+        {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                tracing::warn!(
+                    error,
+                    "Skipping a re-engagement email, the stored details \
+                    are invalid."
+                );
+                continue;
+            }
+        };
+        let link = format!(
+            "{}engagement/keep?token={}",
+            base_url,
+            urlencoding::encode(&subscriber.preferences_token)
+        );
+        let args = [("link", link.as_str()), ("days", days.as_str())];
+        if let Err(error) = email_client
+            .send_email(
+                &recipient,
+                translate(locale, "reengagement_email.subject"),
+                &translate_with(locale, "reengagement_email.html", &args),
+                &translate_with(locale, "reengagement_email.text", &args),
+            )
+            .await
+        {
+            tracing::error!(
+                error = ?error,
+                "Failed to send a re-engagement email, retrying on the next \
+                run."
+            );
+            report.failed += 1;
+            continue;
+        }
+        let sent_at = get_current_utc_timestamp();
+        sqlx::query!(
+            r#"UPDATE subscriptions SET reengagement_sent_at = $1
+            WHERE id = $2"#,
+            sent_at,
+            subscriber.id,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record a re-engagement email.")?;
+        report.reengagement_sent += 1;
+    }
+    Ok(())
+}
+
+async fn suppress_unresponsive(
+    pool: &SqlitePool,
+    settings: &PruningSettings,
+    report: &mut PruningReport,
+) -> Result<(), anyhow::Error> {
+    let now = get_current_utc_timestamp();
+    let unresponsive = sqlx::query!(
+        r#"
+        SELECT id AS "id!" FROM subscriptions
+        WHERE status = 'confirmed'
+            AND reengagement_sent_at IS NOT NULL
+            AND datetime(reengagement_sent_at, '+' || $1 || ' days')
+                <= datetime($2)
+        "#,
+        settings.reengagement_grace_days,
+        now,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query unresponsive subscribers.")?;
+
+    for subscriber in unresponsive {
+        let subscriber_id = Uuid::parse_str(&subscriber.id)
+            .context("Failed to parse stored subscriber id.")?;
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Sqlite connection from the pool.")?;
+        change_status(
+            &mut transaction,
+            subscriber_id,
+            SubscriberStatus::Unsubscribed,
+        )
+        .await
+        .context("Failed to unsubscribe an unresponsive subscriber.")?;
+        // Start afresh if they ever opt back in
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET issues_since_engagement = 0, reengagement_sent_at = NULL
+            WHERE id = $1
+            "#,
+            subscriber.id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to reset subscriber inactivity.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to unsubscribe.")?;
+        report.suppressed += 1;
+    }
+    Ok(())
+}
+
+/// Periodically prune subscribers, never returns
+pub async fn run_pruning_scheduler(
+    pool: SqlitePool,
+    email_client: EmailClient,
+    base_url: String,
+    settings: PruningSettings,
+) {
+    let poll_interval: Duration = settings.poll_interval();
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + poll_interval,
+        poll_interval,
+    );
+    loop {
+        interval.tick().await;
+        match run_pruning(&pool, &email_client, &base_url, &settings).await {
+            Ok(report) => tracing::info!(?report, "Pruned subscribers."),
+            Err(error) => {
+                tracing::error!(error = ?error, "Failed to prune subscribers.")
+            }
+        }
+    }
+}
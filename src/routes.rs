@@ -18,21 +18,33 @@
 
 //! src/routes.rs
 
+mod admin;
+mod data_export;
+mod engagement;
 mod health_check;
 mod home;
 mod login;
+mod metrics;
 mod newsletters;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
+mod web_view;
 
+pub use admin::*;
+pub use data_export::*;
+pub use engagement::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use preferences::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use web_view::*;
 
-fn error_chain_fmt(
+pub(crate) fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
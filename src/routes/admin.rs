@@ -0,0 +1,115 @@
+//! src/routes/admin.rs
+//!
+//! Admin area. Every route here sits behind
+//! `authentication::require_admin`.
+
+mod attributes;
+mod dashboard;
+mod domains;
+mod drafts;
+mod drip;
+mod import;
+mod issues;
+mod layouts;
+mod lists;
+mod pruning;
+mod sources;
+mod subscriber_export;
+mod subscribers;
+mod topics;
+
+pub use attributes::*;
+pub use dashboard::*;
+pub use domains::*;
+pub use drafts::*;
+pub use drip::*;
+pub use import::*;
+pub use issues::*;
+pub use layouts::*;
+pub use lists::*;
+pub use pruning::*;
+pub use sources::*;
+pub use subscriber_export::*;
+pub use subscribers::*;
+pub use topics::*;
+
+use crate::newsletter_issues::IssueError;
+use crate::routes::error_chain_fmt;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Not found.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<IssueError> for AdminError {
+    fn from(e: IssueError) -> Self {
+        match e {
+            IssueError::ValidationError(e) => AdminError::ValidationError(e),
+            IssueError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        }
+    }
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        match self {
+            AdminError::ValidationError(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            AdminError::NotFound => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            AdminError::UnexpectedError(_) => {
+                tracing::error!(error = ?self, "Admin error");
+                (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+        }
+    }
+}
+
+/// Slugs appear in URLs, such as `/subscribe/:list`
+fn parse_slug(s: &str) -> Result<String, AdminError> {
+    let slug = s.trim();
+    let is_valid = !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !is_valid {
+        return Err(AdminError::ValidationError(format!(
+            "\"{slug}\" is not a valid slug, use lowercase letters, digits \
+            and dashes."
+        )));
+    }
+    Ok(slug.to_string())
+}
+
+/// Wrap the body of an admin page with the shared layout
+fn admin_page(title: &str, body: &str) -> Html<String> {
+    Html::from(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin - {title}</title>
+  </head>
+  <body>
+    <p><a href="/admin">Admin dashboard</a></p>
+    <h1>{title}</h1>
+    {body}
+  </body>
+</html>"#
+    ))
+}
@@ -0,0 +1,172 @@
+use crate::attributes::get_attribute_schema;
+use crate::authentication::UserId;
+use crate::domain::{AttributeDefinition, AttributeKind};
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::get_current_utc_timestamp;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+
+#[derive(serde::Deserialize)]
+pub struct AttributeForm {
+    key: String,
+    label: String,
+    kind: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeleteAttributeForm {
+    key: String,
+}
+
+#[tracing::instrument(name = "Show subscriber attributes", skip(pool))]
+pub async fn admin_attributes(
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<impl IntoResponse, AdminError> {
+    let attributes = sqlx::query!(
+        r#"
+        SELECT d.key AS "key!", d.label, d.kind, d.created_at,
+            (SELECT count(*) FROM subscriber_attributes a
+                WHERE a.key = d.key) AS "subscribers!: i64"
+        FROM attribute_definitions d ORDER BY d.key
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query attribute definitions.")?;
+
+    let rows: String = attributes
+        .iter()
+        .map(|a| {
+            let key = encode_minimal(&a.key);
+            format!(
+                r#"
+      <tr>
+        <td><code>{key}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>
+        <td>
+          <form method="post" action="/admin/attributes/delete">
+            <input type="hidden" name="key" value="{key}">
+            <button type="submit">Remove</button>
+          </form>
+        </td>
+      </tr>"#,
+                encode_minimal(&a.label),
+                encode_minimal(&a.kind),
+                a.subscribers,
+                encode_minimal(&a.created_at),
+            )
+        })
+        .collect();
+    let kinds: String = AttributeKind::ALL
+        .iter()
+        .map(|k| format!(r#"<option value="{0}">{0}</option>"#, k.as_str()))
+        .collect();
+
+    let body = format!(
+        r#"<p>Attributes can be set through the JSON subscribe API and CSV
+    import, and used in newsletters as <code>{{{{ attributes.key }}}}</code>
+    or in an audience filter. Removing one deletes its values.</p>
+    <table>
+      <tr><th>Key</th><th>Label</th><th>Type</th><th>Subscribers</th><th>Added</th><th></th></tr>{rows}
+    </table>
+    <h2>Add or rename an attribute</h2>
+    <form method="post" action="/admin/attributes">
+      <label>Key <input type="text" name="key"></label>
+      <label>Label <input type="text" name="label"></label>
+      <label>Type <select name="kind">{kinds}</select></label>
+      <button type="submit">Save</button>
+    </form>"#
+    );
+    Ok((StatusCode::OK, admin_page("Subscriber attributes", &body)))
+}
+
+#[tracing::instrument(
+    name = "Save subscriber attribute",
+    skip(pool, form),
+    fields(key = %form.key, kind = %form.kind)
+)]
+pub async fn save_attribute(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<AttributeForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let key = AttributeDefinition::parse_key(&form.key)
+        .map_err(AdminError::ValidationError)?;
+    let kind = AttributeKind::parse(&form.kind)
+        .map_err(AdminError::ValidationError)?
+        .as_str();
+    let label = match form.label.trim() {
+        "" => key.clone(),
+        label => label.to_string(),
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    // Stored values were validated against the current type
+    if let Some(existing) =
+        get_attribute_schema(&mut *transaction).await?.get(&key)
+    {
+        let in_use = sqlx::query!(
+            r#"SELECT count(*) AS "count!: i64" FROM subscriber_attributes
+            WHERE key = $1"#,
+            key,
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to count attribute values.")?
+        .count
+            > 0;
+        if existing.kind.as_str() != kind && in_use {
+            return Err(AdminError::ValidationError(format!(
+                "{} already has values, its type cannot be changed.",
+                key
+            )));
+        }
+    }
+
+    let created_at = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        INSERT INTO attribute_definitions (key, label, kind, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (key) DO UPDATE SET label = $2, kind = $3
+        "#,
+        key,
+        label,
+        kind,
+        created_at,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save attribute definition.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to save an attribute.")?;
+
+    Ok(Redirect::to("/admin/attributes"))
+}
+
+#[tracing::instrument(
+    name = "Remove subscriber attribute",
+    skip(pool, form),
+    fields(key = %form.key)
+)]
+pub async fn delete_attribute(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<DeleteAttributeForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let key = form.key.trim();
+    sqlx::query!(r#"DELETE FROM attribute_definitions WHERE key = $1"#, key)
+        .execute(&pool)
+        .await
+        .context("Failed to remove attribute definition.")?;
+
+    Ok(Redirect::to("/admin/attributes"))
+}
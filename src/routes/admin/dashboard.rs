@@ -0,0 +1,27 @@
+use crate::authentication::UserId;
+use crate::routes::admin::admin_page;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Extension;
+
+pub async fn admin_dashboard(
+    Extension(user_id): Extension<UserId>,
+) -> impl IntoResponse {
+    let body = format!(
+        r#"<p>Logged in as {user_id}.</p>
+    <ul>
+      <li><a href="/admin/subscribers">Subscribers</a></li>
+      <li><a href="/admin/issues">Newsletter issues</a></li>
+      <li><a href="/admin/layouts">Newsletter layouts</a></li>
+      <li><a href="/admin/import">Import subscribers</a></li>
+      <li><a href="/admin/lists">Lists and signup pages</a></li>
+      <li><a href="/admin/topics">Topics</a></li>
+      <li><a href="/admin/attributes">Subscriber attributes</a></li>
+      <li><a href="/admin/sources">Signup sources</a></li>
+      <li><a href="/admin/drip">Welcome sequence</a></li>
+      <li><a href="/admin/pruning">Inactive subscriber pruning</a></li>
+      <li><a href="/admin/domains">Email domain rules</a></li>
+    </ul>"#
+    );
+    (StatusCode::OK, admin_page("Dashboard", &body))
+}
@@ -0,0 +1,155 @@
+use crate::authentication::UserId;
+use crate::domain_policy::{normalize_domain, DomainPolicy, DomainRule};
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::get_current_utc_timestamp;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+#[derive(serde::Deserialize)]
+pub struct DomainRuleForm {
+    domain: String,
+    rule: String,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeleteDomainRuleForm {
+    domain: String,
+}
+
+#[tracing::instrument(name = "Show email domain rules", skip(pool, policy))]
+pub async fn admin_domains(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(policy): Extension<Arc<DomainPolicy>>,
+) -> Result<impl IntoResponse, AdminError> {
+    let rules = sqlx::query!(
+        r#"SELECT domain AS "domain!", rule, note, created_at
+        FROM email_domain_rules ORDER BY domain"#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query email domain rules.")?;
+
+    let rows: String = rules
+        .iter()
+        .map(|r| {
+            let domain = encode_minimal(&r.domain);
+            format!(
+                r#"
+      <tr>
+        <td>{domain}</td><td>{}</td><td>{}</td><td>{}</td>
+        <td>
+          <form method="post" action="/admin/domains/delete">
+            <input type="hidden" name="domain" value="{domain}">
+            <button type="submit">Remove</button>
+          </form>
+        </td>
+      </tr>"#,
+                encode_minimal(&r.rule),
+                encode_minimal(&r.note),
+                encode_minimal(&r.created_at),
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<table>
+      <tr><th>Domain</th><th>Rule</th><th>Note</th><th>Added</th><th></th></tr>{rows}
+    </table>
+    <h2>Add a rule</h2>
+    <form method="post" action="/admin/domains">
+      <label>Domain <input type="text" name="domain"></label>
+      <label>Rule
+        <select name="rule">
+          <option value="block">block</option>
+          <option value="allow">allow</option>
+        </select>
+      </label>
+      <label>Note <input type="text" name="note"></label>
+      <button type="submit">Save</button>
+    </form>
+    <h2>Disposable domains</h2>
+    <p>{} disposable domains are currently blocked.</p>
+    <form method="post" action="/admin/domains/reload">
+      <button type="submit">Reload disposable domains</button>
+    </form>"#,
+        policy.disposable_count()
+    );
+    Ok((StatusCode::OK, admin_page("Email domain rules", &body)))
+}
+
+#[tracing::instrument(
+    name = "Save email domain rule",
+    skip(pool, form),
+    fields(domain = %form.domain, rule = %form.rule)
+)]
+pub async fn save_domain_rule(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<DomainRuleForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let domain =
+        normalize_domain(&form.domain).map_err(AdminError::ValidationError)?;
+    let rule = DomainRule::parse(&form.rule)
+        .map_err(AdminError::ValidationError)?
+        .as_str();
+    let created_at = get_current_utc_timestamp();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_domain_rules (domain, rule, note, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (domain) DO UPDATE SET rule = $2, note = $3
+        "#,
+        domain,
+        rule,
+        form.note,
+        created_at,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to save email domain rule.")?;
+
+    Ok(Redirect::to("/admin/domains"))
+}
+
+#[tracing::instrument(
+    name = "Remove email domain rule",
+    skip(pool, form),
+    fields(domain = %form.domain)
+)]
+pub async fn delete_domain_rule(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<DeleteDomainRuleForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let domain =
+        normalize_domain(&form.domain).map_err(AdminError::ValidationError)?;
+    sqlx::query!(
+        r#"DELETE FROM email_domain_rules WHERE domain = $1"#,
+        domain
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to remove email domain rule.")?;
+
+    Ok(Redirect::to("/admin/domains"))
+}
+
+#[tracing::instrument(name = "Reload disposable domains", skip(policy))]
+pub async fn reload_disposable_domains(
+    Extension(policy): Extension<Arc<DomainPolicy>>,
+    Extension(user_id): Extension<UserId>,
+) -> Result<impl IntoResponse, AdminError> {
+    let count = policy
+        .reload()
+        .context("Failed to reload disposable domains.")?;
+    tracing::info!(count, "Reloaded disposable domains.");
+    Ok(Redirect::to("/admin/domains"))
+}
@@ -0,0 +1,258 @@
+use crate::attributes::get_attribute_schema;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::newsletter_issues::{publish_draft, update_draft};
+use crate::routes::admin::issues::parse_form_send_at;
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::Content;
+use crate::templates::{
+    get_layout, IssueTemplate, RenderedIssue, TemplateContext,
+};
+use anyhow::Context;
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Most addresses a single test send goes to
+const MAX_TEST_RECIPIENTS: usize = 20;
+
+#[derive(serde::Deserialize)]
+pub struct DraftForm {
+    title: String,
+    html_content: String,
+    text_content: String,
+    #[serde(default)]
+    send_at: String,
+    /// Empty for no layout
+    #[serde(default)]
+    layout: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendForm {
+    /// Separated by commas, spaces or new lines
+    recipients: String,
+}
+
+/// An issue rendered for `TemplateContext::sample`
+async fn render_sample(
+    pool: &SqlitePool,
+    issue_id: &Uuid,
+) -> Result<RenderedIssue, AdminError> {
+    let issue_id = issue_id.to_string();
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, html_content, text_content, layout
+        FROM newsletter_issues WHERE id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query the newsletter issue.")?
+    .ok_or(AdminError::NotFound)?;
+    let layout = match &issue.layout {
+        Some(name) => get_layout(pool, name).await?,
+        None => None,
+    };
+    let template = IssueTemplate {
+        title: issue.title,
+        html: issue.html_content,
+        text: issue.text_content,
+        layout,
+    };
+    let schema = get_attribute_schema(pool).await?;
+    template
+        .render(&TemplateContext::sample(&schema))
+        .map_err(AdminError::ValidationError)
+}
+
+/// Forms to edit, test-send and publish a draft, shown on its page
+pub(super) fn draft_forms(
+    issue_id: &str,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+    send_at: Option<&str>,
+    layout_select: &str,
+) -> String {
+    // Prefilled the way it is typed in, the stored time is in UTC
+    let send_at = send_at
+        .map(|t| format!("{}Z", t.replace(' ', "T")))
+        .unwrap_or_default();
+    format!(
+        r#"
+    <h2>Edit draft</h2>
+    <form method="post" action="/admin/issues/{0}/draft">
+      <label>Title <input type="text" name="title" value="{1}"></label><br>
+      <label>HTML<br><textarea name="html_content" rows="8" cols="60">{2}</textarea></label><br>
+      <label>Plain text<br><textarea name="text_content" rows="8" cols="60">{3}</textarea></label><br>
+      {5}<br>
+      <label>Send at <input type="text" name="send_at" value="{4}"></label><br>
+      <button type="submit">Save draft</button>
+    </form>
+    <h2>Test send</h2>
+    <form method="post" action="/admin/issues/{0}/test">
+      <label>Send to<br><textarea name="recipients" rows="3" cols="60"></textarea></label><br>
+      <button type="submit">Send test</button>
+    </form>
+    <h2>Publish</h2>
+    <form method="post" action="/admin/issues/{0}/publish">
+      <button type="submit">Publish draft</button>
+    </form>"#,
+        encode_minimal(issue_id),
+        encode_minimal(title),
+        encode_minimal(html_content),
+        encode_minimal(text_content),
+        encode_minimal(&send_at),
+        layout_select,
+    )
+}
+
+#[tracing::instrument(
+    name = "Save a draft issue",
+    skip(pool, form),
+    fields(title = %form.title)
+)]
+pub async fn save_draft(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+    Form(form): Form<DraftForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let title = form.title.trim();
+    if title.is_empty()
+        || form.html_content.trim().is_empty()
+        || form.text_content.trim().is_empty()
+    {
+        return Err(AdminError::ValidationError(
+            "An issue needs a title, an HTML and a plain text body.".into(),
+        ));
+    }
+    let send_at = parse_form_send_at(&form.send_at)?;
+    let content = Content {
+        html: form.html_content,
+        text: form.text_content,
+    };
+    let layout = Some(form.layout.as_str()).filter(|l| !l.is_empty());
+    if !update_draft(&pool, issue_id, title, &content, layout, send_at).await? {
+        return Err(AdminError::ValidationError(
+            "Only drafts can be edited.".into(),
+        ));
+    }
+    Ok(Redirect::to(&format!("/admin/issues/{issue_id}")))
+}
+
+#[tracing::instrument(name = "Publish a draft", skip(pool))]
+pub async fn publish_draft_issue(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    if !publish_draft(&pool, issue_id).await? {
+        return Err(AdminError::ValidationError(
+            "Only drafts can be published.".into(),
+        ));
+    }
+    Ok(Redirect::to(&format!("/admin/issues/{issue_id}")))
+}
+
+/// The HTML body of an issue as a subscriber would see it
+#[tracing::instrument(name = "Preview an issue as HTML", skip(pool))]
+pub async fn preview_issue_html(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let html = render_sample(&pool, &issue_id).await?.html;
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            // The issue's own scripts must not run with admin access
+            (header::CONTENT_SECURITY_POLICY, "sandbox"),
+        ],
+        html,
+    ))
+}
+
+#[tracing::instrument(name = "Preview an issue as text", skip(pool))]
+pub async fn preview_issue_text(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let text = render_sample(&pool, &issue_id).await?.text;
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        text,
+    ))
+}
+
+/// Send an issue to the given addresses only, subscribers and the
+/// delivery queue are left alone
+#[tracing::instrument(
+    name = "Test send an issue",
+    skip(pool, email_client, form)
+)]
+pub async fn test_send_issue(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Path(issue_id): Path<Uuid>,
+    Form(form): Form<TestSendForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let recipients = form
+        .recipients
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| SubscriberEmail::parse(s.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AdminError::ValidationError)?;
+    if recipients.is_empty() {
+        return Err(AdminError::ValidationError(
+            "Give at least one address to send the test to.".into(),
+        ));
+    }
+    if recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(AdminError::ValidationError(format!(
+            "A test can be sent to at most {} addresses.",
+            MAX_TEST_RECIPIENTS
+        )));
+    }
+    let issue = render_sample(&pool, &issue_id).await?;
+    let subject = format!("[Test] {}", issue.title);
+
+    let mut rows = String::new();
+    for email in &recipients {
+        let outcome = match email_client
+            .send_email(email, &subject, &issue.html, &issue.text)
+            .await
+        {
+            Ok(()) => "Sent".to_string(),
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Failed to send a test issue."
+                );
+                format!("Failed: {error}")
+            }
+        };
+        rows.push_str(&format!(
+            r#"
+      <tr><td>{}</td><td>{}</td></tr>"#,
+            encode_minimal(email.as_ref()),
+            encode_minimal(&outcome),
+        ));
+    }
+
+    let body = format!(
+        r#"<p><a href="/admin/issues/{}">Back to the issue</a></p>
+    <table>
+      <tr><th>Address</th><th>Outcome</th></tr>{rows}
+    </table>"#,
+        encode_minimal(&issue_id.to_string()),
+    );
+    Ok((StatusCode::OK, admin_page("Test send", &body)))
+}
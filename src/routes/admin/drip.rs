@@ -0,0 +1,161 @@
+use crate::authentication::UserId;
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::get_current_utc_timestamp;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+
+#[derive(serde::Deserialize)]
+pub struct DripStepForm {
+    delay_days: i64,
+    subject: String,
+    html_content: String,
+    text_content: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeleteDripStepForm {
+    delay_days: i64,
+}
+
+#[tracing::instrument(name = "Show drip sequence", skip(pool))]
+pub async fn admin_drip(
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<impl IntoResponse, AdminError> {
+    let steps = sqlx::query!(
+        r#"
+        SELECT st.delay_days AS "delay_days!: i64", st.subject,
+            (SELECT count(*) FROM drip_enrollments e
+                WHERE e.last_step_delay >= st.delay_days) AS "sent!: i64"
+        FROM drip_steps st ORDER BY st.delay_days
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query drip steps.")?;
+    let enrollments = sqlx::query!(
+        r#"
+        SELECT count(*) AS "total!: i64",
+            count(stopped_at) AS "stopped!: i64"
+        FROM drip_enrollments
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .context("Failed to count drip enrollments.")?;
+
+    let rows: String = steps
+        .iter()
+        .map(|s| {
+            format!(
+                r#"
+      <tr>
+        <td>Day {0}</td><td>{1}</td><td>{2}</td>
+        <td>
+          <form method="post" action="/admin/drip/delete">
+            <input type="hidden" name="delay_days" value="{0}">
+            <button type="submit">Remove</button>
+          </form>
+        </td>
+      </tr>"#,
+                s.delay_days,
+                encode_minimal(&s.subject),
+                s.sent,
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<p>Subscribers enter the sequence when they confirm and receive
+    each step once its delay has passed. Unsubscribing stops it.</p>
+    <p>Single opt-in subscribers who are sent the welcome email skip the
+    day 0 step.</p>
+    <p>{} subscribers enrolled, {} stopped.</p>
+    <table>
+      <tr><th>Send after</th><th>Subject</th><th>Sent</th><th></th></tr>{rows}
+    </table>
+    <h2>Add or replace a step</h2>
+    <form method="post" action="/admin/drip">
+      <label>Days after confirming
+        <input type="number" name="delay_days" min="0" value="0">
+      </label><br>
+      <label>Subject <input type="text" name="subject"></label><br>
+      <label>HTML<br><textarea name="html_content" rows="8" cols="60"></textarea></label><br>
+      <label>Plain text<br><textarea name="text_content" rows="8" cols="60"></textarea></label><br>
+      <button type="submit">Save</button>
+    </form>"#,
+        enrollments.total, enrollments.stopped,
+    );
+    Ok((StatusCode::OK, admin_page("Welcome sequence", &body)))
+}
+
+#[tracing::instrument(
+    name = "Save drip step",
+    skip(pool, form),
+    fields(delay_days = %form.delay_days)
+)]
+pub async fn save_drip_step(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<DripStepForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    if form.delay_days < 0 {
+        return Err(AdminError::ValidationError(
+            "The delay cannot be negative.".into(),
+        ));
+    }
+    let subject = form.subject.trim();
+    if subject.is_empty()
+        || form.html_content.trim().is_empty()
+        || form.text_content.trim().is_empty()
+    {
+        return Err(AdminError::ValidationError(
+            "A step needs a subject, an HTML and a plain text body.".into(),
+        ));
+    }
+
+    let created_at = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        INSERT INTO drip_steps
+            (delay_days, subject, html_content, text_content, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (delay_days) DO UPDATE
+        SET subject = $2, html_content = $3, text_content = $4
+        "#,
+        form.delay_days,
+        subject,
+        form.html_content,
+        form.text_content,
+        created_at,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to save drip step.")?;
+
+    Ok(Redirect::to("/admin/drip"))
+}
+
+#[tracing::instrument(
+    name = "Remove drip step",
+    skip(pool, form),
+    fields(delay_days = %form.delay_days)
+)]
+pub async fn delete_drip_step(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<DeleteDripStepForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    sqlx::query!(
+        r#"DELETE FROM drip_steps WHERE delay_days = $1"#,
+        form.delay_days
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to remove drip step.")?;
+
+    Ok(Redirect::to("/admin/drip"))
+}
@@ -0,0 +1,150 @@
+use crate::authentication::UserId;
+use crate::email_client::EmailClient;
+use crate::import::{
+    import_subscribers, ImportError, ImportMode, ImportOptions, ImportReport,
+};
+use crate::routes::admin::{admin_page, AdminError};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use axum::extract::Multipart;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Extension;
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+const IMPORT_FORM: &str = r#"<form method="post" enctype="multipart/form-data">
+      <label>CSV file <input type="file" name="file" accept=".csv,text/csv"></label><br>
+      <label>Name column <input type="text" name="name_column" value="name"></label><br>
+      <label>Email column <input type="text" name="email_column" value="email"></label><br>
+      <label>Attribute columns, one <code>column=attribute</code> per line<br>
+        <textarea name="attribute_columns" rows="3"></textarea>
+      </label><br>
+      <label>Mode
+        <select name="mode">
+          <option value="dry_run">Dry run</option>
+          <option value="pending">Pending, send confirmations</option>
+          <option value="confirmed">Confirmed</option>
+        </select>
+      </label><br>
+      <label>Consent note, required when importing as confirmed
+        <input type="text" name="consent_note">
+      </label><br>
+      <button type="submit">Import</button>
+    </form>"#;
+
+pub async fn admin_import_form() -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        admin_page("Import subscribers", IMPORT_FORM),
+    )
+}
+
+impl From<ImportError> for AdminError {
+    fn from(e: ImportError) -> Self {
+        match e {
+            ImportError::ValidationError(e) => AdminError::ValidationError(e),
+            ImportError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Import subscribers from an upload",
+    skip(pool, email_client, base_url, secret, multipart)
+)]
+pub async fn admin_import(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    Extension(secret): Extension<HmacSecret>,
+    Extension(user_id): Extension<UserId>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AdminError> {
+    let mut csv = None;
+    let mut options = ImportOptions {
+        mode: ImportMode::DryRun,
+        name_column: "name".into(),
+        email_column: "email".into(),
+        attribute_columns: vec![],
+        consent_note: None,
+    };
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AdminError::ValidationError(e.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            let bytes = field
+                .bytes()
+                .await
+                .map_err(|e| AdminError::ValidationError(e.body_text()))?;
+            csv = Some(bytes);
+            continue;
+        }
+        let value = field
+            .text()
+            .await
+            .map_err(|e| AdminError::ValidationError(e.body_text()))?;
+        match name.as_str() {
+            "mode" => {
+                options.mode = ImportMode::parse(&value)
+                    .map_err(AdminError::ValidationError)?
+            }
+            "name_column" => options.name_column = value,
+            "email_column" => options.email_column = value,
+            "attribute_columns" => {
+                for line in value.lines().filter(|l| !l.trim().is_empty()) {
+                    options.attribute_columns.push(
+                        ImportOptions::parse_attribute_column(line)
+                            .map_err(AdminError::ValidationError)?,
+                    );
+                }
+            }
+            "consent_note" => options.consent_note = Some(value),
+            _ => {}
+        }
+    }
+    let csv = csv.ok_or_else(|| {
+        AdminError::ValidationError("A CSV file must be uploaded.".into())
+    })?;
+
+    let report = import_subscribers(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &secret,
+        csv.as_ref(),
+        &options,
+    )
+    .await?;
+    let body = report_html(&report, options.mode);
+    Ok((StatusCode::OK, admin_page("Import report", &body)))
+}
+
+fn report_html(report: &ImportReport, mode: ImportMode) -> String {
+    let verb = if mode == ImportMode::DryRun {
+        "Would import"
+    } else {
+        "Imported"
+    };
+    let rows: String = report
+        .errors
+        .iter()
+        .map(|e| {
+            format!(
+                "\n      <tr><td>{}</td><td>{}</td></tr>",
+                e.line,
+                encode_minimal(&e.message)
+            )
+        })
+        .collect();
+    format!(
+        r#"<p>{verb} {} of {} rows.</p>
+    <table>
+      <tr><th>Line</th><th>Error</th></tr>{rows}
+    </table>"#,
+        report.imported, report.rows
+    )
+}
@@ -0,0 +1,331 @@
+use crate::authentication::UserId;
+use crate::domain::{DeliveryStatus, IssueStatus};
+use crate::newsletter_issues::{
+    cancel_issue, create_issue, parse_send_at, reschedule_issue, NewIssue,
+};
+use crate::routes::admin::drafts::draft_forms;
+use crate::routes::admin::layouts::layout_select;
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::{Content, Translation};
+use anyhow::Context;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct IssueForm {
+    title: String,
+    html_content: String,
+    text_content: String,
+    /// Sent straight away when left empty
+    #[serde(default)]
+    send_at: String,
+    /// Set by the "Save draft" button
+    #[serde(default)]
+    draft: bool,
+    /// Empty for no layout
+    #[serde(default)]
+    layout: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleForm {
+    send_at: String,
+}
+
+pub(super) fn parse_form_send_at(
+    s: &str,
+) -> Result<Option<DateTime<Utc>>, AdminError> {
+    if s.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_send_at(s)
+        .map(Some)
+        .map_err(AdminError::ValidationError)
+}
+
+#[tracing::instrument(name = "Show newsletter issues", skip(pool))]
+pub async fn admin_issues(
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<impl IntoResponse, AdminError> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT i.id AS "id!", i.title, i.status, i.created_at, i.send_at,
+            i.published_at, u.username AS "author?"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        ORDER BY i.created_at DESC
+        LIMIT 100
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query newsletter issues.")?;
+
+    let rows: String = issues
+        .iter()
+        .map(|i| {
+            format!(
+                r#"
+      <tr>
+        <td><a href="/admin/issues/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>
+      </tr>"#,
+                encode_minimal(&i.id),
+                encode_minimal(&i.title),
+                encode_minimal(&i.status),
+                encode_minimal(i.author.as_deref().unwrap_or_default()),
+                encode_minimal(&i.created_at),
+                encode_minimal(i.send_at.as_deref().unwrap_or_default()),
+                encode_minimal(i.published_at.as_deref().unwrap_or_default()),
+            )
+        })
+        .collect();
+
+    let layouts = layout_select(&pool, None).await?;
+    let body = format!(
+        r#"<table>
+      <tr>
+        <th>Title</th><th>Status</th><th>Author</th><th>Created</th>
+        <th>Scheduled for (UTC)</th><th>Published</th>
+      </tr>{rows}
+    </table>
+    <h2>New issue</h2>
+    <p>Goes to every confirmed subscriber. Leave the send time empty to
+    send it straight away, or give a date, time and timezone such as
+    2026-10-20T09:00:00+02:00. Drafts are kept until published, to be
+    edited, previewed and test-sent first.</p>
+    <form method="post" action="/admin/issues">
+      <label>Title <input type="text" name="title"></label><br>
+      <label>HTML<br><textarea name="html_content" rows="8" cols="60"></textarea></label><br>
+      <label>Plain text<br><textarea name="text_content" rows="8" cols="60"></textarea></label><br>
+      {layouts}<br>
+      <label>Send at <input type="text" name="send_at"></label><br>
+      <button type="submit" name="draft" value="true">Save draft</button>
+      <button type="submit">Send</button>
+    </form>"#
+    );
+    Ok((StatusCode::OK, admin_page("Newsletter issues", &body)))
+}
+
+#[tracing::instrument(name = "Show a newsletter issue", skip(pool))]
+pub async fn admin_issue(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let issue_id = issue_id.to_string();
+    let issue = sqlx::query!(
+        r#"
+        SELECT i.title, i.html_content, i.text_content, i.translations,
+            i.audience, i.status, i.created_at, i.send_at, i.published_at,
+            i.layout, u.username AS "author?"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(&pool)
+    .await
+    .context("Failed to query the newsletter issue.")?
+    .ok_or(AdminError::NotFound)?;
+
+    let status =
+        IssueStatus::parse(&issue.status).map_err(|e| anyhow::anyhow!(e))?;
+    let audience: BTreeMap<String, String> =
+        serde_json::from_str(&issue.audience)
+            .context("Invalid stored issue audience.")?;
+    let audience = if audience.is_empty() {
+        "Every confirmed subscriber".to_string()
+    } else {
+        audience
+            .iter()
+            .map(|(key, value)| {
+                format!("{} = {}", encode_minimal(key), encode_minimal(value))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let counts: BTreeMap<String, i64> = sqlx::query!(
+        r#"
+        SELECT status, count(*) AS "count!: i64" FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 GROUP BY status
+        "#,
+        issue_id,
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to count the deliveries of the issue.")?
+    .into_iter()
+    .map(|r| (r.status, r.count))
+    .collect();
+    let deliveries: String = DeliveryStatus::ALL
+        .iter()
+        .map(|status| {
+            format!(
+                "<td>{}</td>",
+                counts.get(status.as_str()).copied().unwrap_or_default()
+            )
+        })
+        .collect();
+    let delivery_headers: String = DeliveryStatus::ALL
+        .iter()
+        .map(|status| format!("<th>{status}</th>"))
+        .collect();
+    let translations: BTreeMap<String, Translation> =
+        serde_json::from_str(&issue.translations)
+            .context("Invalid stored issue translations.")?;
+    let translations: String = translations
+        .iter()
+        .map(|(locale, t)| {
+            format!(
+                r#"
+    <h2>{} ({})</h2>
+    <h3>HTML</h3>
+    <pre>{}</pre>
+    <h3>Text</h3>
+    <pre>{}</pre>"#,
+                encode_minimal(&t.title),
+                encode_minimal(locale),
+                encode_minimal(&t.content.html),
+                encode_minimal(&t.content.text),
+            )
+        })
+        .collect();
+
+    // Only issues still waiting for their send time can be changed
+    let schedule = if status == IssueStatus::Draft {
+        draft_forms(
+            &issue_id,
+            &issue.title,
+            &issue.html_content,
+            &issue.text_content,
+            issue.send_at.as_deref(),
+            &layout_select(&pool, issue.layout.as_deref()).await?,
+        )
+    } else if status == IssueStatus::Scheduled {
+        format!(
+            r#"
+    <h2>Schedule</h2>
+    <form method="post" action="/admin/issues/{0}/reschedule">
+      <label>Send at <input type="text" name="send_at"></label>
+      <button type="submit">Reschedule</button>
+    </form>
+    <form method="post" action="/admin/issues/{0}/cancel">
+      <button type="submit">Cancel issue</button>
+    </form>"#,
+            encode_minimal(&issue_id),
+        )
+    } else {
+        String::new()
+    };
+
+    let body = format!(
+        r#"<p><a href="/admin/issues">All issues</a></p>
+    <p>Preview as <a href="/admin/issues/{id}/preview">HTML</a> or
+    <a href="/admin/issues/{id}/preview/text">plain text</a></p>
+    <dl>
+      <dt>Title</dt><dd>{}</dd>
+      <dt>Status</dt><dd>{}</dd>
+      <dt>Author</dt><dd>{}</dd>
+      <dt>Audience</dt><dd>{audience}</dd>
+      <dt>Layout</dt><dd>{}</dd>
+      <dt>Created</dt><dd>{}</dd>
+      <dt>Scheduled for (UTC)</dt><dd>{}</dd>
+      <dt>Published</dt><dd>{}</dd>
+    </dl>{schedule}
+    <h2>Deliveries</h2>
+    <table>
+      <tr>{delivery_headers}</tr>
+      <tr>{deliveries}</tr>
+    </table>
+    <h3>HTML</h3>
+    <pre>{}</pre>
+    <h3>Text</h3>
+    <pre>{}</pre>{translations}"#,
+        encode_minimal(&issue.title),
+        status,
+        encode_minimal(issue.author.as_deref().unwrap_or_default()),
+        encode_minimal(issue.layout.as_deref().unwrap_or("None")),
+        encode_minimal(&issue.created_at),
+        encode_minimal(issue.send_at.as_deref().unwrap_or_default()),
+        encode_minimal(issue.published_at.as_deref().unwrap_or_default()),
+        encode_minimal(&issue.html_content),
+        encode_minimal(&issue.text_content),
+        id = encode_minimal(&issue_id),
+    );
+    Ok((StatusCode::OK, admin_page("Newsletter issue", &body)))
+}
+
+#[tracing::instrument(
+    name = "Save a newsletter issue",
+    skip(pool, form),
+    fields(title = %form.title)
+)]
+pub async fn save_issue(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<IssueForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let title = form.title.trim();
+    if title.is_empty()
+        || form.html_content.trim().is_empty()
+        || form.text_content.trim().is_empty()
+    {
+        return Err(AdminError::ValidationError(
+            "An issue needs a title, an HTML and a plain text body.".into(),
+        ));
+    }
+    let send_at = parse_form_send_at(&form.send_at)?;
+
+    let content = Content {
+        html: form.html_content,
+        text: form.text_content,
+    };
+    let issue = NewIssue {
+        title,
+        content: &content,
+        translations: BTreeMap::new(),
+        audience: BTreeMap::new(),
+        send_at,
+        draft: form.draft,
+        layout: Some(form.layout.as_str()).filter(|l| !l.is_empty()),
+    };
+    let issue_id = create_issue(&pool, &issue, user_id.0).await?;
+    Ok(Redirect::to(&format!("/admin/issues/{issue_id}")))
+}
+
+#[tracing::instrument(name = "Reschedule a newsletter issue", skip(pool, form))]
+pub async fn reschedule_newsletter_issue(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+    Form(form): Form<RescheduleForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let send_at = parse_form_send_at(&form.send_at)?.ok_or_else(|| {
+        AdminError::ValidationError("A new send time is needed.".into())
+    })?;
+    if !reschedule_issue(&pool, issue_id, send_at).await? {
+        return Err(AdminError::ValidationError(
+            "Only scheduled issues can be rescheduled.".into(),
+        ));
+    }
+    Ok(Redirect::to(&format!("/admin/issues/{issue_id}")))
+}
+
+#[tracing::instrument(name = "Cancel a newsletter issue", skip(pool))]
+pub async fn cancel_newsletter_issue(
+    Extension(pool): Extension<SqlitePool>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    if !cancel_issue(&pool, issue_id).await? {
+        return Err(AdminError::ValidationError(
+            "Only scheduled issues can be cancelled.".into(),
+        ));
+    }
+    Ok(Redirect::to(&format!("/admin/issues/{issue_id}")))
+}
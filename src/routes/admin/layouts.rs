@@ -0,0 +1,200 @@
+use crate::attributes::get_attribute_schema;
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::get_current_utc_timestamp;
+use crate::templates::{IssueTemplate, Layout, TemplateContext};
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+
+#[derive(serde::Deserialize)]
+pub struct LayoutForm {
+    name: String,
+    html_header: String,
+    html_footer: String,
+    text_header: String,
+    text_footer: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeleteLayoutForm {
+    name: String,
+}
+
+/// Drop-down to pick the layout of an issue
+pub(super) async fn layout_select(
+    pool: &SqlitePool,
+    selected: Option<&str>,
+) -> Result<String, AdminError> {
+    let options: String = sqlx::query!(
+        r#"SELECT name AS "name!" FROM newsletter_layouts ORDER BY name"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query newsletter layouts.")?
+    .iter()
+    .map(|l| {
+        format!(
+            r#"<option value="{0}"{1}>{0}</option>"#,
+            encode_minimal(&l.name),
+            if selected == Some(l.name.as_str()) {
+                " selected"
+            } else {
+                ""
+            },
+        )
+    })
+    .collect();
+    Ok(format!(
+        r#"<label>Layout <select name="layout"><option value="">None</option>{options}</select></label>"#
+    ))
+}
+
+#[tracing::instrument(name = "Show newsletter layouts", skip(pool))]
+pub async fn admin_layouts(
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<impl IntoResponse, AdminError> {
+    let layouts = sqlx::query!(
+        r#"
+        SELECT l.name AS "name!", l.updated_at,
+            (SELECT count(*) FROM newsletter_issues i
+                WHERE i.layout = l.name) AS "issues!: i64"
+        FROM newsletter_layouts l ORDER BY l.name
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query newsletter layouts.")?;
+
+    let rows: String = layouts
+        .iter()
+        .map(|l| {
+            format!(
+                r#"
+      <tr>
+        <td>{0}</td><td>{1}</td><td>{2}</td>
+        <td>
+          <form method="post" action="/admin/layouts/delete">
+            <input type="hidden" name="name" value="{0}">
+            <button type="submit">Remove</button>
+          </form>
+        </td>
+      </tr>"#,
+                encode_minimal(&l.name),
+                encode_minimal(&l.updated_at),
+                l.issues,
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<p>A layout wraps a header and a footer around the content of an
+    issue. Both are templates, using the same variables as issues such as
+    <code>{{{{ name }}}}</code>, <code>{{{{ unsubscribe_url }}}}</code> and
+    <code>{{{{ web_view_url }}}}</code>.</p>
+    <table>
+      <tr><th>Name</th><th>Updated</th><th>Issues</th><th></th></tr>{rows}
+    </table>
+    <h2>Add or replace a layout</h2>
+    <form method="post" action="/admin/layouts">
+      <label>Name <input type="text" name="name"></label><br>
+      <label>HTML header<br><textarea name="html_header" rows="4" cols="60"></textarea></label><br>
+      <label>HTML footer<br><textarea name="html_footer" rows="4" cols="60"></textarea></label><br>
+      <label>Plain text header<br><textarea name="text_header" rows="4" cols="60"></textarea></label><br>
+      <label>Plain text footer<br><textarea name="text_footer" rows="4" cols="60"></textarea></label><br>
+      <button type="submit">Save</button>
+    </form>"#
+    );
+    Ok((StatusCode::OK, admin_page("Newsletter layouts", &body)))
+}
+
+#[tracing::instrument(
+    name = "Save newsletter layout",
+    skip(pool, form),
+    fields(name = %form.name)
+)]
+pub async fn save_layout(
+    Extension(pool): Extension<SqlitePool>,
+    Form(form): Form<LayoutForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(AdminError::ValidationError(
+            "A layout needs a name.".into(),
+        ));
+    }
+    let layout = Layout {
+        html_header: form.html_header,
+        html_footer: form.html_footer,
+        text_header: form.text_header,
+        text_footer: form.text_footer,
+    };
+    // Issues using it were checked against the previous version
+    let template = IssueTemplate {
+        title: String::new(),
+        html: String::new(),
+        text: String::new(),
+        layout: Some(layout),
+    };
+    let schema = get_attribute_schema(&pool).await?;
+    template
+        .render(&TemplateContext::sample(&schema))
+        .map_err(AdminError::ValidationError)?;
+    let layout = template.layout.unwrap_or_default();
+
+    let now = get_current_utc_timestamp();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_layouts
+            (name, html_header, html_footer, text_header, text_footer,
+            created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        ON CONFLICT (name) DO UPDATE
+        SET html_header = $2, html_footer = $3, text_header = $4,
+            text_footer = $5, updated_at = $6
+        "#,
+        name,
+        layout.html_header,
+        layout.html_footer,
+        layout.text_header,
+        layout.text_footer,
+        now,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to save newsletter layout.")?;
+
+    Ok(Redirect::to("/admin/layouts"))
+}
+
+#[tracing::instrument(name = "Delete newsletter layout", skip(pool, form))]
+pub async fn delete_layout(
+    Extension(pool): Extension<SqlitePool>,
+    Form(form): Form<DeleteLayoutForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let in_use = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM newsletter_issues WHERE layout = $1
+        ) AS "in_use!: bool"
+        "#,
+        form.name,
+    )
+    .fetch_one(&pool)
+    .await
+    .context("Failed to check whether the layout is in use.")?
+    .in_use;
+    if in_use {
+        return Err(AdminError::ValidationError(format!(
+            "The layout \"{}\" is used by an issue.",
+            form.name
+        )));
+    }
+    sqlx::query!("DELETE FROM newsletter_layouts WHERE name = $1", form.name)
+        .execute(&pool)
+        .await
+        .context("Failed to delete newsletter layout.")?;
+    Ok(Redirect::to("/admin/layouts"))
+}
@@ -0,0 +1,196 @@
+use crate::authentication::UserId;
+use crate::domain::OptIn;
+use crate::routes::admin::{admin_page, parse_slug, AdminError};
+use crate::settings::SubscriptionSettings;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ListForm {
+    slug: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    /// Empty to use the global setting
+    #[serde(default)]
+    opt_in: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListCopyForm {
+    slug: String,
+    signup_heading: String,
+    signup_intro: String,
+}
+
+/// Drop-down of the opt-in modes, empty for the global setting
+fn opt_in_select(selected: Option<&str>, global: OptIn) -> String {
+    let options: String = [OptIn::Single, OptIn::Double]
+        .iter()
+        .map(|mode| {
+            format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                mode.as_str(),
+                if selected == Some(mode.as_str()) {
+                    " selected"
+                } else {
+                    ""
+                },
+            )
+        })
+        .collect();
+    format!(
+        r#"<label>Opt-in <select name="opt_in"><option value="">Global setting ({})</option>{options}</select></label>"#,
+        global.as_str(),
+    )
+}
+
+#[tracing::instrument(name = "Show lists", skip(pool, settings))]
+pub async fn admin_lists(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(settings): Extension<SubscriptionSettings>,
+) -> Result<impl IntoResponse, AdminError> {
+    let lists = sqlx::query!(
+        r#"
+        SELECT slug, name, description, opt_in, signup_heading,
+            signup_intro,
+            (SELECT count(*) FROM subscription_lists sl
+                WHERE sl.list_id = l.id) AS "subscribers!: i64"
+        FROM lists l ORDER BY name
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query lists.")?;
+
+    let rows: String = lists
+        .iter()
+        .map(|l| {
+            let slug = encode_minimal(&l.slug);
+            format!(
+                r#"
+    <h2>{}</h2>
+    <p><a href="/subscribe/{slug}">/subscribe/{slug}</a>, {} subscribers</p>
+    <form method="post" action="/admin/lists/save">
+      <input type="hidden" name="slug" value="{slug}">
+      <label>Name <input type="text" name="name" value="{0}"></label><br>
+      <label>Description<br><textarea name="description" rows="2" cols="60">{}</textarea></label><br>
+      {}<br>
+      <button type="submit">Save settings</button>
+    </form>
+    <form method="post" action="/admin/lists">
+      <input type="hidden" name="slug" value="{slug}">
+      <label>Heading <input type="text" name="signup_heading" value="{}"></label><br>
+      <label>Introduction<br><textarea name="signup_intro" rows="4" cols="60">{}</textarea></label><br>
+      <button type="submit">Save signup page</button>
+    </form>"#,
+                encode_minimal(&l.name),
+                l.subscribers,
+                encode_minimal(&l.description),
+                opt_in_select(l.opt_in.as_deref(), settings.opt_in),
+                encode_minimal(l.signup_heading.as_deref().unwrap_or_default()),
+                encode_minimal(l.signup_intro.as_deref().unwrap_or_default()),
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<p>Each list has its own signup page. Leave the heading or
+    introduction empty to use the list's name and description.</p>{rows}
+    <h2>Add a list</h2>
+    <form method="post" action="/admin/lists/save">
+      <label>Slug <input type="text" name="slug"></label><br>
+      <label>Name <input type="text" name="name"></label><br>
+      <label>Description<br><textarea name="description" rows="2" cols="60"></textarea></label><br>
+      {}<br>
+      <button type="submit">Add</button>
+    </form>"#,
+        opt_in_select(None, settings.opt_in),
+    );
+    Ok((StatusCode::OK, admin_page("Lists", &body)))
+}
+
+/// Create a list, or update the one with the same slug
+#[tracing::instrument(
+    name = "Save list",
+    skip(pool, form),
+    fields(slug = %form.slug)
+)]
+pub async fn save_list(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<ListForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let slug = parse_slug(&form.slug)?;
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(AdminError::ValidationError("A list needs a name.".into()));
+    }
+    let description = form.description.trim();
+    let opt_in = Some(form.opt_in.trim())
+        .filter(|o| !o.is_empty())
+        .map(OptIn::parse)
+        .transpose()
+        .map_err(AdminError::ValidationError)?
+        .map(|o| o.as_str());
+
+    let id = Uuid::new_v4().to_string();
+    sqlx::query!(
+        r#"
+        INSERT INTO lists (id, slug, name, description, opt_in)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (slug) DO UPDATE
+        SET name = $3, description = $4, opt_in = $5
+        "#,
+        id,
+        slug,
+        name,
+        description,
+        opt_in,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to save the list.")?;
+
+    Ok(Redirect::to("/admin/lists"))
+}
+
+#[tracing::instrument(
+    name = "Save list signup copy",
+    skip(pool, form),
+    fields(slug = %form.slug)
+)]
+pub async fn save_list_copy(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<ListCopyForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let non_empty = |v: &str| {
+        let v = v.trim();
+        (!v.is_empty()).then(|| v.to_string())
+    };
+    let heading = non_empty(&form.signup_heading);
+    let intro = non_empty(&form.signup_intro);
+    let updated = sqlx::query!(
+        r#"
+        UPDATE lists SET signup_heading = $1, signup_intro = $2
+        WHERE slug = $3
+        "#,
+        heading,
+        intro,
+        form.slug,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to save the list signup copy.")?;
+    if updated.rows_affected() == 0 {
+        return Err(AdminError::NotFound);
+    }
+
+    Ok(Redirect::to("/admin/lists"))
+}
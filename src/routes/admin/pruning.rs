@@ -0,0 +1,87 @@
+use crate::authentication::UserId;
+use crate::email_client::EmailClient;
+use crate::pruning::run_pruning;
+use crate::routes::admin::{admin_page, AdminError};
+use crate::settings::{PendingAction, PruningSettings};
+use crate::startup::ApplicationBaseUrl;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::Extension;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+#[tracing::instrument(name = "Show pruning reports", skip(pool, settings))]
+pub async fn admin_pruning(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(settings): Extension<PruningSettings>,
+) -> Result<impl IntoResponse, AdminError> {
+    let runs = sqlx::query!(
+        r#"
+        SELECT started_at, finished_at, pending_reminded, pending_deleted,
+            reengagement_sent, suppressed, failed
+        FROM pruning_runs ORDER BY id DESC LIMIT 50
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query pruning runs.")?;
+
+    let rows: String = runs
+        .iter()
+        .map(|r| {
+            format!(
+                r#"
+      <tr>
+        <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>
+      </tr>"#,
+                r.started_at,
+                r.finished_at,
+                r.pending_reminded,
+                r.pending_deleted,
+                r.reengagement_sent,
+                r.suppressed,
+                r.failed,
+            )
+        })
+        .collect();
+
+    let pending = match settings.pending_action {
+        PendingAction::Remind => "sent a new confirmation link",
+        PendingAction::Delete => "deleted",
+    };
+    let body = format!(
+        r#"<p>Signups still unconfirmed after {} days are {pending}.
+    Subscribers who did not open or click any of their last {} issues are
+    asked whether they want to stay, and unsubscribed if they do not answer
+    within {} days.</p>
+    <form method="post" action="/admin/pruning">
+      <button type="submit">Run now</button>
+    </form>
+    <table>
+      <tr>
+        <th>Started</th><th>Finished</th><th>Reminded</th><th>Deleted</th>
+        <th>Re-engagement sent</th><th>Unsubscribed</th><th>Failed</th>
+      </tr>{rows}
+    </table>"#,
+        settings.pending_max_age_days,
+        settings.inactive_after_issues,
+        settings.reengagement_grace_days,
+    );
+    Ok((StatusCode::OK, admin_page("Pruning", &body)))
+}
+
+#[tracing::instrument(
+    name = "Run pruning from the admin area",
+    skip(pool, email_client, base_url, settings)
+)]
+pub async fn run_pruning_now(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    Extension(settings): Extension<PruningSettings>,
+    Extension(user_id): Extension<UserId>,
+) -> Result<impl IntoResponse, AdminError> {
+    run_pruning(&pool, &email_client, &base_url.0, &settings).await?;
+    Ok(Redirect::to("/admin/pruning"))
+}
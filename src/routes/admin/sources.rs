@@ -0,0 +1,107 @@
+use crate::authentication::UserId;
+use crate::routes::admin::{admin_page, AdminError};
+use anyhow::Context;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Extension;
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+
+/// Attribution columns signups can be grouped by
+const DIMENSIONS: [&str; 8] = [
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+    "referrer",
+    "landing_page",
+    "form_id",
+];
+
+#[derive(serde::Deserialize)]
+pub struct SourcesParams {
+    by: Option<String>,
+}
+
+#[tracing::instrument(name = "Show signup sources", skip(pool, params))]
+pub async fn admin_sources(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Query(params): Query<SourcesParams>,
+) -> Result<impl IntoResponse, AdminError> {
+    let by = params.by.as_deref().unwrap_or(DIMENSIONS[0]);
+    if !DIMENSIONS.contains(&by) {
+        return Err(AdminError::ValidationError(format!(
+            "Signups cannot be grouped by {}.",
+            by
+        )));
+    }
+
+    let sources = sqlx::query!(
+        r#"
+        SELECT
+            CASE $1
+                WHEN 'utm_source' THEN utm_source
+                WHEN 'utm_medium' THEN utm_medium
+                WHEN 'utm_campaign' THEN utm_campaign
+                WHEN 'utm_term' THEN utm_term
+                WHEN 'utm_content' THEN utm_content
+                WHEN 'referrer' THEN referrer
+                WHEN 'landing_page' THEN landing_page
+                WHEN 'form_id' THEN form_id
+            END AS "source: String",
+            count(*) AS "signups!: i64",
+            -- Subscribers who confirmed and left since still converted
+            sum(status = 'confirmed' OR EXISTS (
+                SELECT 1 FROM subscriber_status_history h
+                WHERE h.subscriber_id = s.id AND h.to_status = 'confirmed'
+            )) AS "confirmed!: i64"
+        FROM subscriptions s
+        GROUP BY 1
+        ORDER BY 3 DESC, 2 DESC
+        "#,
+        by,
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query signup sources.")?;
+
+    let rows: String = sources
+        .iter()
+        .map(|s| {
+            let source = s
+                .source
+                .as_deref()
+                .map(encode_minimal)
+                .unwrap_or_else(|| "<i>unknown</i>".into());
+            format!(
+                r#"
+      <tr><td>{source}</td><td>{}</td><td>{}</td><td>{:.1}%</td></tr>"#,
+                s.signups,
+                s.confirmed,
+                100.0 * s.confirmed as f64 / s.signups as f64,
+            )
+        })
+        .collect();
+    let links = DIMENSIONS
+        .iter()
+        .map(|d| {
+            if *d == by {
+                format!("<b>{d}</b>")
+            } else {
+                format!(r#"<a href="/admin/sources?by={d}">{d}</a>"#)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    let body = format!(
+        r#"<p>Group by: {links}</p>
+    <table>
+      <tr><th>{by}</th><th>Signups</th><th>Confirmed</th><th>Conversion</th></tr>{rows}
+    </table>"#
+    );
+    Ok((StatusCode::OK, admin_page("Signup sources", &body)))
+}
@@ -0,0 +1,271 @@
+use crate::authentication::UserId;
+use crate::domain::SubscriberStatus;
+use crate::routes::admin::AdminError;
+use anyhow::Context;
+use axum::body::{Body, Bytes};
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use futures_util::TryStreamExt;
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+/// Columns of the CSV export, in the order of `ExportedSubscriber`
+const CSV_COLUMNS: [&str; 21] = [
+    "id",
+    "email",
+    "name",
+    "status",
+    "opt_in",
+    "delivery_frequency",
+    "locale",
+    "lists",
+    "topics",
+    "consent_note",
+    "subscribed_at",
+    "email_canonical",
+    "referrer",
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+    "landing_page",
+    "form_id",
+    "attributes",
+];
+
+#[derive(serde::Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<String>,
+    /// List slug
+    list: Option<String>,
+    /// Inclusive signup dates, `YYYY-MM-DD`
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSubscriber {
+    id: String,
+    email: String,
+    name: String,
+    status: String,
+    opt_in: String,
+    delivery_frequency: String,
+    locale: String,
+    /// Space separated slugs
+    lists: Option<String>,
+    topics: Option<String>,
+    consent_note: Option<String>,
+    subscribed_at: Option<String>,
+    email_canonical: Option<String>,
+    referrer: Option<String>,
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+    utm_term: Option<String>,
+    utm_content: Option<String>,
+    landing_page: Option<String>,
+    form_id: Option<String>,
+    /// JSON object of custom attributes, nested as is in NDJSON
+    attributes: String,
+}
+
+/// Filters with empty form fields dropped and dates validated
+struct ExportFilter {
+    status: Option<String>,
+    list: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+impl TryFrom<ExportParams> for ExportFilter {
+    type Error = String;
+
+    fn try_from(params: ExportParams) -> Result<Self, Self::Error> {
+        let non_empty = |v: Option<String>| {
+            v.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+        };
+        let date = |v: Option<String>| match non_empty(v) {
+            Some(v) => chrono::NaiveDate::parse_from_str(&v, "%Y-%m-%d")
+                .map(|d| Some(d.to_string()))
+                .map_err(|_| format!("{} is not a valid YYYY-MM-DD date.", v)),
+            None => Ok(None),
+        };
+        let status = non_empty(params.status)
+            .map(|s| SubscriberStatus::parse(&s))
+            .transpose()?
+            .map(|s| s.as_str().to_string());
+        Ok(Self {
+            status,
+            list: non_empty(params.list),
+            since: date(params.since)?,
+            until: date(params.until)?,
+        })
+    }
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn encode(
+        &self,
+        subscriber: &ExportedSubscriber,
+    ) -> Result<Bytes, anyhow::Error> {
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(Vec::new());
+                writer.serialize(subscriber)?;
+                Ok(writer.into_inner()?.into())
+            }
+            ExportFormat::Ndjson => {
+                let mut subscriber = serde_json::to_value(subscriber)?;
+                subscriber["attributes"] = serde_json::from_str(
+                    subscriber["attributes"].as_str().unwrap_or("{}"),
+                )?;
+                let mut line = serde_json::to_vec(&subscriber)?;
+                line.push(b'\n');
+                Ok(line.into())
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "Export subscribers", skip(pool, params))]
+pub async fn admin_export_subscribers(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, AdminError> {
+    let format = params.format;
+    let filter =
+        ExportFilter::try_from(params).map_err(AdminError::ValidationError)?;
+
+    // Rows are sent through a small channel so only a handful are in
+    // memory at once, however large the table
+    let (sender, receiver) = mpsc::channel(16);
+    tokio::spawn(
+        stream_subscribers(pool, filter, format, sender)
+            .instrument(tracing::Span::current()),
+    );
+    let body = Body::from_stream(futures_util::stream::unfold(
+        receiver,
+        |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        },
+    ));
+
+    let disposition = format!(
+        "attachment; filename=\"subscribers.{}\"",
+        format.extension()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn stream_subscribers(
+    pool: SqlitePool,
+    filter: ExportFilter,
+    format: ExportFormat,
+    sender: mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) {
+    if let Err(e) = write_subscribers(&pool, &filter, format, &sender).await {
+        tracing::error!(error = ?e, "Failed to stream the subscriber export.");
+        // Aborts the response so a truncated export is not mistaken for
+        // a complete one
+        let _ = sender.send(Err(e)).await;
+    }
+}
+
+async fn write_subscribers(
+    pool: &SqlitePool,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    if format == ExportFormat::Csv {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(CSV_COLUMNS)?;
+        if sender.send(Ok(writer.into_inner()?.into())).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    let mut rows = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT s.id AS "id!", s.email, s.name, s.status, s.opt_in,
+            s.delivery_frequency, s.locale,
+            (SELECT group_concat(l.slug, ' ')
+                FROM subscription_lists sl JOIN lists l ON l.id = sl.list_id
+                WHERE sl.subscriber_id = s.id) AS "lists: String",
+            (SELECT group_concat(t.slug, ' ')
+                FROM subscription_topics st JOIN topics t ON t.id = st.topic_id
+                WHERE st.subscriber_id = s.id) AS "topics: String",
+            s.consent_note, s.subscribed_at, s.email_canonical, s.referrer,
+            s.utm_source, s.utm_medium, s.utm_campaign, s.utm_term,
+            s.utm_content, s.landing_page, s.form_id,
+            (SELECT json_group_object(a.key, a.value)
+                FROM subscriber_attributes a
+                WHERE a.subscriber_id = s.id) AS "attributes!: String"
+        FROM subscriptions s
+        WHERE ($1 IS NULL OR s.status = $1)
+            AND ($2 IS NULL OR EXISTS (
+                SELECT 1
+                FROM subscription_lists sl JOIN lists l ON l.id = sl.list_id
+                WHERE sl.subscriber_id = s.id AND l.slug = $2))
+            AND ($3 IS NULL OR date(s.subscribed_at) >= $3)
+            AND ($4 IS NULL OR date(s.subscribed_at) <= $4)
+        ORDER BY s.subscribed_at, s.id
+        "#,
+        filter.status,
+        filter.list,
+        filter.since,
+        filter.until,
+    )
+    .fetch(pool);
+    while let Some(subscriber) = rows
+        .try_next()
+        .await
+        .context("Failed to fetch subscribers to export.")?
+    {
+        // The client went away, stop reading
+        if sender.send(Ok(format.encode(&subscriber)?)).await.is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use crate::authentication::UserId;
+use crate::domain::SubscriberStatus;
+use crate::erasure::erase_subscriber;
+use crate::routes::admin::{admin_page, AdminError};
+use crate::routes::data_export::{build_data_export, data_export_response};
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Extension;
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberSearch {
+    #[serde(default)]
+    q: String,
+}
+
+#[tracing::instrument(name = "Show subscribers", skip(pool, search))]
+pub async fn admin_subscribers(
+    Extension(pool): Extension<SqlitePool>,
+    Query(search): Query<SubscriberSearch>,
+) -> Result<impl IntoResponse, AdminError> {
+    let pattern = format!("%{}%", search.q.trim().to_lowercase());
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT id AS "id!", email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE email_canonical LIKE $1 OR lower(name) LIKE $1
+        ORDER BY subscribed_at DESC
+        LIMIT 100
+        "#,
+        pattern,
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query subscribers.")?;
+
+    let rows: String = subscribers
+        .iter()
+        .map(|s| {
+            format!(
+                r#"
+      <tr>
+        <td>{0}</td><td>{1}</td><td>{2}</td><td>{3}</td>
+        <td><a href="/admin/subscribers/{4}/export">Export data</a></td>
+        <td>
+          <form method="post" action="/admin/subscribers/{4}/erase">
+            <button type="submit">Erase</button>
+          </form>
+        </td>
+      </tr>"#,
+                encode_minimal(&s.email),
+                encode_minimal(&s.name),
+                encode_minimal(&s.status),
+                encode_minimal(s.subscribed_at.as_deref().unwrap_or_default()),
+                encode_minimal(&s.id),
+            )
+        })
+        .collect();
+
+    let statuses: String = SubscriberStatus::ALL
+        .iter()
+        .filter(|s| **s != SubscriberStatus::Erased)
+        .map(|s| format!(r#"<option value="{0}">{0}</option>"#, s.as_str()))
+        .collect();
+    let body = format!(
+        r#"<form method="get">
+      <input type="search" name="q" value="{}" placeholder="Email or name">
+      <button type="submit">Search</button>
+    </form>
+    <table>
+      <tr><th>Email</th><th>Name</th><th>Status</th><th>Subscribed</th><th></th><th></th></tr>{rows}
+    </table>
+    <h2>Export</h2>
+    <form method="get" action="/admin/subscribers/export">
+      <select name="format">
+        <option value="csv">CSV</option>
+        <option value="ndjson">NDJSON</option>
+      </select>
+      <select name="status">
+        <option value="">Any status</option>{statuses}
+      </select>
+      <input type="text" name="list" placeholder="List slug">
+      <label>From <input type="date" name="since"></label>
+      <label>To <input type="date" name="until"></label>
+      <button type="submit">Export</button>
+    </form>"#,
+        encode_minimal(&search.q)
+    );
+    Ok((StatusCode::OK, admin_page("Subscribers", &body)))
+}
+
+#[tracing::instrument(name = "Export subscriber data as admin", skip(pool))]
+pub async fn admin_export_subscriber(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<Response, AdminError> {
+    let export = build_data_export(&pool, subscriber_id)
+        .await?
+        .ok_or(AdminError::NotFound)?;
+    tracing::info!("Subscriber data exported by an admin.");
+    Ok(data_export_response(subscriber_id, export))
+}
+
+#[tracing::instrument(name = "Erase subscriber as admin", skip(pool, secret))]
+pub async fn admin_erase_subscriber(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(secret): Extension<HmacSecret>,
+    Extension(user_id): Extension<UserId>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    if !erase_subscriber(&pool, subscriber_id, &secret).await? {
+        return Err(AdminError::NotFound);
+    }
+    Ok(Redirect::to("/admin/subscribers"))
+}
@@ -0,0 +1,103 @@
+use crate::authentication::UserId;
+use crate::routes::admin::{admin_page, parse_slug, AdminError};
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct TopicForm {
+    slug: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[tracing::instrument(name = "Show topics", skip(pool))]
+pub async fn admin_topics(
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<impl IntoResponse, AdminError> {
+    let topics = sqlx::query!(
+        r#"
+        SELECT slug, name, description,
+            (SELECT count(*) FROM subscription_topics st
+                WHERE st.topic_id = t.id) AS "subscribers!: i64"
+        FROM topics t ORDER BY name
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Failed to query topics.")?;
+
+    let rows: String = topics
+        .iter()
+        .map(|t| {
+            format!(
+                r#"
+      <tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+                encode_minimal(&t.slug),
+                encode_minimal(&t.name),
+                encode_minimal(&t.description),
+                t.subscribers,
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<p>Subscribers pick the topics they are interested in from their
+    preference center.</p>
+    <table>
+      <tr><th>Slug</th><th>Name</th><th>Description</th><th>Subscribers</th></tr>{rows}
+    </table>
+    <h2>Add or replace a topic</h2>
+    <form method="post" action="/admin/topics">
+      <label>Slug <input type="text" name="slug"></label><br>
+      <label>Name <input type="text" name="name"></label><br>
+      <label>Description<br><textarea name="description" rows="2" cols="60"></textarea></label><br>
+      <button type="submit">Save</button>
+    </form>"#
+    );
+    Ok((StatusCode::OK, admin_page("Topics", &body)))
+}
+
+/// Create a topic, or update the one with the same slug
+#[tracing::instrument(
+    name = "Save topic",
+    skip(pool, form),
+    fields(slug = %form.slug)
+)]
+pub async fn save_topic(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<TopicForm>,
+) -> Result<impl IntoResponse, AdminError> {
+    let slug = parse_slug(&form.slug)?;
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(AdminError::ValidationError(
+            "A topic needs a name.".into(),
+        ));
+    }
+    let description = form.description.trim();
+
+    let id = Uuid::new_v4().to_string();
+    sqlx::query!(
+        r#"
+        INSERT INTO topics (id, slug, name, description)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO UPDATE SET name = $3, description = $4
+        "#,
+        id,
+        slug,
+        name,
+        description,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to save the topic.")?;
+
+    Ok(Redirect::to("/admin/topics"))
+}
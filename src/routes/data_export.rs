@@ -0,0 +1,455 @@
+//! src/routes/data_export.rs
+//!
+//! Self-service "download my data". The subscriber asks for an export by
+//! email and receives a signed, short-lived link to a JSON document.
+
+use crate::bot_protection::BotProtection;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::{error_chain_fmt, get_current_utc_timestamp};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use anyhow::Context;
+use axum::extract::Query;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Form, Json};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// How long a download link stays valid
+const EXPORT_LINK_VALIDITY_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(thiserror::Error)]
+pub enum DataExportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The download link is invalid or has expired.")]
+    InvalidLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for DataExportError {
+    fn into_response(self) -> Response {
+        match self {
+            DataExportError::ValidationError(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            DataExportError::InvalidLink => {
+                (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            DataExportError::UnexpectedError(_) => {
+                tracing::error!(error = ?self, "Data export error");
+                (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataExportRequest {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DownloadParams {
+    subscriber_id: Uuid,
+    expires: i64,
+    tag: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct DataExport {
+    pub generated_at: String,
+    pub subscription: ExportedSubscription,
+    pub confirmation_tokens: Vec<ExportedToken>,
+    pub lists: Vec<ExportedMembership>,
+    pub topics: Vec<ExportedMembership>,
+    pub preference_changes: Vec<ExportedPreferenceChange>,
+    pub attributes: Vec<ExportedAttribute>,
+    pub drip_enrollment: Option<ExportedDripEnrollment>,
+    pub status_history: Vec<ExportedStatusChange>,
+    pub engagement_events: Vec<ExportedEngagementEvent>,
+    pub newsletter_deliveries: Vec<ExportedDelivery>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSubscription {
+    pub id: String,
+    pub email: String,
+    pub email_canonical: Option<String>,
+    pub name: String,
+    pub status: String,
+    pub status_changed_at: Option<String>,
+    pub opt_in: String,
+    pub delivery_frequency: String,
+    pub locale: String,
+    pub subscribed_at: Option<String>,
+    pub consent_note: Option<String>,
+    pub referrer: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+    pub landing_page: Option<String>,
+    pub form_id: Option<String>,
+    pub issues_since_engagement: i64,
+    pub last_engaged_at: Option<String>,
+    pub reengagement_sent_at: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedToken {
+    pub subscription_token: String,
+    pub created_at: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedMembership {
+    pub slug: String,
+    pub name: String,
+    pub subscribed_at: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedAttribute {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedDripEnrollment {
+    pub enrolled_at: String,
+    pub last_step_delay: Option<i64>,
+    pub last_sent_at: Option<String>,
+    pub stopped_at: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedStatusChange {
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub changed_at: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedEngagementEvent {
+    pub kind: String,
+    pub url: Option<String>,
+    pub occurred_at: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedDelivery {
+    pub newsletter_issue_id: String,
+    pub title: String,
+    pub status: String,
+    pub n_attempts: i64,
+    pub error: Option<String>,
+    pub enqueued_at: String,
+    pub finished_at: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedPreferenceChange {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: String,
+}
+
+#[tracing::instrument(
+    name = "Request a data export",
+    skip(pool, email_client, base_url, secret, bot_protection, form)
+)]
+pub async fn request_data_export(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    Extension(secret): Extension<HmacSecret>,
+    Extension(bot_protection): Extension<Arc<BotProtection>>,
+    Form(form): Form<DataExportRequest>,
+) -> Result<impl IntoResponse, DataExportError> {
+    let email = SubscriberEmail::parse(form.email)
+        .map_err(DataExportError::ValidationError)?;
+    let canonical = email.canonical();
+    let response = (
+        StatusCode::OK,
+        "If we hold data for this address, a download link is on its way.",
+    );
+    // Limited whether or not we know the address, for the same reason
+    // as below
+    if !bot_protection.check_data_export(canonical) {
+        tracing::warn!("Data export requested too often for an address.");
+        return Ok(response);
+    }
+
+    let subscriber = sqlx::query!(
+        r#"SELECT id AS "id!", email FROM subscriptions
+        WHERE email_canonical = $1"#,
+        canonical,
+    )
+    .fetch_optional(&pool)
+    .await
+    .context("Failed to look up subscriber for data export.")?;
+
+    // Respond the same way whether or not we know the address, so the
+    // form cannot be used to probe for subscribers
+    if let Some(subscriber) = subscriber {
+        let subscriber_id = Uuid::parse_str(&subscriber.id)
+            .context("Failed to parse stored subscriber id.")?;
+        let recipient = SubscriberEmail::parse(subscriber.email)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let expires =
+            chrono::Utc::now().timestamp() + EXPORT_LINK_VALIDITY_SECONDS;
+        let tag = export_link_tag(subscriber_id, expires, &secret)
+            .finalize()
+            .into_bytes();
+        let download_link = format!(
+            "{}data-export/download?subscriber_id={subscriber_id}\
+            &expires={expires}&tag={tag:x}",
+            base_url.0
+        );
+        email_client
+            .send_email(
+                &recipient,
+                "Your data export",
+                &format!(
+                    "Click <a href=\"{}\">here</a> to download a copy of \
+                    the data we hold about you. The link expires in 24 hours.",
+                    download_link
+                ),
+                &format!(
+                    "Visit {} to download a copy of the data we hold about \
+                    you. The link expires in 24 hours.",
+                    download_link
+                ),
+            )
+            .await
+            .context("Failed to send the data export email.")?;
+    }
+
+    Ok(response)
+}
+
+#[tracing::instrument(
+    name = "Download a data export",
+    skip(pool, secret, params),
+    fields(subscriber_id = %params.subscriber_id)
+)]
+pub async fn download_data_export(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(secret): Extension<HmacSecret>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Response, DataExportError> {
+    let tag =
+        hex::decode(&params.tag).map_err(|_| DataExportError::InvalidLink)?;
+    export_link_tag(params.subscriber_id, params.expires, &secret)
+        .verify_slice(&tag)
+        .map_err(|_| DataExportError::InvalidLink)?;
+    if params.expires < chrono::Utc::now().timestamp() {
+        return Err(DataExportError::InvalidLink);
+    }
+
+    let export = build_data_export(&pool, params.subscriber_id)
+        .await?
+        .ok_or(DataExportError::InvalidLink)?;
+    Ok(data_export_response(params.subscriber_id, export))
+}
+
+fn export_link_tag(
+    subscriber_id: Uuid,
+    expires: i64,
+    secret: &HmacSecret,
+) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(
+        secret.0.expose_secret().as_bytes(),
+    )
+    .unwrap();
+    mac.update(
+        format!("data_export:subscriber_id={subscriber_id}&expires={expires}")
+            .as_bytes(),
+    );
+    mac
+}
+
+/// JSON download of a data export
+pub fn data_export_response(
+    subscriber_id: Uuid,
+    export: DataExport,
+) -> Response {
+    let disposition =
+        format!("attachment; filename=\"data-export-{subscriber_id}.json\"");
+    ([(header::CONTENT_DISPOSITION, disposition)], Json(export)).into_response()
+}
+
+/// Collect everything we store about a subscriber
+#[tracing::instrument(name = "Build data export", skip(pool))]
+pub async fn build_data_export(
+    pool: &SqlitePool,
+    subscriber_id: Uuid,
+) -> Result<Option<DataExport>, anyhow::Error> {
+    let id = subscriber_id.to_string();
+    let Some(subscription) = sqlx::query_as!(
+        ExportedSubscription,
+        r#"
+        SELECT id AS "id!", email, email_canonical, name, status,
+            status_changed_at, opt_in,
+            delivery_frequency, locale, subscribed_at, consent_note, referrer,
+            utm_source, utm_medium, utm_campaign, utm_term, utm_content,
+            landing_page, form_id, issues_since_engagement, last_engaged_at,
+            reengagement_sent_at
+        FROM subscriptions WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query subscription for data export.")?
+    else {
+        return Ok(None);
+    };
+
+    let confirmation_tokens = sqlx::query_as!(
+        ExportedToken,
+        r#"SELECT subscription_token, created_at FROM subscription_tokens
+        WHERE subscriber_id = $1"#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query tokens for data export.")?;
+
+    let lists = sqlx::query_as!(
+        ExportedMembership,
+        r#"
+        SELECT l.slug, l.name, sl.subscribed_at
+        FROM subscription_lists sl JOIN lists l ON l.id = sl.list_id
+        WHERE sl.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query lists for data export.")?;
+
+    let topics = sqlx::query_as!(
+        ExportedMembership,
+        r#"
+        SELECT t.slug, t.name, st.subscribed_at
+        FROM subscription_topics st JOIN topics t ON t.id = st.topic_id
+        WHERE st.subscriber_id = $1
+        ORDER BY t.slug
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query topics for data export.")?;
+
+    let preference_changes = sqlx::query_as!(
+        ExportedPreferenceChange,
+        r#"
+        SELECT field, old_value, new_value, changed_at
+        FROM preference_changes WHERE subscriber_id = $1
+        ORDER BY id
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query preference changes for data export.")?;
+
+    let attributes = sqlx::query_as!(
+        ExportedAttribute,
+        r#"
+        SELECT key, value FROM subscriber_attributes
+        WHERE subscriber_id = $1
+        ORDER BY key
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query attributes for data export.")?;
+
+    let drip_enrollment = sqlx::query_as!(
+        ExportedDripEnrollment,
+        r#"
+        SELECT enrolled_at, last_step_delay, last_sent_at, stopped_at
+        FROM drip_enrollments WHERE subscriber_id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query the drip enrollment for data export.")?;
+
+    let status_history = sqlx::query_as!(
+        ExportedStatusChange,
+        r#"
+        SELECT from_status, to_status, changed_at
+        FROM subscriber_status_history
+        WHERE subscriber_id = $1
+        ORDER BY id
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query the status history for data export.")?;
+
+    let engagement_events = sqlx::query_as!(
+        ExportedEngagementEvent,
+        r#"
+        SELECT kind, url, occurred_at
+        FROM engagement_events WHERE subscriber_id = $1
+        ORDER BY id
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query engagement events for data export.")?;
+
+    let newsletter_deliveries = sqlx::query_as!(
+        ExportedDelivery,
+        r#"
+        SELECT q.newsletter_issue_id, i.title, q.status, q.n_attempts, q.error,
+            q.enqueued_at, q.finished_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.id = q.newsletter_issue_id
+        WHERE q.subscriber_id = $1
+        ORDER BY q.enqueued_at
+        "#,
+        id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query newsletter deliveries for data export.")?;
+
+    Ok(Some(DataExport {
+        generated_at: get_current_utc_timestamp(),
+        subscription,
+        confirmation_tokens,
+        lists,
+        topics,
+        preference_changes,
+        attributes,
+        drip_enrollment,
+        status_history,
+        engagement_events,
+        newsletter_deliveries,
+    }))
+}
@@ -0,0 +1,115 @@
+//! src/routes/engagement.rs
+//!
+//! Tracking pixel, click redirect and the "keep me subscribed" link of
+//! the re-engagement email.
+
+use crate::engagement::{record_engagement, verify_click, EngagementKind};
+use crate::i18n::translate;
+use crate::startup::HmacSecret;
+use axum::extract::Query;
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Extension;
+use sqlx::SqlitePool;
+
+/// Transparent 1x1 GIF
+const PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+    0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct TrackingParams {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClickParams {
+    token: String,
+    url: String,
+    tag: String,
+}
+
+#[tracing::instrument(name = "Track an open", skip(pool, params))]
+pub async fn track_open(
+    Extension(pool): Extension<SqlitePool>,
+    Query(params): Query<TrackingParams>,
+) -> Response {
+    // The image is shown whatever happens, tracking is best effort
+    if let Err(error) =
+        record_engagement(&pool, &params.token, EngagementKind::Open, None)
+            .await
+    {
+        tracing::error!(error = ?error, "Failed to record an open.");
+    }
+    (
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        PIXEL,
+    )
+        .into_response()
+}
+
+#[tracing::instrument(name = "Track a click", skip(pool, secret, params))]
+pub async fn track_click(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(secret): Extension<HmacSecret>,
+    Query(params): Query<ClickParams>,
+) -> Response {
+    // Unsigned destinations would turn this into an open redirect
+    if !verify_click(&params.url, &params.tag, &secret) {
+        return (StatusCode::BAD_REQUEST, "Invalid link.").into_response();
+    }
+    if let Err(error) = record_engagement(
+        &pool,
+        &params.token,
+        EngagementKind::Click,
+        Some(&params.url),
+    )
+    .await
+    {
+        tracing::error!(error = ?error, "Failed to record a click.");
+    }
+    Redirect::to(&params.url).into_response()
+}
+
+#[tracing::instrument(
+    name = "Keep an inactive subscription",
+    skip(pool, params)
+)]
+pub async fn keep_subscription(
+    Extension(pool): Extension<SqlitePool>,
+    Query(params): Query<TrackingParams>,
+) -> Result<Response, StatusCode> {
+    let engaged =
+        record_engagement(&pool, &params.token, EngagementKind::Keep, None)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to keep a subscription.");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let locale = engaged.locale;
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="{0}">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{1}</title>
+  </head>
+  <body>
+    <h1>{1}</h1>
+    <p>{2}</p>
+  </body>
+</html>"#,
+        locale.as_str(),
+        translate(locale, "keep.title"),
+        translate(locale, "keep.message"),
+    );
+    Ok((StatusCode::OK, Html::from(html)).into_response())
+}
@@ -1,6 +1,199 @@
+use crate::bot_protection::issue_form_token;
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use axum::extract::Path;
 use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Extension;
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
 
-pub async fn home() -> impl IntoResponse {
-    (StatusCode::OK, Html::from(include_str!("home/home.html")))
+/// Text shown above a signup form
+pub struct SignupCopy {
+    pub heading: String,
+    pub intro: String,
+    /// Slug of the list the form subscribes to
+    pub list: Option<String>,
+}
+
+impl Default for SignupCopy {
+    fn default() -> Self {
+        Self {
+            heading: "Welcome to our newsletter!".into(),
+            intro: "Sign up to receive new issues by email.".into(),
+            list: None,
+        }
+    }
+}
+
+/// What was typed into a signup form, echoed back when it is invalid
+#[derive(Default)]
+pub struct SignupFormValues {
+    pub name: String,
+    pub email: String,
+    pub form_token: Option<String>,
+}
+
+pub async fn home(
+    Extension(secret): Extension<HmacSecret>,
+) -> impl IntoResponse {
+    signup_page(
+        StatusCode::OK,
+        &SignupCopy::default(),
+        &SignupFormValues::default(),
+        None,
+        &secret,
+    )
+}
+
+#[tracing::instrument(name = "Show list signup page", skip(pool, secret))]
+pub async fn subscribe_page(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(secret): Extension<HmacSecret>,
+    Path(list): Path<String>,
+) -> Result<Response, StatusCode> {
+    let copy = get_signup_copy(&pool, Some(&list))
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, "Failed to load the signup page.");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(signup_page(
+        StatusCode::OK,
+        &copy,
+        &SignupFormValues::default(),
+        None,
+        &secret,
+    ))
+}
+
+/// Copy for the home page form or a list's signup page, `None` if the
+/// list does not exist
+pub async fn get_signup_copy(
+    pool: &SqlitePool,
+    list: Option<&str>,
+) -> Result<Option<SignupCopy>, anyhow::Error> {
+    let Some(slug) = list else {
+        return Ok(Some(SignupCopy::default()));
+    };
+    let row = sqlx::query!(
+        r#"
+        SELECT slug, name, description, signup_heading, signup_intro
+        FROM lists WHERE slug = $1
+        "#,
+        slug,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to query the list to sign up to.")?;
+
+    Ok(row.map(|r| SignupCopy {
+        heading: r
+            .signup_heading
+            .filter(|h| !h.trim().is_empty())
+            .unwrap_or_else(|| format!("Subscribe to {}", r.name)),
+        intro: r
+            .signup_intro
+            .filter(|i| !i.trim().is_empty())
+            .unwrap_or(r.description),
+        list: Some(r.slug),
+    }))
+}
+
+/// Render a signup form, with `error` shown above it
+pub fn signup_page(
+    status: StatusCode,
+    copy: &SignupCopy,
+    values: &SignupFormValues,
+    error: Option<&str>,
+    secret: &HmacSecret,
+) -> Response {
+    let error_html = match error {
+        Some(error) => {
+            format!(r#"<p role="alert"><i>{}</i></p>"#, encode_minimal(error))
+        }
+        None => String::new(),
+    };
+    // Keep the original timestamp so fixing a typo is not "too fast"
+    let form_token = values.form_token.clone().unwrap_or_else(|| {
+        issue_form_token(chrono::Utc::now().timestamp(), secret)
+    });
+    let (list_html, form_id) = match &copy.list {
+        Some(slug) => (
+            format!(
+                r#"<input type="hidden" name="list" value="{}">"#,
+                encode_minimal(slug)
+            ),
+            "list_page",
+        ),
+        None => (String::new(), "home"),
+    };
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{heading}</title>
+  </head>
+  <body>
+    <h1>{heading}</h1>
+    <p>{intro}</p>
+    {error_html}
+    <form method="post" action="/subscriptions">
+      <label>Name
+        <input type="text" name="name" value="{name}" required>
+      </label>
+      <label>Email
+        <input type="email" name="email" value="{email}" required>
+      </label>
+      {list_html}
+      <input type="hidden" name="form_id" value="{form_id}">
+      <input type="hidden" name="form_token" value="{form_token}">
+      <div style="display:none">
+        <label>Leave this empty <input type="text" name="website" tabindex="-1" autocomplete="off"></label>
+      </div>
+      <button type="submit">Subscribe</button>
+    </form>
+  </body>
+</html>"#,
+        heading = encode_minimal(&copy.heading),
+        intro = encode_minimal(&copy.intro),
+        name = encode_minimal(&values.name),
+        email = encode_minimal(&values.email),
+        form_token = encode_minimal(&form_token),
+    );
+    (status, Html::from(html)).into_response()
+}
+
+/// Shown after a successful signup from a browser
+pub fn signup_success_page(confirmation_sent: bool) -> Response {
+    let (title, message) = if confirmation_sent {
+        (
+            "Check your inbox",
+            "We have sent you an email with a link to confirm your \
+            subscription. It may take a few minutes to arrive, remember to \
+            check your spam folder.",
+        )
+    } else {
+        (
+            "You are subscribed",
+            "Thanks for subscribing to our newsletter!",
+        )
+    };
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+  </head>
+  <body>
+    <h1>{title}</h1>
+    <p>{message}</p>
+    <p><a href="/">Back to the home page</a></p>
+  </body>
+</html>"#
+    );
+    (StatusCode::OK, Html::from(html)).into_response()
 }
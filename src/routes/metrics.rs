@@ -0,0 +1,21 @@
+//! src/routes/metrics.rs
+
+use crate::metrics::Metrics;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+use std::sync::Arc;
+
+/// Metrics
+///
+/// Counters in the Prometheus text exposition format, behind admin
+/// `Basic` authentication.
+pub async fn metrics(
+    Extension(metrics): Extension<Arc<Metrics>>,
+) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
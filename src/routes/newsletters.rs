@@ -1,24 +1,45 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::attributes::get_attribute_schema;
+use crate::authentication::{
+    basic_authentication, validate_credentials, AuthError,
+};
+use crate::domain::Locale;
+use crate::idempotency::{
+    release, save_response, try_processing, IdempotencyKey, NextAction,
+};
+use crate::markdown;
+use crate::newsletter_issues::{
+    create_issue, parse_send_at, IssueError, NewIssue,
+};
 use crate::routes::error_chain_fmt;
-use anyhow::Context;
+use crate::settings::IdempotencySettings;
 use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
-use base64::Engine;
-use secrecy::Secret;
 use sqlx::SqlitePool;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("A request with this idempotency key is still being processed.")]
+    Conflict,
     #[error(transparent)]
     UnexepectedError(#[from] anyhow::Error),
 }
 
+impl From<IssueError> for PublishError {
+    fn from(e: IssueError) -> Self {
+        match e {
+            IssueError::ValidationError(e) => PublishError::ValidationError(e),
+            IssueError::UnexpectedError(e) => PublishError::UnexepectedError(e),
+        }
+    }
+}
+
 impl std::fmt::Debug for PublishError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -36,6 +57,12 @@ impl IntoResponse for PublishError {
                     .insert(header::WWW_AUTHENTICATE, header_value);
                 resp
             }
+            PublishError::ValidationError(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            PublishError::Conflict => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
             PublishError::UnexepectedError(_) => {
                 tracing::error!(error = ?self, "Publish error.");
                 (StatusCode::INTERNAL_SERVER_ERROR).into_response()
@@ -48,27 +75,78 @@ impl IntoResponse for PublishError {
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Only send to subscribers whose custom attributes match every
+    /// value given here
+    #[serde(default)]
+    audience: HashMap<String, serde_json::Value>,
+    /// Variants keyed by locale, subscribers whose locale has none get
+    /// `title` and `content`
+    #[serde(default)]
+    translations: HashMap<String, Translation>,
+    /// RFC 3339 time to send the issue at instead of straight away
+    #[serde(default)]
+    send_at: Option<String>,
+    /// Store the issue as a draft, to be reviewed and published from the
+    /// admin area
+    #[serde(default)]
+    draft: bool,
+    /// Name of a layout to wrap the content in
+    #[serde(default)]
+    layout: Option<String>,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Translation {
+    pub title: String,
+    pub content: Content,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "ContentInput")]
 pub struct Content {
-    html: String,
-    text: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Content as sent, Markdown is rendered into whichever of `html` and
+/// `text` is not given explicitly
+#[derive(serde::Deserialize)]
+struct ContentInput {
+    markdown: Option<String>,
+    html: Option<String>,
+    text: Option<String>,
+}
+
+impl TryFrom<ContentInput> for Content {
+    type Error = String;
+
+    fn try_from(input: ContentInput) -> Result<Content, String> {
+        let markdown = input.markdown.as_deref();
+        let html = input.html.or_else(|| markdown.map(markdown::to_html));
+        let text = input.text.or_else(|| markdown.map(markdown::to_text));
+        match (html, text) {
+            (Some(html), Some(text)) => Ok(Content { html, text }),
+            _ => Err("Content needs Markdown, or both an HTML and a plain \
+                text body."
+                .into()),
+        }
+    }
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+#[derive(serde::Serialize)]
+pub struct PublishedIssue {
+    issue_id: Uuid,
 }
 
 // `HeaderMap` must come before `Json` as the later consumes the whole
 // request leaving nothing for `HeaderMap` to do
-#[tracing::instrument(name = "Publish a newsletter issue", skip(pool, email_client, body, headers), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
+#[tracing::instrument(name = "Publish a newsletter issue", skip(pool, settings, body, headers), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
 pub async fn publish_newsletter(
     Extension(pool): Extension<SqlitePool>,
-    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(settings): Extension<IdempotencySettings>,
     headers: HeaderMap,
     Json(body): Json<BodyData>,
-) -> Result<impl IntoResponse, PublishError> {
+) -> Result<Response, PublishError> {
     let credentials =
         basic_authentication(&headers).map_err(PublishError::AuthError)?;
     tracing::Span::current()
@@ -89,91 +167,70 @@ pub async fn publish_newsletter(
     tracing::Span::current()
         .record("user_id", tracing::field::display(&user_id));
 
-    let subscribers = get_confirmed_subscribers(&pool)
-        .await
-        .context("Unable to query confirmed subscribers")?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => email_client
-                .send_email(
-                    &subscriber.email,
-                    &body.title,
-                    &body.content.html,
-                    &body.content.text,
-                )
-                .await
-                // Necessary for runtime costs, avoids paying for the error path
-                // `.context` would store memory on the heap for every call,
-                // `.with_context` only does it _if_ there is a failure
-                .with_context(|| {
-                    format!(
-                        "Unable to send newsletter issue to {:?}.",
-                        subscriber.email
-                    )
-                })?,
-            Err(error) => {
-                tracing::warn!(error.cause_chain = ?error, "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid",);
-            }
+    // Retried requests get the first response instead of a second issue
+    let Some(key) = IdempotencyKey::from_headers(&headers)
+        .map_err(PublishError::ValidationError)?
+    else {
+        return queue_issue(&pool, &body, &user_id).await;
+    };
+    match try_processing(&pool, &key, user_id, &settings).await? {
+        NextAction::StartProcessing => {}
+        NextAction::ReturnSavedResponse(response) => return Ok(response),
+        NextAction::InFlight => return Err(PublishError::Conflict),
+    }
+    match queue_issue(&pool, &body, &user_id).await {
+        Ok(response) => {
+            Ok(save_response(&pool, &key, user_id, response).await?)
+        }
+        Err(error) => {
+            release(&pool, &key, user_id).await?;
+            Err(error)
         }
     }
-    Ok(StatusCode::OK)
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
+/// Store an issue, queued for every subscriber in its audience once due
+/// unless it is a draft
+async fn queue_issue(
     pool: &SqlitePool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-
-    Ok(confirmed_subscribers)
-}
-
-fn basic_authentication(
-    headers: &HeaderMap,
-) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string.")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = base64::engine::general_purpose::STANDARD
-        .decode(base64encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
-
-    // Split into two segments, using ':' as delimiter
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| {
-            anyhow::anyhow!("A username must be provided in 'Basic' auth.")
-        })?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| {
-            anyhow::anyhow!("A password must be provided in 'Basic' auth.")
-        })?
-        .to_string();
+    body: &BodyData,
+    user_id: &Uuid,
+) -> Result<Response, PublishError> {
+    let audience = get_attribute_schema(pool)
+        .await?
+        .validate(&body.audience)
+        .map_err(PublishError::ValidationError)?;
+    // Stored under the canonical locale name the worker looks up
+    let translations = body
+        .translations
+        .iter()
+        .map(|(locale, translation)| {
+            Ok((Locale::parse(locale)?.as_str(), translation))
+        })
+        .collect::<Result<BTreeMap<_, _>, String>>()
+        .map_err(PublishError::ValidationError)?;
+    let send_at = body
+        .send_at
+        .as_deref()
+        .map(parse_send_at)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
 
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
+    let issue = NewIssue {
+        title: &body.title,
+        content: &body.content,
+        translations,
+        audience,
+        send_at,
+        draft: body.draft,
+        layout: body.layout.as_deref(),
+    };
+    let issue_id = create_issue(pool, &issue, *user_id).await?;
+    // Nothing is sent for drafts, they are only stored
+    let status = if body.draft {
+        StatusCode::CREATED
+    } else {
+        StatusCode::ACCEPTED
+    };
+    Ok((status, Json(PublishedIssue { issue_id })).into_response())
 }
@@ -0,0 +1,161 @@
+//! src/routes/preferences.rs
+//!
+//! Self-service preference center. Each subscriber accesses it through
+//! the secret `preferences_token` stored alongside their subscription.
+
+mod erase;
+mod get;
+mod post;
+
+pub use erase::erase_my_data;
+pub use get::preferences_form;
+pub use post::update_preferences;
+
+use crate::domain::{DeliveryFrequency, Locale, SubscriberStatus};
+use crate::routes::error_chain_fmt;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use sqlx::{Sqlite, Transaction};
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("Unknown preferences token.")]
+    UnknownToken,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PreferencesError {
+    fn into_response(self) -> Response {
+        match self {
+            PreferencesError::UnknownToken => {
+                (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            PreferencesError::ValidationError(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            PreferencesError::UnexpectedError(_) => {
+                tracing::error!(error = ?self, "Preferences error");
+                (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+        }
+    }
+}
+
+/// A list or topic a subscriber can opt in to or out of
+pub struct Choice {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub selected: bool,
+}
+
+/// Everything a subscriber can see and change about their subscription
+pub struct SubscriberPreferences {
+    pub subscriber_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub status: SubscriberStatus,
+    pub delivery_frequency: DeliveryFrequency,
+    pub locale: Locale,
+    pub lists: Vec<Choice>,
+    pub topics: Vec<Choice>,
+}
+
+#[tracing::instrument(
+    name = "Get subscriber preferences",
+    skip(transaction, preferences_token)
+)]
+pub async fn get_subscriber_preferences(
+    transaction: &mut Transaction<'_, Sqlite>,
+    preferences_token: &str,
+) -> Result<Option<SubscriberPreferences>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, name, email, status, delivery_frequency, locale
+        FROM subscriptions
+        WHERE preferences_token = $1
+        "#,
+        preferences_token,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to query subscriber by preferences token.")?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let id = row.id.context("Subscriber is missing an id.")?;
+    let subscriber_id = Uuid::parse_str(&id)
+        .context("Failed to parse stored subscriber id.")?;
+    let delivery_frequency = DeliveryFrequency::parse(&row.delivery_frequency)
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.id AS "id!", l.name, l.description,
+            sl.subscriber_id IS NOT NULL AS "selected!: bool"
+        FROM lists l
+        LEFT JOIN subscription_lists sl
+            ON sl.list_id = l.id AND sl.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        id,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to query list memberships.")?
+    .into_iter()
+    .map(|r| Choice {
+        id: r.id,
+        name: r.name,
+        description: r.description,
+        selected: r.selected,
+    })
+    .collect();
+
+    let topics = sqlx::query!(
+        r#"
+        SELECT t.id AS "id!", t.name, t.description,
+            st.subscriber_id IS NOT NULL AS "selected!: bool"
+        FROM topics t
+        LEFT JOIN subscription_topics st
+            ON st.topic_id = t.id AND st.subscriber_id = $1
+        ORDER BY t.name
+        "#,
+        id,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to query topic memberships.")?
+    .into_iter()
+    .map(|r| Choice {
+        id: r.id,
+        name: r.name,
+        description: r.description,
+        selected: r.selected,
+    })
+    .collect();
+
+    Ok(Some(SubscriberPreferences {
+        subscriber_id,
+        name: row.name,
+        email: row.email,
+        status: SubscriberStatus::parse(&row.status)
+            .map_err(|e| anyhow::anyhow!(e))?,
+        delivery_frequency,
+        locale: Locale::parse(&row.locale).map_err(|e| anyhow::anyhow!(e))?,
+        lists,
+        topics,
+    }))
+}
@@ -0,0 +1,58 @@
+use crate::erasure::erase_subscriber;
+use crate::i18n::translate;
+use crate::routes::preferences::{
+    get_subscriber_preferences, PreferencesError,
+};
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse};
+use axum::{Extension, Form};
+use sqlx::SqlitePool;
+
+#[derive(serde::Deserialize)]
+pub struct EraseForm {
+    token: String,
+}
+
+#[tracing::instrument(
+    name = "Erase own subscriber data",
+    skip(pool, secret, form)
+)]
+pub async fn erase_my_data(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(secret): Extension<HmacSecret>,
+    Form(form): Form<EraseForm>,
+) -> Result<impl IntoResponse, PreferencesError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    let preferences = get_subscriber_preferences(&mut transaction, &form.token)
+        .await?
+        .ok_or(PreferencesError::UnknownToken)?;
+    transaction
+        .rollback()
+        .await
+        .context("Unable to to complete SQL transaction.")?;
+
+    erase_subscriber(&pool, preferences.subscriber_id, &secret).await?;
+
+    let locale = preferences.locale;
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="{}">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{}</title>
+  </head>
+  <body>
+    <p>{}</p>
+  </body>
+</html>"#,
+        locale.as_str(),
+        translate(locale, "preferences.erased_title"),
+        translate(locale, "preferences.erased_message"),
+    );
+    Ok((StatusCode::OK, Html::from(html)))
+}
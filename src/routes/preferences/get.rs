@@ -0,0 +1,169 @@
+use crate::domain::{DeliveryFrequency, Locale, SubscriberStatus};
+use crate::i18n::{translate, translate_with};
+use crate::routes::preferences::{
+    get_subscriber_preferences, Choice, PreferencesError, SubscriberPreferences,
+};
+use anyhow::Context;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse};
+use axum::Extension;
+use htmlescape::encode_minimal;
+use sqlx::SqlitePool;
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    token: String,
+    #[serde(default)]
+    saved: bool,
+}
+
+#[tracing::instrument(name = "Show subscriber preferences", skip(pool, params))]
+pub async fn preferences_form(
+    Extension(pool): Extension<SqlitePool>,
+    Query(params): Query<QueryParams>,
+) -> Result<impl IntoResponse, PreferencesError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    let preferences =
+        get_subscriber_preferences(&mut transaction, &params.token)
+            .await?
+            .ok_or(PreferencesError::UnknownToken)?;
+    transaction
+        .commit()
+        .await
+        .context("Unable to to complete SQL transaction.")?;
+
+    let html = preferences_html(&params.token, &preferences, params.saved);
+    Ok((StatusCode::OK, Html::from(html)))
+}
+
+fn choices_html(field: &str, choices: &[Choice]) -> String {
+    choices
+        .iter()
+        .map(|c| {
+            let checked = if c.selected { " checked" } else { "" };
+            format!(
+                r#"
+      <label>
+        <input type="checkbox" name="{field}" value="{}"{checked}>
+        {} <small>{}</small>
+      </label><br>"#,
+                encode_minimal(&c.id),
+                encode_minimal(&c.name),
+                encode_minimal(&c.description),
+            )
+        })
+        .collect()
+}
+
+fn preferences_html(
+    token: &str,
+    preferences: &SubscriberPreferences,
+    saved: bool,
+) -> String {
+    let locale = preferences.locale;
+    let t = |key: &str| translate(locale, &format!("preferences.{}", key));
+    let saved_html = if saved {
+        format!("<p><i>{}</i></p>", t("saved"))
+    } else {
+        String::new()
+    };
+    let status_html = if preferences.status == SubscriberStatus::Unsubscribed {
+        format!("<p>{}</p>", t("unsubscribed"))
+    } else {
+        String::new()
+    };
+    let frequency_html: String = DeliveryFrequency::ALL
+        .iter()
+        .map(|f| {
+            let selected = if *f == preferences.delivery_frequency {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                f.as_str(),
+                t(&format!("frequency_{}", f.as_str())),
+            )
+        })
+        .collect();
+    let locale_html: String = Locale::ALL
+        .iter()
+        .map(|l| {
+            let selected = if *l == locale { " selected" } else { "" };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                l.as_str(),
+                l.native_name(),
+            )
+        })
+        .collect();
+    let lists_html = choices_html("list", &preferences.lists);
+    let topics_html = choices_html("topic", &preferences.topics);
+    let managing = translate_with(
+        locale,
+        "preferences.managing",
+        &[("email", &encode_minimal(&preferences.email))],
+    );
+    let token = encode_minimal(token);
+    let name = encode_minimal(&preferences.name);
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+  </head>
+  <body>
+    {saved_html}
+    <p>{managing}</p>
+    {status_html}
+    <form method="post" action="/preferences">
+      <input type="hidden" name="token" value="{token}">
+      <label>{name_label}
+        <input type="text" name="name" value="{name}">
+      </label>
+      <label>{language_label}
+        <select name="locale">{locale_html}</select>
+      </label>
+      <label>{frequency_label}
+        <select name="delivery_frequency">{frequency_html}</select>
+      </label>
+      <fieldset>
+        <legend>{lists_label}</legend>{lists_html}
+      </fieldset>
+      <fieldset>
+        <legend>{topics_label}</legend>{topics_html}
+      </fieldset>
+      <label>
+        <input type="checkbox" name="unsubscribe_all" value="on">
+        {unsubscribe_all}
+      </label>
+
+      <button type="submit">{save}</button>
+    </form>
+    <form method="post" action="/preferences/erase">
+      <input type="hidden" name="token" value="{token}">
+      <p>{erase_notice}</p>
+      <button type="submit">{erase_button}</button>
+    </form>
+  </body>
+</html>"#,
+        lang = locale.as_str(),
+        title = t("title"),
+        name_label = t("name"),
+        language_label = t("language"),
+        frequency_label = t("delivery_frequency"),
+        lists_label = t("lists"),
+        topics_label = t("topics"),
+        unsubscribe_all = t("unsubscribe_all"),
+        save = t("save"),
+        erase_notice = t("erase_notice"),
+        erase_button = t("erase_button"),
+    )
+}
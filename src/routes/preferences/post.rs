@@ -0,0 +1,389 @@
+use crate::domain::{
+    DeliveryFrequency, Locale, SubscriberName, SubscriberStatus,
+};
+use crate::lifecycle::change_status;
+use crate::routes::get_current_utc_timestamp;
+use crate::routes::preferences::{
+    get_subscriber_preferences, Choice, PreferencesError,
+};
+use anyhow::Context;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use sqlx::{Executor, Sqlite, SqlitePool, Transaction};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Submitted preference form
+///
+/// Checkboxes repeat their field name once per selected value, which
+/// `axum::Form` cannot deserialize into a struct, so we collect the raw
+/// key/value pairs instead.
+pub struct PreferencesForm {
+    token: String,
+    name: Option<String>,
+    delivery_frequency: Option<String>,
+    locale: Option<String>,
+    lists: HashSet<String>,
+    topics: HashSet<String>,
+    unsubscribe_all: bool,
+}
+
+impl TryFrom<Vec<(String, String)>> for PreferencesForm {
+    type Error = String;
+
+    fn try_from(pairs: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut token = None;
+        let mut form = PreferencesForm {
+            token: String::new(),
+            name: None,
+            delivery_frequency: None,
+            locale: None,
+            lists: HashSet::new(),
+            topics: HashSet::new(),
+            unsubscribe_all: false,
+        };
+        for (key, value) in pairs {
+            match key.as_str() {
+                "token" => token = Some(value),
+                "name" => form.name = Some(value),
+                "delivery_frequency" => form.delivery_frequency = Some(value),
+                "locale" => form.locale = Some(value),
+                "list" => {
+                    form.lists.insert(value);
+                }
+                "topic" => {
+                    form.topics.insert(value);
+                }
+                "unsubscribe_all" => form.unsubscribe_all = value == "on",
+                _ => {}
+            }
+        }
+        form.token = token.ok_or("A preferences token must be provided.")?;
+        Ok(form)
+    }
+}
+
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip(pool, pairs),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn update_preferences(
+    Extension(pool): Extension<SqlitePool>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, PreferencesError> {
+    let form: PreferencesForm = pairs
+        .try_into()
+        .map_err(PreferencesError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Sqlite connection from the pool.")?;
+    let preferences = get_subscriber_preferences(&mut transaction, &form.token)
+        .await?
+        .ok_or(PreferencesError::UnknownToken)?;
+    let subscriber_id = preferences.subscriber_id;
+    tracing::Span::current()
+        .record("subscriber_id", tracing::field::display(&subscriber_id));
+    let changed_at = get_current_utc_timestamp();
+
+    if let Some(name) = form.name {
+        let name = SubscriberName::parse(name)
+            .map_err(PreferencesError::ValidationError)?;
+        if name.as_ref() != preferences.name {
+            update_name(&mut transaction, subscriber_id, name.as_ref())
+                .await
+                .context("Failed to update subscriber name.")?;
+            record_change(
+                &mut transaction,
+                subscriber_id,
+                "name",
+                Some(&preferences.name),
+                Some(name.as_ref()),
+                &changed_at,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(frequency) = form.delivery_frequency {
+        let frequency = DeliveryFrequency::parse(&frequency)
+            .map_err(PreferencesError::ValidationError)?;
+        if frequency != preferences.delivery_frequency {
+            update_delivery_frequency(
+                &mut transaction,
+                subscriber_id,
+                frequency,
+            )
+            .await
+            .context("Failed to update delivery frequency.")?;
+            record_change(
+                &mut transaction,
+                subscriber_id,
+                "delivery_frequency",
+                Some(preferences.delivery_frequency.as_str()),
+                Some(frequency.as_str()),
+                &changed_at,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(locale) = form.locale {
+        let locale = Locale::parse(&locale)
+            .map_err(PreferencesError::ValidationError)?;
+        if locale != preferences.locale {
+            update_locale(&mut transaction, subscriber_id, locale)
+                .await
+                .context("Failed to update subscriber locale.")?;
+            record_change(
+                &mut transaction,
+                subscriber_id,
+                "locale",
+                Some(preferences.locale.as_str()),
+                Some(locale.as_str()),
+                &changed_at,
+            )
+            .await?;
+        }
+    }
+
+    // Unsubscribing from everything wins over any individual selection
+    let (lists, topics) = if form.unsubscribe_all {
+        (HashSet::new(), HashSet::new())
+    } else {
+        (form.lists, form.topics)
+    };
+    update_memberships(
+        &mut transaction,
+        subscriber_id,
+        Membership::List,
+        &preferences.lists,
+        &lists,
+        &changed_at,
+    )
+    .await?;
+    update_memberships(
+        &mut transaction,
+        subscriber_id,
+        Membership::Topic,
+        &preferences.topics,
+        &topics,
+        &changed_at,
+    )
+    .await?;
+
+    if form.unsubscribe_all
+        && preferences
+            .status
+            .can_transition_to(SubscriberStatus::Unsubscribed)
+    {
+        change_status(
+            &mut transaction,
+            subscriber_id,
+            SubscriberStatus::Unsubscribed,
+        )
+        .await
+        .context("Failed to unsubscribe subscriber.")?;
+        record_change(
+            &mut transaction,
+            subscriber_id,
+            "status",
+            Some(preferences.status.as_str()),
+            Some(SubscriberStatus::Unsubscribed.as_str()),
+            &changed_at,
+        )
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store preferences.")?;
+
+    Ok(Redirect::to(&format!(
+        "/preferences?token={}&saved=true",
+        urlencoding::Encoded::new(&form.token)
+    )))
+}
+
+#[derive(Clone, Copy)]
+enum Membership {
+    List,
+    Topic,
+}
+
+impl Membership {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Membership::List => "list",
+            Membership::Topic => "topic",
+        }
+    }
+}
+
+/// Apply the difference between the current and requested selections,
+/// ignoring any identifiers we do not know about.
+async fn update_memberships(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    membership: Membership,
+    current: &[Choice],
+    requested: &HashSet<String>,
+    changed_at: &str,
+) -> Result<(), anyhow::Error> {
+    for choice in current {
+        let wanted = requested.contains(&choice.id);
+        if wanted == choice.selected {
+            continue;
+        }
+        set_membership(
+            transaction,
+            subscriber_id,
+            membership,
+            &choice.id,
+            wanted,
+            changed_at,
+        )
+        .await
+        .with_context(|| {
+            format!("Failed to update {} membership.", membership.as_str())
+        })?;
+        let (old_value, new_value) = if wanted {
+            ("unsubscribed", "subscribed")
+        } else {
+            ("subscribed", "unsubscribed")
+        };
+        record_change(
+            transaction,
+            subscriber_id,
+            &format!("{}:{}", membership.as_str(), choice.id),
+            Some(old_value),
+            Some(new_value),
+            changed_at,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn set_membership(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    membership: Membership,
+    id: &str,
+    subscribed: bool,
+    changed_at: &str,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let query = match (membership, subscribed) {
+        (Membership::List, true) => sqlx::query!(
+            r#"INSERT INTO subscription_lists
+            (subscriber_id, list_id, subscribed_at) VALUES ($1, $2, $3)"#,
+            subscriber_id,
+            id,
+            changed_at,
+        ),
+        (Membership::List, false) => sqlx::query!(
+            r#"DELETE FROM subscription_lists
+            WHERE subscriber_id = $1 AND list_id = $2"#,
+            subscriber_id,
+            id,
+        ),
+        (Membership::Topic, true) => sqlx::query!(
+            r#"INSERT INTO subscription_topics
+            (subscriber_id, topic_id, subscribed_at) VALUES ($1, $2, $3)"#,
+            subscriber_id,
+            id,
+            changed_at,
+        ),
+        (Membership::Topic, false) => sqlx::query!(
+            r#"DELETE FROM subscription_topics
+            WHERE subscriber_id = $1 AND topic_id = $2"#,
+            subscriber_id,
+            id,
+        ),
+    };
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+async fn update_name(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    name: &str,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET name = $1 WHERE id = $2"#,
+        name,
+        subscriber_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+async fn update_delivery_frequency(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    frequency: DeliveryFrequency,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let frequency = frequency.as_str();
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET delivery_frequency = $1 WHERE id = $2"#,
+        frequency,
+        subscriber_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+async fn update_locale(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    locale: Locale,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let locale = locale.as_str();
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET locale = $1 WHERE id = $2"#,
+        locale,
+        subscriber_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Record preference change",
+    skip(transaction, old_value, new_value, changed_at)
+)]
+async fn record_change(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    field: &str,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    changed_at: &str,
+) -> Result<(), anyhow::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO preference_changes
+            (subscriber_id, field, old_value, new_value, changed_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        subscriber_id,
+        field,
+        old_value,
+        new_value,
+        changed_at,
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to record preference change.")?;
+    Ok(())
+}
@@ -19,22 +19,41 @@
 //! src/routes/subscriptions.rs
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
-    routes::error_chain_fmt,
-    startup::ApplicationBaseUrl,
+    attributes::{get_attribute_schema, set_subscriber_attributes},
+    bot_protection::{BotProtection, RejectionReason},
+    domain::{
+        Locale, NewSubscriber, OptIn, SignupSource, SubscriberEmail,
+        SubscriberName, SubscriberStatus,
+    },
+    domain_policy::DomainPolicy,
+    drip::enroll_subscriber,
+    email_client::{EmailClient, EmailError},
+    erasure::is_suppressed,
+    i18n::{request_locale, translate, translate_with},
+    lifecycle::record_initial_status,
+    metrics::Metrics,
+    routes::{
+        error_chain_fmt, get_signup_copy, signup_page, signup_success_page,
+        SignupFormValues,
+    },
+    settings::SubscriptionSettings,
+    startup::{ApplicationBaseUrl, HmacSecret},
 };
 use anyhow::Context;
 use axum::{
-    http::StatusCode,
+    async_trait,
+    extract::{ConnectInfo, FromRequest, Request},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    Extension, Form,
+    Extension, Form, Json,
 };
 use chrono::{DateTime, Utc};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 use sqlx::SqlitePool;
 use sqlx::{Executor, Sqlite, Transaction};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::{char, sync::Arc};
 use uuid::Uuid;
 
@@ -43,16 +62,35 @@ pub enum SubscriptionsError {
     // String or &String cannot use #[from] or #[source], requires `.map_err(...)`
     #[error("{0}")]
     ValidationError(String),
+    #[error("Subscription rejected: {0}.")]
+    Rejected(RejectionReason),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl SubscriptionsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriptionsError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscriptionsError::Rejected(
+                RejectionReason::IpRateLimited
+                | RejectionReason::DomainRateLimited,
+            ) => StatusCode::TOO_MANY_REQUESTS,
+            SubscriptionsError::Rejected(_) => StatusCode::BAD_REQUEST,
+            SubscriptionsError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
 impl IntoResponse for SubscriptionsError {
     fn into_response(self) -> Response {
         // Can match here to give specific a `StatusCode`
         match self {
-            SubscriptionsError::ValidationError(_) => {
-                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            SubscriptionsError::ValidationError(_)
+            | SubscriptionsError::Rejected(_) => {
+                (self.status_code(), self.to_string()).into_response()
             }
             SubscriptionsError::UnexpectedError(_) => {
                 // Avoid passing internal details to the user only use `tracing::error`
@@ -74,6 +112,71 @@ impl std::fmt::Debug for SubscriptionsError {
 pub struct SignUp {
     name: String,
     email: String,
+    /// Optional slug of the list being subscribed to
+    list: Option<String>,
+    /// Honeypot, hidden from humans and left empty
+    website: Option<String>,
+    /// Signed timestamp of when the form was rendered
+    form_token: Option<String>,
+    #[serde(alias = "h-captcha-response", alias = "g-recaptcha-response")]
+    captcha_response: Option<String>,
+    /// Attribution, usually hidden fields filled in by the page
+    referrer: Option<String>,
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+    utm_term: Option<String>,
+    utm_content: Option<String>,
+    /// Defaults to the `Referer` of the submission, i.e., the page the
+    /// form is on
+    landing_page: Option<String>,
+    form_id: Option<String>,
+    /// Preferred language, detected from `Accept-Language` when missing
+    locale: Option<String>,
+    /// Custom attributes, only settable through the JSON API
+    #[serde(default)]
+    attributes: HashMap<String, serde_json::Value>,
+}
+
+/// Accepts either an HTML form or a JSON body, depending on the
+/// `Content-Type`
+pub struct FormOrJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for FormOrJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(
+        req: Request,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+        if is_json {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        } else {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        }
+    }
+}
+
+/// A list a new subscriber signs up to
+pub struct SignUpList {
+    id: String,
+    opt_in: Option<OptIn>,
 }
 
 impl TryFrom<SignUp> for NewSubscriber {
@@ -82,60 +185,526 @@ impl TryFrom<SignUp> for NewSubscriber {
     fn try_from(value: SignUp) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(value.name)?;
         let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+        let source = SignupSource::new(
+            value.referrer,
+            [
+                value.utm_source,
+                value.utm_medium,
+                value.utm_campaign,
+                value.utm_term,
+                value.utm_content,
+            ],
+            value.landing_page,
+            value.form_id,
+        );
+        let locale = value
+            .locale
+            .as_deref()
+            .map(Locale::parse)
+            .transpose()?
+            .unwrap_or_default();
+        Ok(NewSubscriber {
+            email,
+            name,
+            source,
+            locale,
+        })
     }
 }
 
-fn get_current_utc_timestamp() -> String {
+pub(crate) fn get_current_utc_timestamp() -> String {
     let now: DateTime<Utc> = Utc::now();
     now.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(sign_up, pool, email_client, base_url),
+    skip(
+        sign_up,
+        pool,
+        email_client,
+        base_url,
+        settings,
+        bot_protection,
+        domain_policy,
+        metrics,
+        hmac_secret,
+        peer,
+        headers
+    ),
     fields(
         subscriber_email = %sign_up.email,
-        subscriber_name = %sign_up.name
+        subscriber_name = %sign_up.name,
+        opt_in = tracing::field::Empty
     )
 )]
 #[axum_macros::debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscriptions(
     Extension(pool): Extension<SqlitePool>,
     Extension(email_client): Extension<Arc<EmailClient>>,
     Extension(base_url): Extension<ApplicationBaseUrl>,
-    Form(sign_up): Form<SignUp>,
-) -> Result<impl IntoResponse, SubscriptionsError> {
-    let new_subscriber = sign_up
+    Extension(settings): Extension<SubscriptionSettings>,
+    Extension(bot_protection): Extension<Arc<BotProtection>>,
+    Extension(domain_policy): Extension<Arc<DomainPolicy>>,
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(hmac_secret): Extension<HmacSecret>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    FormOrJson(sign_up): FormOrJson<SignUp>,
+) -> Result<Response, SubscriptionsError> {
+    if !wants_html(&headers) {
+        return subscribe(
+            pool,
+            email_client,
+            base_url,
+            settings,
+            bot_protection,
+            domain_policy,
+            metrics,
+            hmac_secret,
+            peer,
+            headers,
+            sign_up,
+        )
+        .await
+        .map(|_| StatusCode::OK.into_response());
+    }
+
+    // Browsers get the form back with the error, or a success page
+    let values = SignupFormValues {
+        name: sign_up.name.clone(),
+        email: sign_up.email.clone(),
+        form_token: sign_up.form_token.clone(),
+    };
+    let list = sign_up.list.clone().filter(|s| !s.trim().is_empty());
+    let result = subscribe(
+        pool.clone(),
+        email_client,
+        base_url,
+        settings,
+        bot_protection,
+        domain_policy,
+        metrics,
+        hmac_secret.clone(),
+        peer,
+        headers,
+        sign_up,
+    )
+    .await;
+    let error = match result {
+        Ok(outcome) => {
+            return Ok(signup_success_page(
+                outcome != SignupOutcome::Subscribed,
+            ))
+        }
+        Err(e @ SubscriptionsError::UnexpectedError(_)) => return Err(e),
+        Err(e) => e,
+    };
+    let copy = get_signup_copy(&pool, list.as_deref())
+        .await?
+        .unwrap_or_default();
+    let status = error.status_code();
+    Ok(signup_page(
+        status,
+        &copy,
+        &values,
+        Some(&error.to_string()),
+        &hmac_secret,
+    ))
+}
+
+/// Browsers ask for HTML, API clients keep the bare status code
+fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/html"))
+        .unwrap_or(false)
+}
+
+/// How a signup that went through ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignupOutcome {
+    ConfirmationSent,
+    Subscribed,
+    /// Dropped as a bot without telling the sender
+    Ignored,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn subscribe(
+    pool: SqlitePool,
+    email_client: Arc<EmailClient>,
+    base_url: ApplicationBaseUrl,
+    settings: SubscriptionSettings,
+    bot_protection: Arc<BotProtection>,
+    domain_policy: Arc<DomainPolicy>,
+    metrics: Arc<Metrics>,
+    hmac_secret: HmacSecret,
+    peer: SocketAddr,
+    headers: HeaderMap,
+    mut sign_up: SignUp,
+) -> Result<SignupOutcome, SubscriptionsError> {
+    let remote_ip = bot_protection.client_ip(&headers, peer);
+    if let Err(reason) = bot_protection
+        .check_ip(remote_ip)
+        .and_then(|_| bot_protection.check_honeypot(sign_up.website.as_deref()))
+        .and_then(|_| {
+            bot_protection
+                .check_form_token(sign_up.form_token.as_deref(), &hmac_secret)
+        })
+    {
+        return reject_attempt(reason, remote_ip, &metrics);
+    }
+    let captcha_response = sign_up.captcha_response.clone();
+    let attributes = std::mem::take(&mut sign_up.attributes);
+    if sign_up.landing_page.is_none() {
+        sign_up.landing_page = headers
+            .get(header::REFERER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+    }
+    if sign_up.locale.is_none() {
+        sign_up.locale = Some(request_locale(&headers).as_str().to_string());
+    }
+    let list_slug = sign_up.list.clone().filter(|s| !s.trim().is_empty());
+    let new_subscriber: NewSubscriber = sign_up
         .try_into()
         .map_err(SubscriptionsError::ValidationError)?;
 
+    let domain = new_subscriber.email.domain();
+    if let Err(rejection) = domain_policy
+        .check(&pool, domain)
+        .await
+        .context("Failed to check the email domain policy.")?
+    {
+        tracing::warn!(
+            domain,
+            reason = rejection.as_str(),
+            "Rejected a subscription from a blocked domain."
+        );
+        metrics.increment(
+            "subscriptions_blocked_domains_total",
+            &[("reason", rejection.as_str())],
+        );
+        return Err(SubscriptionsError::ValidationError(format!(
+            "Email addresses from {} are not accepted.",
+            domain
+        )));
+    }
+    if let Err(reason) = bot_protection.check_domain(domain) {
+        return reject_attempt(reason, remote_ip, &metrics);
+    }
+    // The CAPTCHA goes last as it requires a round trip to the provider
+    if let Err(reason) = bot_protection
+        .check_captcha(captcha_response.as_deref(), remote_ip)
+        .await
+        .context("Failed to verify the CAPTCHA response.")?
+    {
+        return reject_attempt(reason, remote_ip, &metrics);
+    }
+
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Sqlite connection from the pool.")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let attributes = get_attribute_schema(&mut *transaction)
+        .await?
+        .validate(&attributes)
+        .map_err(SubscriptionsError::ValidationError)?;
+    let list = match list_slug {
+        Some(slug) => Some(
+            get_list(&mut transaction, &slug)
+                .await
+                .context("Failed to query the requested list.")?
+                .ok_or_else(|| {
+                    SubscriptionsError::ValidationError(format!(
+                        "{} is not a known list.",
+                        slug
+                    ))
+                })?,
+        ),
+        None => None,
+    };
+    // A list's own opt-in mode takes precedence over the global one
+    let opt_in = list
+        .as_ref()
+        .and_then(|l| l.opt_in)
+        .unwrap_or(settings.opt_in);
+    tracing::Span::current().record("opt_in", opt_in.as_str());
+    // Rejecting the address would reveal that its owner subscribed once
+    // and asked to be erased
+    if is_suppressed(&mut transaction, &new_subscriber.email, &hmac_secret)
         .await
-        .context("Failed to insert new subscriber into the database")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .context("Failed to check the suppression list.")?
+    {
+        tracing::info!("Ignored a signup for an erased address.");
+        return Ok(signup_outcome(opt_in));
+    }
+
+    // Signing up again with the same address must not create a second
+    // subscriber. Anything it changes for an existing subscriber waits
+    // on their confirmation, as the sender may not own the address.
+    let existing = get_subscriber_by_email(
+        &mut transaction,
+        new_subscriber.email.canonical(),
+    )
+    .await
+    .context("Failed to look up an existing subscriber.")?;
+    let (subscriber_id, pending_change) = match existing {
+        Some(existing) => {
+            let list_id = match &list {
+                Some(list)
+                    if !is_list_member(
+                        &mut transaction,
+                        existing.id,
+                        &list.id,
+                    )
+                    .await
+                    .context("Failed to check the list membership.")? =>
+                {
+                    Some(list.id.clone())
+                }
+                _ => None,
+            };
+            let change = PendingChange {
+                list_id,
+                resubscribe: existing
+                    .status
+                    .can_transition_to(SubscriberStatus::Confirmed)
+                    && existing.status != SubscriberStatus::PendingConfirmation,
+            };
+            // Nothing to confirm, answering differently would tell
+            // anyone whether the address is subscribed
+            if existing.status != SubscriberStatus::PendingConfirmation
+                && change.is_empty()
+            {
+                tracing::info!("Ignored a signup that would change nothing.");
+                return Ok(signup_outcome(opt_in));
+            }
+            (existing.id, Some(change))
+        }
+        None => {
+            let subscriber_id =
+                insert_subscriber(&mut transaction, &new_subscriber, opt_in)
+                    .await
+                    .context(
+                        "Failed to insert new subscriber into the database",
+                    )?;
+            // Only on creation, signing up again with someone else's
+            // address must not overwrite their attributes
+            set_subscriber_attributes(
+                &mut transaction,
+                subscriber_id,
+                &attributes,
+            )
+            .await
+            .context("Failed to store the subscriber attributes.")?;
+            // Single opt-in subscribers are confirmed straight away
+            if opt_in == OptIn::Single {
+                enroll_subscriber(
+                    &mut transaction,
+                    subscriber_id,
+                    settings.send_welcome_email,
+                )
+                .await
+                .context("Failed to start the welcome sequence.")?;
+            }
+            if let Some(list) = &list {
+                add_subscriber_to_list(
+                    &mut transaction,
+                    subscriber_id,
+                    &list.id,
+                )
+                .await
+                .context("Failed to add new subscriber to the list.")?;
+            }
+            let change = (opt_in == OptIn::Double).then(PendingChange::default);
+            (subscriber_id, change)
+        }
+    };
+
+    if let Some(change) = pending_change {
+        let subscription_token = generate_subscription_token();
+        store_pending_change_token(
+            &mut transaction,
+            subscriber_id,
+            &subscription_token,
+            &change,
+        )
         .await
         .context(
             "Failed to store the confirmation token for a new subscriber.",
         )?;
-    transaction.commit().await.context(
-        "Failed to commit SQL transaction to store a new subscriber.",
-    )?;
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
+        transaction.commit().await.context(
+            "Failed to commit SQL transaction to store a new subscriber.",
+        )?;
+        send_confirmation_email(
+            &email_client,
+            new_subscriber,
+            &base_url.0,
+            &subscription_token,
+        )
+        .await
+        .context("Failed to send a confirmation email.")?;
+    } else {
+        transaction.commit().await.context(
+            "Failed to commit SQL transaction to store a new subscriber.",
+        )?;
+        if settings.send_welcome_email {
+            send_welcome_email(&email_client, new_subscriber)
+                .await
+                .context("Failed to send a welcome email.")?;
+        }
+    }
+
+    Ok(signup_outcome(opt_in))
+}
+
+/// What a signup answers, the same for new and existing addresses so the
+/// form cannot be used to find out who is subscribed
+fn signup_outcome(opt_in: OptIn) -> SignupOutcome {
+    match opt_in {
+        OptIn::Single => SignupOutcome::Subscribed,
+        OptIn::Double => SignupOutcome::ConfirmationSent,
+    }
+}
+
+fn reject_attempt(
+    reason: RejectionReason,
+    remote_ip: IpAddr,
+    metrics: &Metrics,
+) -> Result<SignupOutcome, SubscriptionsError> {
+    tracing::warn!(%remote_ip, %reason, "Rejected a subscription attempt.");
+    metrics.increment(
+        "subscriptions_rejected_bots_total",
+        &[("reason", reason.as_str())],
+    );
+    match reason {
+        // Pretend all went well so bots do not learn about the trap
+        RejectionReason::Honeypot => Ok(SignupOutcome::Ignored),
+        reason => Err(SubscriptionsError::Rejected(reason)),
+    }
+}
+
+/// A subscriber already stored under the same canonical email
+pub struct ExistingSubscriber {
+    id: Uuid,
+    status: SubscriberStatus,
+}
+
+#[tracing::instrument(
+    name = "Get subscriber by canonical email",
+    skip(transaction, email_canonical)
+)]
+pub async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Sqlite>,
+    email_canonical: &str,
+) -> Result<Option<ExistingSubscriber>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id AS "id!", status FROM subscriptions
+        WHERE email_canonical = $1"#,
+        email_canonical,
     )
-    .await
-    .context("Failed to send a confirmation email.")?;
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    row.map(|r| {
+        Ok(ExistingSubscriber {
+            id: Uuid::parse_str(&r.id)?,
+            status: SubscriberStatus::parse(&r.status)
+                .map_err(|e| anyhow::anyhow!(e))?,
+        })
+    })
+    .transpose()
+}
+
+#[tracing::instrument(name = "Get list by slug", skip(transaction))]
+pub async fn get_list(
+    transaction: &mut Transaction<'_, Sqlite>,
+    slug: &str,
+) -> Result<Option<SignUpList>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id AS "id!", opt_in FROM lists WHERE slug = $1"#,
+        slug,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let opt_in = row
+        .opt_in
+        .map(|o| OptIn::parse(&o))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!(e))?;
+    Ok(Some(SignUpList { id: row.id, opt_in }))
+}
+
+#[tracing::instrument(
+    name = "Check list membership",
+    skip(transaction, subscriber_id)
+)]
+async fn is_list_member(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    list_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM subscription_lists
+            WHERE subscriber_id = $1 AND list_id = $2
+        ) AS "is_member!: bool"
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok(row.is_member)
+}
+
+#[tracing::instrument(
+    name = "Add subscriber to list",
+    skip(transaction, subscriber_id)
+)]
+pub async fn add_subscriber_to_list(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    list_id: &str,
+) -> Result<(), sqlx::Error> {
+    let subscriber_id = subscriber_id.to_string();
+    let current_time = get_current_utc_timestamp();
+    let query = sqlx::query!(
+        r#"INSERT OR IGNORE INTO subscription_lists
+            (subscriber_id, list_id, subscribed_at)
+        VALUES ($1, $2, $3)"#,
+        subscriber_id,
+        list_id,
+        current_time,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// What confirming a token does on top of confirming a pending
+/// subscriber
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PendingChange {
+    /// Join this list
+    pub list_id: Option<String>,
+    /// Bring back a subscriber who unsubscribed or bounced
+    pub resubscribe: bool,
+}
 
-    Ok(StatusCode::OK)
+impl PendingChange {
+    pub fn is_empty(&self) -> bool {
+        self.list_id.is_none() && !self.resubscribe
+    }
 }
 
 #[tracing::instrument(
@@ -147,13 +716,38 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Sqlite>,
     subscriber_id: Uuid,
     subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    store_pending_change_token(
+        transaction,
+        subscriber_id,
+        subscription_token,
+        &PendingChange::default(),
+    )
+    .await
+}
+
+#[tracing::instrument(
+    name = "Store a token confirming a change in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_pending_change_token(
+    transaction: &mut Transaction<'_, Sqlite>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+    change: &PendingChange,
 ) -> Result<(), sqlx::Error> {
     let subscriber_id_string = subscriber_id.to_string();
+    let created_at = get_current_utc_timestamp();
     let query = sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens
+            (subscription_token, subscriber_id, created_at, list_id,
+            resubscribe)
+        VALUES ($1, $2, $3, $4, $5)"#,
         subscription_token,
         subscriber_id_string,
+        created_at,
+        change.list_id,
+        change.resubscribe,
     );
     // Can define `impl From<sqlx::Error> for StoreTokenError` and
     // propogate errors early with `?`
@@ -162,7 +756,7 @@ pub async fn store_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -179,27 +773,40 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), EmailError> {
     let confirmation_link = format!(
         "{}subscriptions/confirm?subscription_token={subscription_token}",
         base_url
     );
-    // Send a (useless) email to the new subscriber.
-    //We are ignoring e-mail delivery errors for now.
+    let locale = new_subscriber.locale;
+    let args = [("link", confirmation_link.as_str())];
     email_client
-      .send_email(
-          &new_subscriber.email,
-          "Welcome!",
-          &format!(
-              "Welcome to our newsletter!<br />\
-              Click <a href=\"{}\">here</a> to confirm your subscription.",
-              confirmation_link
-          ),
-          &format!(
-              "Welcome to our newsletter!\n Visit {} to confirm your subscription.",
-              confirmation_link),
-      )
-      .await
+        .send_email(
+            &new_subscriber.email,
+            translate(locale, "confirmation_email.subject"),
+            &translate_with(locale, "confirmation_email.html", &args),
+            &translate_with(locale, "confirmation_email.text", &args),
+        )
+        .await
+}
+
+#[tracing::instrument(
+    name = "Send a welcome email to a new subscriber",
+    skip(email_client, new_subscriber)
+)]
+pub async fn send_welcome_email(
+    email_client: &EmailClient,
+    new_subscriber: NewSubscriber,
+) -> Result<(), EmailError> {
+    let locale = new_subscriber.locale;
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            translate(locale, "welcome_email.subject"),
+            translate(locale, "welcome_email.html"),
+            translate(locale, "welcome_email.text"),
+        )
+        .await
 }
 
 #[tracing::instrument(
@@ -209,23 +816,55 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Sqlite>,
     new_subscriber: &NewSubscriber,
+    opt_in: OptIn,
 ) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     let current_time = get_current_utc_timestamp();
 
+    let preferences_token = generate_subscription_token();
+    // Single opt-in subscribers need no further confirmation
+    let status = match opt_in {
+        OptIn::Single => SubscriberStatus::Confirmed,
+        OptIn::Double => SubscriberStatus::PendingConfirmation,
+    };
+    let status_str = status.as_str();
+    let opt_in = opt_in.as_str();
+
     let subscriber_id_string = subscriber_id.to_string();
     let subscriber_name = new_subscriber.name.as_ref();
     let subscriber_email = new_subscriber.email.as_ref();
+    let subscriber_email_canonical = new_subscriber.email.canonical();
+    let source = &new_subscriber.source;
+    let locale = new_subscriber.locale.as_str();
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions
+            (id, email, email_canonical, name, subscribed_at, status,
+            status_changed_at, preferences_token, opt_in, referrer,
+            utm_source, utm_medium, utm_campaign, utm_term, utm_content,
+            landing_page, form_id, locale)
+        VALUES ($1, $2, $3, $4, $5, $6, $5, $7, $8, $9, $10, $11, $12, $13,
+            $14, $15, $16, $17)
         "#,
         subscriber_id_string,
         subscriber_email,
+        subscriber_email_canonical,
         subscriber_name,
-        current_time
+        current_time,
+        status_str,
+        preferences_token,
+        opt_in,
+        source.referrer,
+        source.utm_source,
+        source.utm_medium,
+        source.utm_campaign,
+        source.utm_term,
+        source.utm_content,
+        source.landing_page,
+        source.form_id,
+        locale,
     );
     transaction.execute(query).await?;
+    record_initial_status(transaction, subscriber_id, status).await?;
     Ok(subscriber_id)
 }
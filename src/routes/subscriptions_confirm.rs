@@ -1,13 +1,29 @@
-use crate::routes::error_chain_fmt;
+use crate::domain::{
+    Locale, NewSubscriber, SignupSource, SubscriberEmail, SubscriberName,
+    SubscriberStatus,
+};
+use crate::drip::enroll_subscriber;
+use crate::email_client::EmailClient;
+use crate::i18n::{request_locale, translate};
+use crate::lifecycle::{change_status, StatusChangeError};
+use crate::routes::{
+    add_subscriber_to_list, error_chain_fmt, generate_subscription_token,
+    send_confirmation_email, store_pending_change_token, PendingChange,
+};
+use crate::settings::SubscriptionSettings;
+use crate::startup::ApplicationBaseUrl;
 use anyhow::Context;
 use axum::{
     extract::Query,
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Extension,
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, Form, Json,
 };
+use chrono::{Duration, NaiveDateTime, Utc};
+use htmlescape::encode_minimal;
 use sqlx::SqlitePool;
-use sqlx::{Executor, Sqlite, Transaction};
+use sqlx::{Sqlite, Transaction};
+use std::sync::Arc;
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -17,8 +33,6 @@ pub struct Parameters {
 
 #[derive(thiserror::Error)]
 pub enum SubscriptionsConfirmError {
-    #[error("{0}")]
-    InvalidTokenIdError(#[from] uuid::Error),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -26,9 +40,6 @@ pub enum SubscriptionsConfirmError {
 impl IntoResponse for SubscriptionsConfirmError {
     fn into_response(self) -> Response {
         match self {
-            SubscriptionsConfirmError::InvalidTokenIdError(_) => {
-                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
-            }
             SubscriptionsConfirmError::UnexpectedError(_) => {
                 tracing::error!(error = ?self, "Subscription Confirmation Error",);
                 (StatusCode::INTERNAL_SERVER_ERROR).into_response()
@@ -43,38 +54,301 @@ impl std::fmt::Debug for SubscriptionsConfirmError {
     }
 }
 
+/// What happened to a confirmation link, rendered as a page for people
+/// and as JSON for API clients
+pub enum ConfirmationOutcome {
+    Confirmed { preferences_token: Option<String> },
+    AlreadyConfirmed { preferences_token: Option<String> },
+    Expired { subscription_token: String },
+    Invalid,
+    Resent,
+}
+
+impl ConfirmationOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfirmationOutcome::Confirmed { .. } => "confirmed",
+            ConfirmationOutcome::AlreadyConfirmed { .. } => "already_confirmed",
+            ConfirmationOutcome::Expired { .. } => "expired",
+            ConfirmationOutcome::Invalid => "invalid",
+            ConfirmationOutcome::Resent => "resent",
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmationOutcome::Confirmed { .. }
+            | ConfirmationOutcome::AlreadyConfirmed { .. }
+            | ConfirmationOutcome::Resent => StatusCode::OK,
+            ConfirmationOutcome::Expired { .. } => StatusCode::GONE,
+            ConfirmationOutcome::Invalid => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn title(&self, locale: Locale) -> &'static str {
+        translate(locale, &format!("confirm.{}_title", self.as_str()))
+    }
+
+    fn message(&self, locale: Locale) -> &'static str {
+        translate(locale, &format!("confirm.{}_message", self.as_str()))
+    }
+
+    fn next_steps_html(&self, locale: Locale) -> String {
+        match self {
+            ConfirmationOutcome::Confirmed { preferences_token }
+            | ConfirmationOutcome::AlreadyConfirmed { preferences_token } => {
+                match preferences_token {
+                    Some(token) => format!(
+                        r#"<p><a href="/preferences?token={}">{}</a></p>"#,
+                        encode_minimal(token),
+                        translate(locale, "confirm.manage_preferences"),
+                    ),
+                    None => String::new(),
+                }
+            }
+            ConfirmationOutcome::Expired { subscription_token } => format!(
+                r#"<form method="post" action="/subscriptions/confirm/resend">
+      <input type="hidden" name="subscription_token" value="{}">
+      <button type="submit">{}</button>
+    </form>"#,
+                encode_minimal(subscription_token),
+                translate(locale, "confirm.resend_button"),
+            ),
+            ConfirmationOutcome::Invalid => {
+                format!("<p>{}</p>", translate(locale, "confirm.invalid_next"))
+            }
+            ConfirmationOutcome::Resent => {
+                format!("<p>{}</p>", translate(locale, "confirm.resent_next"))
+            }
+        }
+    }
+
+    /// Rendered in `locale`, or the visitor's `Accept-Language` when we
+    /// do not know who they are
+    fn into_response(
+        self,
+        locale: Option<Locale>,
+        headers: &HeaderMap,
+    ) -> Response {
+        let locale = locale.unwrap_or_else(|| request_locale(headers));
+        if wants_json(headers) {
+            return (
+                self.status_code(),
+                Json(serde_json::json!({
+                    "status": self.as_str(),
+                    "message": self.message(locale),
+                })),
+            )
+                .into_response();
+        }
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html lang="{3}">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{0}</title>
+  </head>
+  <body>
+    <h1>{0}</h1>
+    <p>{1}</p>
+    {2}
+  </body>
+</html>"#,
+            self.title(locale),
+            self.message(locale),
+            self.next_steps_html(locale),
+            locale.as_str(),
+        );
+        (self.status_code(), Html::from(html)).into_response()
+    }
+}
+
+/// API clients opt into JSON, browsers get a page
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("application/json"))
+        .unwrap_or(false)
+}
+
+struct TokenRecord {
+    subscriber_id: Uuid,
+    status: SubscriberStatus,
+    preferences_token: Option<String>,
+    created_at: Option<String>,
+    locale: Locale,
+    change: PendingChange,
+}
+
+impl TokenRecord {
+    /// The outcome when the token has nothing left to confirm
+    fn spent_outcome(&self) -> Option<ConfirmationOutcome> {
+        match self.status {
+            SubscriberStatus::PendingConfirmation => None,
+            SubscriberStatus::Confirmed if self.change.list_id.is_some() => {
+                None
+            }
+            SubscriberStatus::Confirmed => {
+                Some(ConfirmationOutcome::AlreadyConfirmed {
+                    preferences_token: self.preferences_token.clone(),
+                })
+            }
+            // Unsubscribed, bounced or complained subscribers are only
+            // brought back by a link sent when they signed up again
+            status
+                if self.change.resubscribe
+                    && status
+                        .can_transition_to(SubscriberStatus::Confirmed) =>
+            {
+                None
+            }
+            _ => Some(ConfirmationOutcome::Invalid),
+        }
+    }
+
+    fn is_expired(&self, ttl_hours: i64) -> bool {
+        // Tokens without a timestamp predate expiry and stay valid
+        self.created_at
+            .as_deref()
+            .and_then(|t| {
+                NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").ok()
+            })
+            .map(|t| t.and_utc() + Duration::hours(ttl_hours) < Utc::now())
+            .unwrap_or(false)
+    }
+}
+
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(pool, params)
+    skip(pool, settings, headers, params)
 )]
 pub async fn confirm(
     Extension(pool): Extension<SqlitePool>,
+    Extension(settings): Extension<SubscriptionSettings>,
+    headers: HeaderMap,
     Query(params): Query<Parameters>,
-) -> Result<impl IntoResponse, SubscriptionsConfirmError> {
+) -> Result<Response, SubscriptionsConfirmError> {
     let mut transaction = pool
         .begin()
         .await
         .context("Unable to acqurie SQL connection to pool.")?;
 
-    let id = get_subscriber_id_from_token(
-        &mut transaction,
-        &params.subscription_token,
-    )
-    .await
-    .context("Unable to query `subscriber_id`.")?;
+    let record = get_token_record(&mut transaction, &params.subscription_token)
+        .await
+        .context("Unable to query `subscriber_id`.")?;
 
-    match id {
-        None => return Ok(StatusCode::UNAUTHORIZED),
-        Some(id) => confirm_subscriber(&mut transaction, id)
+    let locale = record.as_ref().map(|r| r.locale);
+    let outcome = match record {
+        None => ConfirmationOutcome::Invalid,
+        Some(record) => match record.spent_outcome() {
+            Some(outcome) => outcome,
+            None if record
+                .is_expired(settings.confirmation_token_ttl_hours) =>
+            {
+                ConfirmationOutcome::Expired {
+                    subscription_token: params.subscription_token,
+                }
+            }
+            None => {
+                if record.status != SubscriberStatus::Confirmed {
+                    confirm_subscriber(&mut transaction, record.subscriber_id)
+                        .await
+                        .context("Failed to confirm subscriber.")?;
+                }
+                if let Some(list_id) = &record.change.list_id {
+                    add_subscriber_to_list(
+                        &mut transaction,
+                        record.subscriber_id,
+                        list_id,
+                    )
+                    .await
+                    .context("Failed to add the subscriber to the list.")?;
+                }
+                ConfirmationOutcome::Confirmed {
+                    preferences_token: record.preferences_token,
+                }
+            }
+        },
+    };
+    transaction
+        .commit()
+        .await
+        .context("Unable to to complete SQL transaction.")?;
+
+    tracing::info!(outcome = outcome.as_str(), "Confirmation link used.");
+    Ok(outcome.into_response(locale, &headers))
+}
+
+#[tracing::instrument(
+    name = "Resend a confirmation link",
+    skip(pool, email_client, base_url, headers, form)
+)]
+pub async fn resend_confirmation(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    headers: HeaderMap,
+    Form(form): Form<Parameters>,
+) -> Result<Response, SubscriptionsConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Unable to acqurie SQL connection to pool.")?;
+
+    let Some(record) =
+        get_token_record(&mut transaction, &form.subscription_token)
             .await
-            .context("Failed to confirm subscriber.")?,
+            .context("Unable to query `subscriber_id`.")?
+    else {
+        return Ok(ConfirmationOutcome::Invalid.into_response(None, &headers));
+    };
+    let locale = Some(record.locale);
+    if let Some(outcome) = record.spent_outcome() {
+        return Ok(outcome.into_response(locale, &headers));
+    }
+
+    let subscriber_id = record.subscriber_id.to_string();
+    let subscriber = sqlx::query!(
+        "SELECT email, name FROM subscriptions WHERE id = $1",
+        subscriber_id,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to query the subscriber to resend to.")?;
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(subscriber.email)
+            .map_err(|e| anyhow::anyhow!(e))?,
+        name: SubscriberName::parse(subscriber.name)
+            .map_err(|e| anyhow::anyhow!(e))?,
+        source: SignupSource::default(),
+        locale: record.locale,
     };
+
+    let subscription_token = generate_subscription_token();
+    // The new link confirms the same change as the expired one
+    store_pending_change_token(
+        &mut transaction,
+        record.subscriber_id,
+        &subscription_token,
+        &record.change,
+    )
+    .await
+    .context("Failed to store the new confirmation token.")?;
     transaction
         .commit()
         .await
         .context("Unable to to complete SQL transaction.")?;
+    send_confirmation_email(
+        &email_client,
+        new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
 
-    Ok(StatusCode::OK)
+    Ok(ConfirmationOutcome::Resent.into_response(locale, &headers))
 }
 
 #[tracing::instrument(
@@ -84,35 +358,55 @@ pub async fn confirm(
 pub async fn confirm_subscriber(
     transaction: &mut Transaction<'_, Sqlite>,
     subscriber_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    let subscriber_id = subscriber_id.to_string();
-    let query = sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id,
-    );
-
-    transaction.execute(query).await?;
+) -> Result<(), StatusChangeError> {
+    change_status(transaction, subscriber_id, SubscriberStatus::Confirmed)
+        .await?;
+    enroll_subscriber(transaction, subscriber_id, false)
+        .await
+        .context("Failed to start the welcome sequence.")?;
     Ok(())
 }
 
 #[tracing::instrument(
-    name = "Get subscriber id from token",
+    name = "Get confirmation token details",
     skip(transaction, subscription_token)
 )]
-pub async fn get_subscriber_id_from_token(
+async fn get_token_record(
     transaction: &mut Transaction<'_, Sqlite>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let subscription_token = subscription_token.to_string();
+) -> Result<Option<TokenRecord>, anyhow::Error> {
     // &mut Transaction<'_, Sqlite> wraps the executor twice, hence the double dereference
     let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
+        r#"
+        SELECT t.subscriber_id, t.created_at, t.list_id,
+            t.resubscribe AS "resubscribe: bool", s.status,
+            s.preferences_token, s.locale
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        "#,
         subscription_token,
     )
     .fetch_optional(&mut **transaction)
     .await?;
 
-    let id = result.map(|r| r.subscriber_id).unwrap();
-    Ok(Uuid::parse_str(&id).ok())
+    // A malformed stored id is treated like an unknown token
+    let Some(r) = result else {
+        return Ok(None);
+    };
+    let Ok(subscriber_id) = Uuid::parse_str(&r.subscriber_id) else {
+        return Ok(None);
+    };
+    Ok(Some(TokenRecord {
+        subscriber_id,
+        status: SubscriberStatus::parse(&r.status)
+            .map_err(|e| anyhow::anyhow!(e))?,
+        preferences_token: r.preferences_token,
+        created_at: r.created_at,
+        locale: Locale::parse(&r.locale).map_err(|e| anyhow::anyhow!(e))?,
+        change: PendingChange {
+            list_id: r.list_id,
+            resubscribe: r.resubscribe,
+        },
+    }))
 }
@@ -0,0 +1,67 @@
+//! src/routes/web_view.rs
+//!
+//! Issues as sent, to read in the browser through the `web_view_url` of
+//! their templates.
+
+use crate::issue_delivery_worker::load_issue_for_subscriber;
+use crate::startup::ApplicationBaseUrl;
+use axum::extract::{Path, Query};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct WebViewParams {
+    token: String,
+}
+
+#[tracing::instrument(
+    name = "Show an issue in the browser",
+    skip(pool, base_url, params)
+)]
+pub async fn web_view(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    Path(issue_id): Path<Uuid>,
+    Query(params): Query<WebViewParams>,
+) -> Result<Response, StatusCode> {
+    let internal_error = |e: anyhow::Error| {
+        tracing::error!(error = ?e, "Failed to show an issue.");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let issue_id = issue_id.to_string();
+    // Only the recipients of an issue can read it
+    let recipient = sqlx::query!(
+        r#"
+        SELECT s.id AS "id!" FROM subscriptions s
+        JOIN issue_delivery_queue q ON q.subscriber_id = s.id
+        WHERE s.preferences_token = $1 AND q.newsletter_issue_id = $2
+        "#,
+        params.token,
+        issue_id,
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| internal_error(e.into()))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let issue =
+        load_issue_for_subscriber(&pool, &base_url.0, &issue_id, &recipient.id)
+            .await
+            .map_err(internal_error)?;
+    let rendered = issue
+        .template
+        .render(&issue.context)
+        .map_err(|e| internal_error(anyhow::anyhow!(e)))?;
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CONTENT_SECURITY_POLICY, "sandbox"),
+        ],
+        rendered.html,
+    )
+        .into_response())
+}
@@ -18,7 +18,8 @@
 
 //! src/settings.rs
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{OptIn, SubscriberEmail};
+use crate::email_client::{EmailClient, RetryPolicy};
 use reqwest::Url;
 use serde::Deserialize;
 use std::fs;
@@ -36,6 +37,14 @@ pub struct AppSettings {
     pub port: u16,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub domain_policy: DomainPolicySettings,
+    pub drip: DripSettings,
+    pub pruning: PruningSettings,
+    pub delivery: DeliverySettings,
+    pub scheduling: SchedulingSettings,
+    pub idempotency: IdempotencySettings,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
 }
@@ -87,6 +96,185 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn client(&self) -> EmailClient {
+        let sender = self.sender().expect("Invalid sender email!");
+        EmailClient::new(
+            self.base_url.clone(),
+            sender,
+            self.authorization_token.clone(),
+            self.timeout(),
+        )
+    }
+}
+
+/// SubscriptionSettings
+///
+/// Controls how new subscriptions are confirmed. Lists may override
+/// `opt_in` with their own mode.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubscriptionSettings {
+    pub opt_in: OptIn,
+    /// Send a welcome email to single opt-in subscribers
+    pub send_welcome_email: bool,
+    /// How long a confirmation link stays valid
+    pub confirmation_token_ttl_hours: i64,
+}
+
+/// BotProtectionSettings
+///
+/// Controls the checks run against the public subscription form.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BotProtectionSettings {
+    /// Reject submissions without a signed form timestamp
+    pub require_form_token: bool,
+    /// Minimum time between rendering and submitting the form
+    pub min_submit_seconds: i64,
+    pub max_signups_per_ip: usize,
+    pub max_signups_per_domain: usize,
+    /// Data export emails sent to one address, so the export form cannot
+    /// be used to flood an inbox
+    pub max_data_exports_per_email: usize,
+    pub rate_limit_window_seconds: u64,
+    /// Only enable when running behind a reverse proxy that appends the
+    /// client address to the `X-Forwarded-For` header
+    pub trust_x_forwarded_for: bool,
+    pub captcha: CaptchaSettings,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum CaptchaSettings {
+    Disabled,
+    /// Any `siteverify` compatible provider, e.g., hCaptcha
+    Http {
+        verify_url: String,
+        secret: Secret<String>,
+    },
+}
+
+/// DripSettings
+///
+/// Controls the background task sending the welcome sequence.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DripSettings {
+    /// How often to look for subscribers with a step due
+    pub poll_interval_seconds: u64,
+}
+
+impl DripSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+/// PruningSettings
+///
+/// Controls the background task cleaning up subscribers who never
+/// confirmed or stopped reading.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PruningSettings {
+    pub poll_interval_seconds: u64,
+    /// Age at which an unconfirmed signup is reminded or deleted
+    pub pending_max_age_days: i64,
+    pub pending_action: PendingAction,
+    /// Issues in a row without an open or click before a subscriber is
+    /// asked whether they still want the newsletter
+    pub inactive_after_issues: i64,
+    /// Time to answer the re-engagement email before being unsubscribed
+    pub reengagement_grace_days: i64,
+}
+
+impl PruningSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+/// What happens to subscribers still pending past their maximum age
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PendingAction {
+    /// Send a new confirmation link, once
+    Remind,
+    Delete,
+}
+
+impl PendingAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PendingAction::Remind => "remind",
+            PendingAction::Delete => "delete",
+        }
+    }
+}
+
+/// DeliverySettings
+///
+/// Controls the background worker sending newsletter issues.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeliverySettings {
+    /// How long to wait before looking again once the queue is empty
+    pub poll_interval_seconds: u64,
+    /// Time after which a recipient claimed by a worker that never
+    /// finished is sent again
+    pub claim_timeout_seconds: i64,
+    /// Attempts at sending to a recipient before giving up on transient
+    /// failures
+    pub max_attempts: u32,
+    pub base_backoff_seconds: u64,
+    pub max_backoff_seconds: u64,
+}
+
+impl DeliverySettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_secs(
+                self.base_backoff_seconds,
+            ),
+            max_delay: std::time::Duration::from_secs(self.max_backoff_seconds),
+        }
+    }
+}
+
+/// SchedulingSettings
+///
+/// Controls the background task queueing scheduled issues once due.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SchedulingSettings {
+    /// How often to look for issues due to be sent
+    pub poll_interval_seconds: u64,
+}
+
+impl SchedulingSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+/// IdempotencySettings
+///
+/// Controls how long retried `POST /newsletters` requests get the saved
+/// response instead of publishing again.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdempotencySettings {
+    pub key_ttl_hours: i64,
+}
+
+/// DomainPolicySettings
+///
+/// Controls which email domains are rejected at signup, on top of the
+/// allowlist and blocklist maintained from the admin area.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DomainPolicySettings {
+    pub block_disposable: bool,
+    /// Replaces the bundled list of disposable domains when set
+    pub disposable_domains_path: Option<String>,
 }
 
 pub enum Environment {
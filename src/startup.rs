@@ -18,14 +18,38 @@
 
 //! src/startup.rs
 
+use crate::authentication::{reject_cross_site_requests, require_admin};
+use crate::bot_protection::BotProtection;
+use crate::domain_policy::DomainPolicy;
+use crate::drip::run_drip_scheduler;
 use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::metrics::Metrics;
+use crate::newsletter_issues::run_issue_scheduler;
+use crate::pruning::run_pruning_scheduler;
 use crate::routes::{
-    confirm, health_check, home, login, login_form, publish_newsletter,
-    subscriptions,
+    admin_attributes, admin_dashboard, admin_domains, admin_drip,
+    admin_erase_subscriber, admin_export_subscriber, admin_export_subscribers,
+    admin_import, admin_import_form, admin_issue, admin_issues, admin_layouts,
+    admin_lists, admin_pruning, admin_sources, admin_subscribers, admin_topics,
+    cancel_newsletter_issue, confirm, delete_attribute, delete_domain_rule,
+    delete_drip_step, delete_layout, download_data_export, erase_my_data,
+    health_check, home, keep_subscription, login, login_form, metrics,
+    preferences_form, preview_issue_html, preview_issue_text,
+    publish_draft_issue, publish_newsletter, reload_disposable_domains,
+    request_data_export, reschedule_newsletter_issue, resend_confirmation,
+    run_pruning_now, save_attribute, save_domain_rule, save_draft,
+    save_drip_step, save_issue, save_layout, save_list, save_list_copy,
+    save_topic, subscribe_page, subscriptions, test_send_issue, track_click,
+    track_open, update_preferences, web_view,
+};
+use crate::settings::{
+    AppSettings, DatabaseSettings, DeliverySettings, EmailClientSettings,
+    PruningSettings,
 };
-use crate::settings::AppSettings;
 use axum::{
     http::Request,
+    middleware,
     routing::{get, post},
     Extension, Router,
 };
@@ -46,6 +70,20 @@ pub struct Application {
     port: u16,
     router: Router,
     listener: TcpListener,
+    background_tasks: BackgroundTasks,
+}
+
+/// Everything the background drip, pruning, scheduling and delivery tasks
+/// need, started alongside the server
+struct BackgroundTasks {
+    pool: SqlitePool,
+    email_client: EmailClientSettings,
+    base_url: String,
+    hmac_secret: HmacSecret,
+    drip_poll_interval: std::time::Duration,
+    scheduling_poll_interval: std::time::Duration,
+    pruning: PruningSettings,
+    delivery: DeliverySettings,
 }
 
 // Need to wrap base url to prevent raw `String` conflicts on access.
@@ -58,24 +96,103 @@ pub struct HmacSecret(pub Secret<String>);
 pub fn app(
     pool: SqlitePool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-) -> Router {
+    settings: &AppSettings,
+) -> Result<Router, std::io::Error> {
     // wrap client in Arc for multiple handlers
     let shared_client = Arc::new(email_client);
-    let base_url = ApplicationBaseUrl(base_url);
-    let hmac_secret = HmacSecret(hmac_secret);
+    let base_url = ApplicationBaseUrl(
+        settings
+            .normalized_base_url()
+            .expect("Invalid base url!")
+            .into(),
+    );
+    let hmac_secret = HmacSecret(settings.hmac_secret.clone());
+    let bot_protection =
+        Arc::new(BotProtection::new(settings.bot_protection.clone()));
+    let domain_policy =
+        Arc::new(DomainPolicy::new(settings.domain_policy.clone())?);
+    let metrics_registry = Arc::new(Metrics::default());
+
+    let admin_routes = Router::new()
+        .route("/admin", get(admin_dashboard))
+        .route(
+            "/admin/attributes",
+            get(admin_attributes).post(save_attribute),
+        )
+        .route("/admin/attributes/delete", post(delete_attribute))
+        .route("/admin/domains", get(admin_domains).post(save_domain_rule))
+        .route("/admin/domains/delete", post(delete_domain_rule))
+        .route("/admin/domains/reload", post(reload_disposable_domains))
+        .route("/admin/drip", get(admin_drip).post(save_drip_step))
+        .route("/admin/drip/delete", post(delete_drip_step))
+        .route("/admin/import", get(admin_import_form).post(admin_import))
+        .route("/admin/issues", get(admin_issues).post(save_issue))
+        .route("/admin/layouts", get(admin_layouts).post(save_layout))
+        .route("/admin/layouts/delete", post(delete_layout))
+        .route("/admin/issues/:issue_id", get(admin_issue))
+        .route("/admin/issues/:issue_id/draft", post(save_draft))
+        .route("/admin/issues/:issue_id/publish", post(publish_draft_issue))
+        .route("/admin/issues/:issue_id/preview", get(preview_issue_html))
+        .route(
+            "/admin/issues/:issue_id/preview/text",
+            get(preview_issue_text),
+        )
+        .route("/admin/issues/:issue_id/test", post(test_send_issue))
+        .route(
+            "/admin/issues/:issue_id/reschedule",
+            post(reschedule_newsletter_issue),
+        )
+        .route(
+            "/admin/issues/:issue_id/cancel",
+            post(cancel_newsletter_issue),
+        )
+        .route("/admin/lists", get(admin_lists).post(save_list_copy))
+        .route("/admin/lists/save", post(save_list))
+        // Scrapers authenticate like any admin
+        .route("/metrics", get(metrics))
+        .route("/admin/pruning", get(admin_pruning).post(run_pruning_now))
+        .route("/admin/sources", get(admin_sources))
+        .route("/admin/subscribers", get(admin_subscribers))
+        .route("/admin/subscribers/export", get(admin_export_subscribers))
+        .route(
+            "/admin/subscribers/:subscriber_id/export",
+            get(admin_export_subscriber),
+        )
+        .route(
+            "/admin/subscribers/:subscriber_id/erase",
+            post(admin_erase_subscriber),
+        )
+        .route("/admin/topics", get(admin_topics).post(save_topic))
+        .route_layer(middleware::from_fn(require_admin))
+        .route_layer(middleware::from_fn(reject_cross_site_requests));
+
     // Define single routes for now
-    Router::new()
+    Ok(Router::new()
         .route("/", get(home))
         .route("/health_check", get(health_check))
         .route("/login", get(login_form))
         // "/login" is reused when sending a post request or page
         // refresh when submitting a form
         .route("/login", post(login))
+        .route("/subscribe/:list", get(subscribe_page))
         .route("/subscriptions", post(subscriptions))
         .route("/subscriptions/confirm", get(confirm))
-        .route("/newsletters", post(publish_newsletter))
+        .route("/subscriptions/confirm/resend", post(resend_confirmation))
+        .route(
+            "/newsletters",
+            post(publish_newsletter)
+                .layer(middleware::from_fn(reject_cross_site_requests)),
+        )
+        .route("/preferences", get(preferences_form))
+        .route("/preferences", post(update_preferences))
+        .route("/preferences/erase", post(erase_my_data))
+        .route("/data-export", post(request_data_export))
+        .route("/data-export/download", get(download_data_export))
+        .route("/engagement/open", get(track_open))
+        .route("/engagement/click", get(track_click))
+        .route("/engagement/keep", get(keep_subscription))
+        .route("/issues/:issue_id", get(web_view))
+        .merge(admin_routes)
         .layer(Extension(pool))
         // Use Extension to add the Arc<Reqwest::Client>
         // if using multiple Reqwest::Client, then order matters
@@ -83,6 +200,12 @@ pub fn app(
         .layer(Extension(shared_client))
         .layer(Extension(base_url))
         .layer(Extension(hmac_secret))
+        .layer(Extension(settings.subscriptions.clone()))
+        .layer(Extension(settings.pruning.clone()))
+        .layer(Extension(settings.idempotency.clone()))
+        .layer(Extension(bot_protection))
+        .layer(Extension(domain_policy))
+        .layer(Extension(metrics_registry))
         .layer(TraceLayer::new_for_http().make_span_with(
             |request: &Request<_>| {
                 let request_id = uuid::Uuid::new_v4().to_string();
@@ -96,51 +219,53 @@ pub fn app(
                     version = ?request.version(),
                 )
             },
-        ))
+        )))
+}
+
+pub async fn get_connection_pool(settings: &DatabaseSettings) -> SqlitePool {
+    let connection_str =
+        settings.connection_string().expose_secret().to_string();
+    let conn_opt = SqliteConnectOptions::from_str(&connection_str)
+        .expect("Failed to create sqlite connection.")
+        .create_if_missing(true);
+    SqlitePoolOptions::new()
+        .max_connections(10)
+        .connect_with(conn_opt)
+        .await
+        .expect("Failed to create database pool.")
 }
 
 impl Application {
     pub async fn build(settings: AppSettings) -> Result<Self, std::io::Error> {
         let addr = &settings.addr;
         let port = settings.port;
-        let connection_str = settings
-            .database
-            .connection_string()
-            .expose_secret()
-            .to_string();
         // Naive way to create a binded address
         let bind_addr = format!("{}:{}", addr, port);
 
-        let sender = settings
-            .email_client
-            .sender()
-            .expect("Invalid sender email!");
-        let timeout = settings.email_client.timeout();
-        let email_client = EmailClient::new(
-            settings.email_client.base_url.clone(),
-            sender,
-            settings.email_client.authorization_token.clone(),
-            timeout,
-        );
-        let hmac_secret = settings.hmac_secret.clone();
-
-        let conn_opt = SqliteConnectOptions::from_str(&connection_str)
-            .expect("Failed to create sqlite connection.")
-            .create_if_missing(true);
-        let pool = SqlitePoolOptions::new()
-            .max_connections(10)
-            .connect_with(conn_opt)
-            .await
-            .expect("Failed to create database pool.");
+        let email_client = settings.email_client.client();
+        let pool = get_connection_pool(&settings.database).await;
+        let background_tasks = BackgroundTasks {
+            pool: pool.clone(),
+            email_client: settings.email_client.clone(),
+            base_url: settings
+                .normalized_base_url()
+                .expect("Invalid base url!")
+                .into(),
+            hmac_secret: HmacSecret(settings.hmac_secret.clone()),
+            drip_poll_interval: settings.drip.poll_interval(),
+            scheduling_poll_interval: settings.scheduling.poll_interval(),
+            pruning: settings.pruning.clone(),
+            delivery: settings.delivery.clone(),
+        };
 
         // Run app using hyper while listening onto the configured port
         tracing::info!("Listening on {}", port);
         let listener = tokio::net::TcpListener::bind(bind_addr).await?;
-        let base_url = settings.normalized_base_url().unwrap();
         Ok(Self {
             port,
-            router: app(pool, email_client, base_url.into(), hmac_secret),
+            router: app(pool, email_client, &settings)?,
             listener,
+            background_tasks,
         })
     }
 
@@ -154,6 +279,36 @@ impl Application {
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        axum::serve(self.listener, self.router).await
+        let tasks = self.background_tasks;
+        tokio::spawn(run_drip_scheduler(
+            tasks.pool.clone(),
+            tasks.email_client.client(),
+            tasks.base_url.clone(),
+            tasks.drip_poll_interval,
+        ));
+        tokio::spawn(run_pruning_scheduler(
+            tasks.pool.clone(),
+            tasks.email_client.client(),
+            tasks.base_url.clone(),
+            tasks.pruning,
+        ));
+        tokio::spawn(run_issue_scheduler(
+            tasks.pool.clone(),
+            tasks.scheduling_poll_interval,
+        ));
+        tokio::spawn(run_worker_until_stopped(
+            tasks.pool,
+            tasks.email_client.client(),
+            tasks.base_url,
+            tasks.hmac_secret,
+            tasks.delivery,
+        ));
+        // Connection info is needed to rate limit subscriptions per IP
+        axum::serve(
+            self.listener,
+            self.router
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
     }
 }
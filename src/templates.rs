@@ -0,0 +1,249 @@
+//! src/templates.rs
+//!
+//! Templates of newsletter issues, rendered once per recipient with
+//! minijinja. Variables are `name`, `attributes.<key>` for every key in
+//! the attribute schema (empty when unset), `unsubscribe_url` and
+//! `web_view_url`. Using anything else is an error, so issues are rendered
+//! for a sample recipient when saved and a typo fails there instead of
+//! at send time. An issue can be wrapped in a layout, a header and footer
+//! stored in `newsletter_layouts` that are templates too.
+
+use crate::domain::AttributeSchema;
+use anyhow::Context;
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use sqlx::{Executor, Sqlite};
+use std::collections::{BTreeMap, HashMap};
+
+/// Values a template can use
+pub struct TemplateContext {
+    pub name: String,
+    pub attributes: BTreeMap<String, String>,
+    pub unsubscribe_url: String,
+    pub web_view_url: String,
+}
+
+impl TemplateContext {
+    /// Context of a subscriber, `attributes` overriding the empty values
+    /// of the schema
+    pub fn new(
+        name: String,
+        schema: &AttributeSchema,
+        attributes: HashMap<String, String>,
+        unsubscribe_url: String,
+        web_view_url: String,
+    ) -> Self {
+        let mut context = Self::sample(schema);
+        context.name = name;
+        context.attributes.extend(attributes);
+        context.unsubscribe_url = unsubscribe_url;
+        context.web_view_url = web_view_url;
+        context
+    }
+
+    fn to_value(&self) -> Value {
+        context! {
+            name => &self.name,
+            attributes => &self.attributes,
+            // Built from the base URL and URL-encoded tokens, escaping
+            // them for HTML would only mangle them
+            unsubscribe_url => Value::from_safe_string(
+                self.unsubscribe_url.clone()
+            ),
+            web_view_url => Value::from_safe_string(self.web_view_url.clone()),
+        }
+    }
+
+    /// Stand-in for validation, previews and test sends
+    pub fn sample(schema: &AttributeSchema) -> Self {
+        Self {
+            name: "Subscriber".into(),
+            attributes: schema
+                .keys()
+                .map(|key| (key.into(), "".into()))
+                .collect(),
+            unsubscribe_url: "#".into(),
+            web_view_url: "#".into(),
+        }
+    }
+}
+
+/// Header and footer wrapped around the body of an issue
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub html_header: String,
+    pub html_footer: String,
+    pub text_header: String,
+    pub text_footer: String,
+}
+
+/// Everything rendered for one recipient of an issue
+pub struct IssueTemplate {
+    pub title: String,
+    pub html: String,
+    pub text: String,
+    pub layout: Option<Layout>,
+}
+
+#[derive(Debug)]
+pub struct RenderedIssue {
+    pub title: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl IssueTemplate {
+    pub fn render(
+        &self,
+        context: &TemplateContext,
+    ) -> Result<RenderedIssue, String> {
+        let layout = self.layout.clone().unwrap_or_default();
+        let html = format!(
+            "{}{}{}",
+            layout.html_header, self.html, layout.html_footer
+        );
+        let text = format!(
+            "{}{}{}",
+            layout.text_header, self.text, layout.text_footer
+        );
+        let context = context.to_value();
+        Ok(RenderedIssue {
+            title: render("title.txt", &self.title, &context)?,
+            html: render("body.html", &html, &context)?,
+            text: render("body.txt", &text, &context)?,
+        })
+    }
+}
+
+/// Render `source`, escaping values for HTML when `name` ends in `.html`
+fn render(name: &str, source: &str, context: &Value) -> Result<String, String> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+    let describe = |e: minijinja::Error| {
+        let part = match name {
+            "title.txt" => "title",
+            "body.html" => "HTML body",
+            _ => "plain text body",
+        };
+        format!("Invalid template in the {part}: {e}")
+    };
+    env.add_template(name, source).map_err(describe)?;
+    env.get_template(name)
+        .and_then(|template| template.render(context))
+        .map_err(describe)
+}
+
+#[tracing::instrument(name = "Get a newsletter layout", skip(executor))]
+pub async fn get_layout<'c>(
+    executor: impl Executor<'c, Database = Sqlite>,
+    name: &str,
+) -> Result<Option<Layout>, anyhow::Error> {
+    let layout = sqlx::query_as!(
+        Layout,
+        r#"
+        SELECT html_header, html_footer, text_header, text_footer
+        FROM newsletter_layouts WHERE name = $1
+        "#,
+        name,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to query the newsletter layout.")?;
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IssueTemplate, Layout, TemplateContext};
+    use crate::domain::{AttributeDefinition, AttributeKind, AttributeSchema};
+    use claims::assert_err;
+    use std::collections::HashMap;
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new(vec![AttributeDefinition {
+            key: "city".into(),
+            label: "City".into(),
+            kind: AttributeKind::Text,
+        }])
+    }
+
+    fn context() -> TemplateContext {
+        TemplateContext::new(
+            "Ursula <Le Guin>".into(),
+            &schema(),
+            HashMap::new(),
+            "https://example.com/unsubscribe".into(),
+            "https://example.com/view".into(),
+        )
+    }
+
+    fn issue(body: &str) -> IssueTemplate {
+        IssueTemplate {
+            title: "Hi {{ name }}".into(),
+            html: body.into(),
+            text: body.into(),
+            layout: None,
+        }
+    }
+
+    #[test]
+    fn variables_are_filled_in() {
+        let rendered = issue("{{ name }} from {{ attributes.city }}.")
+            .render(&context())
+            .unwrap();
+        assert_eq!(rendered.title, "Hi Ursula <Le Guin>");
+        assert_eq!(rendered.text, "Ursula <Le Guin> from .");
+    }
+
+    #[test]
+    fn html_values_are_escaped() {
+        let rendered = issue("<p>{{ name }}</p>").render(&context()).unwrap();
+        assert_eq!(rendered.html, "<p>Ursula &lt;Le Guin&gt;</p>");
+    }
+
+    #[test]
+    fn undefined_variables_are_rejected() {
+        assert_err!(issue("{{ other }}").render(&context()));
+        assert_err!(issue("{{ attributes.plan }}").render(&context()));
+        assert_err!(issue("{% if %}").render(&context()));
+    }
+
+    #[test]
+    fn markdown_links_to_placeholders_are_filled_in() {
+        let markdown = "[Unsubscribe]({{unsubscribe_url}})";
+        let issue = IssueTemplate {
+            title: "News".into(),
+            html: crate::markdown::to_html(markdown),
+            text: crate::markdown::to_text(markdown),
+            layout: None,
+        };
+        let rendered = issue.render(&context()).unwrap();
+        assert!(rendered
+            .html
+            .contains(r#"href="https://example.com/unsubscribe""#));
+        assert!(rendered
+            .text
+            .contains("[1] https://example.com/unsubscribe"));
+    }
+
+    #[test]
+    fn the_layout_wraps_the_body() {
+        let mut issue = issue("Body\n");
+        issue.layout = Some(Layout {
+            html_header: "<h1>News</h1>".into(),
+            html_footer: r#"<a href="{{ unsubscribe_url }}">Leave</a>"#.into(),
+            text_header: "News\n\n".into(),
+            text_footer: "\nRead online: {{ web_view_url }}".into(),
+        });
+        let rendered = issue.render(&context()).unwrap();
+        assert_eq!(
+            rendered.html,
+            r#"<h1>News</h1>Body
+<a href="https://example.com/unsubscribe">Leave</a>"#
+        );
+        assert_eq!(
+            rendered.text,
+            "News\n\nBody\n\nRead online: https://example.com/view"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::helpers::{cleanup_test_db, spawn_app_with_settings, TestApp};
+use axum::http::StatusCode;
+use sqlx::{Connection, SqliteConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::domain::OptIn;
+
+async fn spawn_app_with_attributes() -> TestApp {
+    let app = spawn_app_with_settings(|s| {
+        s.subscriptions.opt_in = OptIn::Single;
+        s.subscriptions.send_welcome_email = false;
+    })
+    .await;
+    for (key, kind) in
+        [("company", "text"), ("plan", "text"), ("seats", "number")]
+    {
+        let resp = app
+            .post_admin_form(
+                "/admin/attributes",
+                &[("key", key), ("label", ""), ("kind", kind)],
+            )
+            .await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    }
+    app
+}
+
+async fn post_subscription_json(
+    app: &TestApp,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/subscriptions", &app.addr))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn stored_attributes(app: &TestApp) -> Vec<(String, String)> {
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    sqlx::query!("SELECT key, value FROM subscriber_attributes ORDER BY key")
+        .fetch_all(&mut connection)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.key, r.value))
+        .collect()
+}
+
+#[tokio::test]
+async fn attributes_are_stored_from_the_json_subscribe_api() {
+    let app = spawn_app_with_attributes().await;
+
+    let resp = post_subscription_json(
+        &app,
+        serde_json::json!({
+            "name": "Ursula",
+            "email": "ursula@example.com",
+            "attributes": {"company": "Earthsea Ltd", "seats": "12"}
+        }),
+    )
+    .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(
+        stored_attributes(&app).await,
+        vec![
+            ("company".into(), "Earthsea Ltd".into()),
+            ("seats".into(), "12".into())
+        ]
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn attributes_outside_the_schema_are_rejected() {
+    let app = spawn_app_with_attributes().await;
+    let test_cases = [
+        (serde_json::json!({"city": "Portland"}), "unknown attribute"),
+        (serde_json::json!({"seats": "a dozen"}), "invalid number"),
+    ];
+
+    for (attributes, description) in test_cases {
+        let resp = post_subscription_json(
+            &app,
+            serde_json::json!({
+                "name": "Ursula",
+                "email": "ursula@example.com",
+                "attributes": attributes
+            }),
+        )
+        .await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            resp.status().as_u16(),
+            "The API did not reject an {}.",
+            description
+        );
+    }
+    assert!(stored_attributes(&app).await.is_empty());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn attributes_are_imported_from_mapped_csv_columns() {
+    let app = spawn_app_with_attributes().await;
+    let csv = "name,email,Company Name\n\
+        Ursula,ursula@example.com,Earthsea Ltd\n";
+    let form = reqwest::multipart::Form::new()
+        .text("mode", "confirmed")
+        .text("consent_note", "Opted in on the old tool")
+        .text("attribute_columns", "Company Name=company")
+        .part("file", reqwest::multipart::Part::text(csv));
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/import", &app.addr))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(
+        stored_attributes(&app).await,
+        vec![("company".into(), "Earthsea Ltd".into())]
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn newsletters_are_personalized_and_filtered_by_attributes() {
+    let app = spawn_app_with_attributes().await;
+    for (name, email, plan) in [
+        ("Ursula", "ursula@example.com", "pro"),
+        ("Octavia", "octavia@example.com", "free"),
+    ] {
+        post_subscription_json(
+            &app,
+            serde_json::json!({
+                "name": name,
+                "email": email,
+                "attributes": {"plan": plan, "company": "<Earthsea>"}
+            }),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Pro features",
+            "content": {
+                "text": "Hi {{ name }} from {{ attributes.company }}",
+                "html": "<p>Hi {{ name }} from {{attributes.company}}</p>",
+            },
+            "audience": {"plan": "pro"}
+        }))
+        .await;
+
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["To"], "ursula@example.com");
+    assert_eq!(body["TextBody"], "Hi Ursula from <Earthsea>");
+    // Followed by the open tracking pixel
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<p>Hi Ursula from &lt;Earthsea&gt;</p><img "));
+
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Pro features",
+            "content": {"text": "Hi", "html": "<p>Hi</p>"},
+            "audience": {"city": "Portland"}
+        }))
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn the_type_of_an_attribute_in_use_cannot_change() {
+    let app = spawn_app_with_attributes().await;
+    post_subscription_json(
+        &app,
+        serde_json::json!({
+            "name": "Ursula",
+            "email": "ursula@example.com",
+            "attributes": {"seats": 12}
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let resp = app
+        .post_admin_form(
+            "/admin/attributes",
+            &[("key", "seats"), ("label", "Seats"), ("kind", "date")],
+        )
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    let resp = app
+        .post_admin_form("/admin/attributes/delete", &[("key", "seats")])
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    assert!(stored_attributes(&app).await.is_empty());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
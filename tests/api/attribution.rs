@@ -0,0 +1,122 @@
+use crate::helpers::{cleanup_test_db, spawn_app};
+use axum::http::StatusCode;
+use sqlx::{Connection, SqliteConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn signup_source_is_stored_with_the_subscription() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/subscriptions", &app.addr))
+        .header(
+            "Referer",
+            "https://example.com/blog/post?utm_source=mastodon&utm_campaign=launch",
+        )
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("referrer", "https://social.example/@ursula"),
+            ("utm_medium", "social"),
+            ("form_id", "footer"),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    let saved = sqlx::query!(
+        "SELECT referrer, utm_source, utm_medium, utm_campaign, utm_term,
+            landing_page, form_id
+        FROM subscriptions"
+    )
+    .fetch_one(&mut connection)
+    .await
+    .unwrap();
+    assert_eq!(
+        saved.referrer.as_deref(),
+        Some("https://social.example/@ursula")
+    );
+    assert_eq!(saved.utm_source.as_deref(), Some("mastodon"));
+    assert_eq!(saved.utm_medium.as_deref(), Some("social"));
+    assert_eq!(saved.utm_campaign.as_deref(), Some("launch"));
+    assert_eq!(saved.utm_term, None);
+    assert!(saved
+        .landing_page
+        .unwrap()
+        .starts_with("https://example.com/blog/post"));
+    assert_eq!(saved.form_id.as_deref(), Some("footer"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn conversion_is_reported_by_source() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions
+            (id, email, name, subscribed_at, status, utm_source, form_id)
+        VALUES
+            ('a', 'a@example.com', 'A', '2024-01-01 00:00:00', 'confirmed',
+                'mastodon', 'footer'),
+            ('b', 'b@example.com', 'B', '2024-01-01 00:00:00',
+                'pending_confirmation', 'mastodon', 'footer'),
+            ('c', 'c@example.com', 'C', '2024-01-01 00:00:00', 'confirmed',
+                NULL, 'sidebar'),
+            ('d', 'd@example.com', 'D', '2024-01-01 00:00:00', 'unsubscribed',
+                'mastodon', 'footer');
+        INSERT INTO subscriber_status_history
+            (subscriber_id, from_status, to_status, changed_at)
+        VALUES
+            ('d', 'pending_confirmation', 'confirmed', '2024-01-02 00:00:00'),
+            ('d', 'confirmed', 'unsubscribed', '2024-02-01 00:00:00');
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .unwrap();
+
+    let html = app.get_admin("/admin/sources").await.text().await.unwrap();
+    assert!(html.contains(
+        "<tr><td>mastodon</td><td>3</td><td>2</td><td>66.7%</td></tr>"
+    ));
+    assert!(html.contains(
+        "<tr><td><i>unknown</i></td><td>1</td><td>1</td><td>100.0%</td></tr>"
+    ));
+
+    let html = app
+        .get_admin("/admin/sources?by=form_id")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains(
+        "<tr><td>sidebar</td><td>1</td><td>1</td><td>100.0%</td></tr>"
+    ));
+
+    let resp = app.get_admin("/admin/sources?by=password_hash").await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,251 @@
+use crate::helpers::{cleanup_test_db, spawn_app_with_settings, TestApp};
+use axum::http::StatusCode;
+use secrecy::Secret;
+use sqlx::{Connection, SqliteConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod_axum::bot_protection::issue_form_token;
+use zero2prod_axum::settings::{read_settings_file, CaptchaSettings};
+use zero2prod_axum::startup::HmacSecret;
+
+fn form_token(seconds_ago: i64) -> String {
+    let secret = HmacSecret(read_settings_file().unwrap().hmac_secret);
+    issue_form_token(chrono::Utc::now().timestamp() - seconds_ago, &secret)
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM subscriptions"#)
+        .fetch_one(&mut connection)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_silently_dropped() {
+    let app = spawn_app_with_settings(|_| {}).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com\
+            &website=http%3A%2F%2Fspam.example.com"
+                .into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_missing_form_token_is_rejected_when_required() {
+    let app = spawn_app_with_settings(|s| {
+        s.bot_protection.require_form_token = true;
+    })
+    .await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn submitting_the_form_too_fast_is_rejected() {
+    let app = spawn_app_with_settings(|s| {
+        s.bot_protection.require_form_token = true;
+        s.bot_protection.min_submit_seconds = 3;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    let too_fast = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token(0)
+        ))
+        .await;
+    let human_speed = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token(10)
+        ))
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, too_fast.status().as_u16());
+    assert_eq!(StatusCode::OK, human_speed.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_forged_form_token_is_rejected() {
+    let app = spawn_app_with_settings(|_| {}).await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com\
+            &form_token=1600000000.deadbeef"
+                .into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn too_many_attempts_from_one_ip_are_rate_limited() {
+    let app = spawn_app_with_settings(|s| {
+        s.bot_protection.max_signups_per_ip = 2;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    let mut statuses = Vec::new();
+    for i in 0..3 {
+        let resp = app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula{i}%40example{i}.com"
+            ))
+            .await;
+        statuses.push(resp.status().as_u16());
+    }
+
+    assert_eq!(statuses, vec![200, 200, 429]);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn too_many_attempts_for_one_domain_are_rate_limited() {
+    let app = spawn_app_with_settings(|s| {
+        s.bot_protection.max_signups_per_domain = 1;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    let first = app
+        .post_subscriptions("name=a&email=a%40victim.com".into())
+        .await;
+    let same_domain = app
+        .post_subscriptions("name=b&email=b%40VICTIM.com".into())
+        .await;
+    let other_domain = app
+        .post_subscriptions("name=c&email=c%40example.com".into())
+        .await;
+
+    assert_eq!(StatusCode::OK, first.status().as_u16());
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, same_domain.status().as_u16());
+    assert_eq!(StatusCode::OK, other_domain.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn captcha_responses_are_verified_with_the_provider() {
+    let captcha_server = MockServer::start().await;
+    let verify_url = format!("{}/siteverify", captcha_server.uri());
+    let app = spawn_app_with_settings(|s| {
+        s.bot_protection.captcha = CaptchaSettings::Http {
+            verify_url,
+            secret: Secret::new("captcha-secret".into()),
+        };
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(wiremock::matchers::body_string_contains("response=good"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "success": true })),
+        )
+        .mount(&captcha_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(wiremock::matchers::body_string_contains("response=bad"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "success": false })),
+        )
+        .mount(&captcha_server)
+        .await;
+
+    let missing = app
+        .post_subscriptions("name=a&email=a%40example.com".into())
+        .await;
+    let bad = app
+        .post_subscriptions(
+            "name=a&email=a%40example.com&h-captcha-response=bad".into(),
+        )
+        .await;
+    let good = app
+        .post_subscriptions(
+            "name=a&email=a%40example.com&h-captcha-response=good".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, missing.status().as_u16());
+    assert_eq!(StatusCode::BAD_REQUEST, bad.status().as_u16());
+    assert_eq!(StatusCode::OK, good.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,142 @@
+use crate::helpers::{cleanup_test_db, spawn_app};
+use axum::http::StatusCode;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn unknown_emails_get_the_same_answer_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app.post_data_export("nobody@example.com").await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn the_emailed_link_downloads_the_subscriber_data() {
+    let app = spawn_app().await;
+    app.create_subscriber().await;
+
+    let resp = app.post_data_export("Ursula_Le_Guin@Gmail.com").await;
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let links = app.get_confirmation_links(&requests[1]);
+    assert_eq!(links.html.path(), "/data-export/download");
+
+    let resp = reqwest::get(links.html).await.unwrap();
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert!(resp.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let export: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(export["subscription"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["confirmation_tokens"].as_array().unwrap().len(), 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_tampered_download_link_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+    app.create_subscriber().await;
+    app.post_data_export("ursula_le_guin@gmail.com").await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let mut link = app.get_confirmation_links(&requests[1]).html;
+
+    let query: Vec<(String, String)> = link
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if k == "expires" {
+                "9999999999".into()
+            } else {
+                v
+            };
+            (k.into_owned(), v.into_owned())
+        })
+        .collect();
+    link.query_pairs_mut().clear().extend_pairs(query);
+
+    let resp = reqwest::get(link).await.unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn admins_can_export_a_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = app.create_subscriber().await.id;
+    let export_path = format!("/admin/subscribers/{subscriber_id}/export");
+
+    let resp = reqwest::get(format!("http://{}{}", app.addr, export_path))
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    let resp = app.get_admin("/admin/subscribers").await;
+    assert!(resp.text().await.unwrap().contains(&export_path));
+
+    let resp = app.get_admin(&export_path).await;
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let export: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(export["subscription"]["id"], subscriber_id.as_str());
+
+    let resp = app
+        .get_admin(&format!(
+            "/admin/subscribers/{}/export",
+            uuid::Uuid::new_v4()
+        ))
+        .await;
+    assert_eq!(StatusCode::NOT_FOUND, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn data_exports_are_emailed_once_per_window() {
+    let app = spawn_app().await;
+    app.create_subscriber().await;
+
+    for email in ["ursula_le_guin@gmail.com", "Ursula_Le_Guin@Gmail.com"] {
+        let resp = app.post_data_export(email).await;
+        assert_eq!(StatusCode::OK, resp.status().as_u16());
+    }
+
+    // The confirmation email and a single export email
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,159 @@
+use crate::helpers::{cleanup_test_db, spawn_app, spawn_app_with_settings};
+use axum::http::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn disposable_domains_are_rejected_and_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=bot&email=someone%40Mail.Mailinator.com".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    assert!(resp.text().await.unwrap().contains("are not accepted"));
+
+    let metrics = app.get_admin("/metrics").await.text().await.unwrap();
+    assert!(metrics.contains(
+        r#"subscriptions_blocked_domains_total{reason="disposable"} 1"#
+    ));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn metrics_require_an_admin() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!("http://{}/metrics", app.addr))
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn disposable_domains_are_accepted_when_blocking_is_disabled() {
+    let app = spawn_app_with_settings(|s| {
+        s.domain_policy.block_disposable = false;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions("name=bot&email=someone%40mailinator.com".into())
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn blocklisted_domains_from_the_admin_area_are_rejected() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_admin_form(
+            "/admin/domains",
+            &[("domain", "Spammy.Example"), ("rule", "block")],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let resp = app
+        .post_subscriptions("name=a&email=a%40spammy.example".into())
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    let page = app.get_admin("/admin/domains").await.text().await.unwrap();
+    assert!(page.contains("spammy.example"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn allowlisted_domains_override_the_disposable_list() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_admin_form(
+        "/admin/domains",
+        &[("domain", "mailinator.com"), ("rule", "allow")],
+    )
+    .await;
+    let allowed = app
+        .post_subscriptions("name=a&email=a%40mailinator.com".into())
+        .await;
+    app.post_admin_form(
+        "/admin/domains/delete",
+        &[("domain", "mailinator.com")],
+    )
+    .await;
+    let blocked = app
+        .post_subscriptions("name=b&email=b%40mailinator.com".into())
+        .await;
+
+    assert_eq!(StatusCode::OK, allowed.status().as_u16());
+    assert_eq!(StatusCode::BAD_REQUEST, blocked.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn admin_pages_require_authentication() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::Client::new()
+        .get(format!("http://{}/admin/domains", app.addr))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+    assert_eq!(r#"Basic realm="admin""#, resp.headers()["WWW-Authenticate"]);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
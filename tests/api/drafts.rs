@@ -0,0 +1,184 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Store a draft through the API and return its ID
+async fn create_draft(app: &TestApp) -> String {
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Work in progress",
+            "content": {
+                "text": "Hi {{ name }}",
+                "html": "<p>Hi {{ name }}</p>",
+            },
+            "draft": true,
+        }))
+        .await;
+    assert_eq!(StatusCode::CREATED, resp.status().as_u16());
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["issue_id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn drafts_are_only_sent_once_published() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = create_draft(&app).await;
+
+    assert_eq!(app.issue_status(&issue_id).await, "draft");
+    assert_eq!(app.queued_deliveries().await, 0);
+    assert_eq!(app.enqueue_due_issues().await, 0);
+
+    let resp = app
+        .post_admin_form(
+            &format!("/admin/issues/{issue_id}/draft"),
+            &[
+                ("title", "Finished"),
+                ("html_content", "<p>Done</p>"),
+                ("text_content", "Done"),
+                ("send_at", ""),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let resp = app
+        .post_admin_form(&format!("/admin/issues/{issue_id}/publish"), &[])
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(app.issue_status(&issue_id).await, "published");
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(body["Subject"], "Finished");
+    assert_eq!(body["TextBody"], "Done");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn only_drafts_can_be_edited_or_published() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    let resp = app
+        .post_admin_form(&format!("/admin/issues/{issue_id}/publish"), &[])
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let resp = app
+        .post_admin_form(&format!("/admin/issues/{issue_id}/publish"), &[])
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    let resp = app
+        .post_admin_form(
+            &format!("/admin/issues/{issue_id}/draft"),
+            &[
+                ("title", "Too late"),
+                ("html_content", "<p>Done</p>"),
+                ("text_content", "Done"),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn drafts_can_be_previewed() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+
+    let resp = app
+        .get_admin(&format!("/admin/issues/{issue_id}/preview"))
+        .await;
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert_eq!(resp.text().await.unwrap(), "<p>Hi Subscriber</p>");
+
+    let resp = app
+        .get_admin(&format!("/admin/issues/{issue_id}/preview/text"))
+        .await;
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    assert_eq!(resp.text().await.unwrap(), "Hi Subscriber");
+
+    let html = app
+        .get_admin(&format!("/admin/issues/{issue_id}"))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("Edit draft"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_the_given_addresses() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_admin_form(
+            &format!("/admin/issues/{issue_id}/test"),
+            &[("recipients", "ged@example.com,\ntenar@example.com")],
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let requests = app.email_server.received_requests().await.unwrap();
+    let sent: Vec<serde_json::Value> = requests[requests.len() - 2..]
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    assert_eq!(sent[0]["To"], "ged@example.com");
+    assert_eq!(sent[1]["To"], "tenar@example.com");
+    assert_eq!(sent[0]["Subject"], "[Test] Work in progress");
+    assert_eq!(app.issue_status(&issue_id).await, "draft");
+    assert_eq!(app.queued_deliveries().await, 0);
+
+    let resp = app
+        .post_admin_form(
+            &format!("/admin/issues/{issue_id}/test"),
+            &[("recipients", "not an address")],
+        )
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
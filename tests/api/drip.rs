@@ -0,0 +1,271 @@
+use crate::helpers::{
+    cleanup_test_db, spawn_app, spawn_app_with_settings, TestApp,
+};
+use axum::http::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::domain::OptIn;
+
+async fn add_step(app: &TestApp, delay_days: &str, subject: &str) {
+    let resp = app
+        .post_admin_form(
+            "/admin/drip",
+            &[
+                ("delay_days", delay_days),
+                ("subject", subject),
+                ("html_content", "<p>Hello</p>"),
+                ("text_content", "Hello"),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+}
+
+async fn subscribe_and_confirm(app: &TestApp) {
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+fn subjects(requests: &[wiremock::Request]) -> Vec<String> {
+    requests
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value =
+                serde_json::from_slice(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_receive_each_step_when_due() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    add_step(&app, "0", "Welcome aboard").await;
+    add_step(&app, "3", "Our best posts").await;
+
+    subscribe_and_confirm(&app).await;
+    assert_eq!(app.send_due_drip_emails().await, 1);
+    // The day 3 step is not due yet
+    assert_eq!(app.send_due_drip_emails().await, 0);
+
+    sqlx::query!(
+        "UPDATE drip_enrollments
+        SET enrolled_at = datetime('now', '-4 days')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(app.send_due_drip_emails().await, 1);
+    assert_eq!(app.send_due_drip_emails().await, 0);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(
+        subjects(&requests[1..]),
+        vec!["Welcome aboard", "Our best posts"]
+    );
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests[1].body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("/preferences?token="));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unconfirmed_subscribers_are_not_enrolled() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    add_step(&app, "0", "Welcome aboard").await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+
+    assert_eq!(app.send_due_drip_emails().await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unsubscribing_stops_the_sequence() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    add_step(&app, "0", "Welcome aboard").await;
+    add_step(&app, "3", "Our best posts").await;
+
+    subscribe_and_confirm(&app).await;
+    assert_eq!(app.send_due_drip_emails().await, 1);
+    sqlx::query!(
+        "UPDATE drip_enrollments
+        SET enrolled_at = datetime('now', '-4 days')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let token = sqlx::query!("SELECT preferences_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .preferences_token
+        .unwrap();
+    app.post_preferences(&[("token", &token), ("unsubscribe_all", "on")])
+        .await;
+
+    assert_eq!(app.send_due_drip_emails().await, 0);
+    let enrollment = sqlx::query!("SELECT stopped_at FROM drip_enrollments")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(enrollment.stopped_at.is_some());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn admin_page_lists_the_steps() {
+    let app = spawn_app().await;
+    add_step(&app, "7", "A week in").await;
+
+    let html = app.get_admin("/admin/drip").await.text().await.unwrap();
+    assert!(html.contains("<td>Day 7</td><td>A week in</td>"));
+
+    let resp = app
+        .post_admin_form(
+            "/admin/drip",
+            &[
+                ("delay_days", "1"),
+                ("subject", ""),
+                ("html_content", "x"),
+                ("text_content", "x"),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn the_welcome_email_replaces_the_day_0_step() {
+    let app = spawn_app_with_settings(|s| {
+        s.subscriptions.opt_in = OptIn::Single;
+        s.subscriptions.send_welcome_email = true;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    add_step(&app, "0", "Welcome aboard").await;
+    add_step(&app, "3", "Our best posts").await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    assert_eq!(app.send_due_drip_emails().await, 0);
+
+    sqlx::query!(
+        "UPDATE drip_enrollments
+        SET enrolled_at = datetime('now', '-4 days')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(app.send_due_drip_emails().await, 1);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(subjects(&requests), vec!["Welcome!", "Our best posts"]);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn the_footer_is_in_the_subscriber_language() {
+    let app = spawn_app_with_settings(|s| {
+        s.subscriptions.opt_in = OptIn::Single;
+        s.subscriptions.send_welcome_email = false;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    add_step(&app, "0", "Bienvenue").await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr".into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    assert_eq!(app.send_due_drip_emails().await, 1);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests[0].body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Gérer vos préférences"));
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Gérer vos préférences"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,146 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+
+async fn post_erase(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/preferences/erase", &app.addr))
+        .form(&[("token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn erasure_removes_every_row_and_keeps_only_a_hash() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+    sqlx::query!(
+        "INSERT INTO lists (id, slug, name) VALUES ('l1', 'rust', 'Rust news')",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.post_preferences(&[("token", &token), ("list", "l1")])
+        .await;
+
+    let resp = post_erase(&app, &token).await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let remaining = sqlx::query!(
+        r#"SELECT
+            (SELECT count(*) FROM subscriptions) AS "subscriptions!: i64",
+            (SELECT count(*) FROM subscription_tokens) AS "tokens!: i64",
+            (SELECT count(*) FROM subscription_lists) AS "lists!: i64",
+            (SELECT count(*) FROM preference_changes) AS "changes!: i64"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.lists, 0);
+    assert_eq!(remaining.changes, 0);
+
+    let suppressed = sqlx::query!("SELECT email_hash FROM suppressed_emails")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressed.len(), 1);
+    assert!(!suppressed[0].email_hash.contains("ursula"));
+
+    let history = sqlx::query!(
+        "SELECT from_status, to_status FROM subscriber_status_history"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(
+        history[0].from_status.as_deref(),
+        Some("pending_confirmation")
+    );
+    assert_eq!(history[0].to_status, "erased");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn an_erased_address_is_quietly_not_subscribed_again() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+    post_erase(&app, &token).await.error_for_status().unwrap();
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=Ursula_Le_Guin%40Gmail.com".into(),
+        )
+        .await;
+
+    // Same answer as any other signup, without storing or sending
+    // anything
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let subscribers = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+    // Only the confirmation email sent before erasure
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn erasure_with_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let resp = post_erase(&app, "not-a-real-token").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn admins_can_erase_a_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = app.create_subscriber().await.id;
+    let erase_path = format!("/admin/subscribers/{subscriber_id}/erase");
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}{}", app.addr, erase_path))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    let resp = app.post_admin_form(&erase_path, &[]).await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let subscribers = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+
+    let resp = app.post_admin_form(&erase_path, &[]).await;
+    assert_eq!(StatusCode::NOT_FOUND, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
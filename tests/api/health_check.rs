@@ -16,8 +16,9 @@ async fn health_check_success() {
     assert!(resp.status().is_success());
     assert_eq!(resp.content_length(), Some(0));
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
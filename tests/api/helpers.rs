@@ -6,9 +6,17 @@ use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
 use std::net::SocketAddr;
 use std::{fs::remove_file, str::FromStr};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod_axum::settings::read_settings_file;
-use zero2prod_axum::startup::Application;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod_axum::email_client::EmailClient;
+use zero2prod_axum::issue_delivery_worker::{
+    try_execute_task, ExecutionOutcome,
+};
+use zero2prod_axum::newsletter_issues::enqueue_due_issues;
+use zero2prod_axum::settings::{
+    read_settings_file, AppSettings, DeliverySettings,
+};
+use zero2prod_axum::startup::{Application, HmacSecret};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
@@ -42,12 +50,23 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+/// A subscriber signed up through the public API
+pub struct TestSubscriber {
+    pub id: String,
+    pub preferences_token: String,
+}
+
 pub struct TestApp {
     pub addr: SocketAddr,
     pub port: u16,
     pub db_name: String,
     pub email_server: MockServer,
     pub test_user: TestUser,
+    pub db_pool: SqlitePool,
+    pub email_client: EmailClient,
+    pub base_url: String,
+    pub hmac_secret: HmacSecret,
+    pub delivery: DeliverySettings,
 }
 
 impl TestApp {
@@ -90,6 +109,197 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    /// Sign up without confirming, through the public API. Emails sent
+    /// afterwards are accepted too.
+    pub async fn create_subscriber(&self) -> TestSubscriber {
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&self.email_server)
+            .await;
+        self.post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+
+        let saved = sqlx::query!(
+            r#"SELECT id AS "id!", preferences_token FROM subscriptions"#
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+        TestSubscriber {
+            id: saved.id,
+            preferences_token: saved.preferences_token.unwrap(),
+        }
+    }
+
+    /// Sign up and follow the confirmation link, through the public API
+    pub async fn create_confirmed_subscriber(&self) {
+        self.create_confirmed_subscriber_as(
+            "le guin",
+            "ursula_le_guin@gmail.com",
+        )
+        .await
+    }
+
+    pub async fn create_confirmed_subscriber_as(
+        &self,
+        name: &str,
+        email: &str,
+    ) {
+        let body = format!(
+            "name={}&email={}",
+            urlencoding::encode(name),
+            urlencoding::encode(email)
+        );
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+        let requests = self.email_server.received_requests().await.unwrap();
+        let links = self.get_confirmation_links(requests.last().unwrap());
+        reqwest::get(links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub async fn get_preferences(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("http://{}/preferences", &self.addr))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_preferences(
+        &self,
+        body: &[(&str, &str)],
+    ) -> reqwest::Response {
+        // Do not follow the redirect back to the preferences page so
+        // tests can assert on it
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .post(format!("http://{}/preferences", &self.addr))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_export(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("http://{}/data-export", &self.addr))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// GET an admin page as the test user
+    pub async fn get_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("http://{}{}", &self.addr, path))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// POST a form to an admin page as the test user, without following
+    /// the redirect
+    pub async fn post_admin_form(
+        &self,
+        path: &str,
+        body: &[(&str, &str)],
+    ) -> reqwest::Response {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .post(format!("http://{}{}", &self.addr, path))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Run the drip scheduler once instead of waiting for its interval
+    pub async fn send_due_drip_emails(&self) -> usize {
+        zero2prod_axum::drip::send_due_drip_emails(
+            &self.db_pool,
+            &self.email_client,
+            &self.base_url,
+        )
+        .await
+        .expect("Failed to send due drip emails.")
+    }
+
+    /// Drain the delivery queue instead of waiting on the worker
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+                &self.delivery,
+            )
+            .await
+            .expect("Failed to execute a delivery task.")
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn issue_status(&self, issue_id: &str) -> String {
+        sqlx::query!(
+            "SELECT status FROM newsletter_issues WHERE id = $1",
+            issue_id
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .unwrap()
+        .status
+    }
+
+    /// Deliveries in the queue, whatever their status
+    pub async fn queued_deliveries(&self) -> i64 {
+        sqlx::query!(
+            r#"SELECT count(*) AS "n!: i64" FROM issue_delivery_queue"#
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .unwrap()
+        .n
+    }
+
+    pub async fn enqueue_due_issues(&self) -> usize {
+        enqueue_due_issues(&self.db_pool)
+            .await
+            .expect("Failed to enqueue due issues.")
+    }
+
     pub async fn post_newsletter(
         &self,
         body: serde_json::Value,
@@ -156,6 +366,16 @@ impl TestUser {
 /// example, Django. This allows us to change the backend implementation
 /// but still use the testing pipline here as needed.
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with_settings(|_| {}).await
+}
+
+/// spawn_app_with_settings
+///
+/// Same as `spawn_app`, but lets a test tweak the settings before the
+/// app is built.
+pub async fn spawn_app_with_settings(
+    configure: impl FnOnce(&mut AppSettings),
+) -> TestApp {
     Lazy::force(&TRACING);
 
     let db_conn = create_connect_test_db()
@@ -175,6 +395,14 @@ pub async fn spawn_app() -> TestApp {
         // Otherwise all bound to same port and tests complain about used
         // port number.
         settings.port = 0u16;
+        // Deliveries are dispatched and due issues queued by the tests,
+        // keep the background tasks idle
+        settings.delivery.poll_interval_seconds = 3600;
+        settings.scheduling.poll_interval_seconds = 3600;
+        // Most tests post to the subscription form directly, those
+        // covering the form token turn it back on
+        settings.bot_protection.require_form_token = false;
+        configure(&mut settings);
         settings
     };
 
@@ -189,8 +417,13 @@ pub async fn spawn_app() -> TestApp {
         db_name: db_conn.db_name,
         email_server,
         test_user: TestUser::generate(),
+        db_pool: db_conn.pool,
+        email_client: app_settings.email_client.client(),
+        base_url: app_settings.normalized_base_url().unwrap().into(),
+        hmac_secret: HmacSecret(app_settings.hmac_secret.clone()),
+        delivery: app_settings.delivery.clone(),
     };
-    test_app.test_user.store(&db_conn.pool).await;
+    test_app.test_user.store(&test_app.db_pool).await;
 
     test_app
 }
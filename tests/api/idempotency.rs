@@ -0,0 +1,167 @@
+use crate::helpers::{
+    cleanup_test_db, spawn_app, spawn_app_with_settings, TestApp,
+};
+use axum::http::StatusCode;
+use uuid::Uuid;
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"text": "Plain body", "html": "<p>HTML body</p>"}
+    })
+}
+
+async fn post_newsletter_with_key(
+    app: &TestApp,
+    body: serde_json::Value,
+    key: &str,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/newsletters", &app.addr))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("Idempotency-Key", key)
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn issue_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!: i64" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn retried_requests_get_the_saved_response() {
+    let app = spawn_app().await;
+    let key = Uuid::new_v4().to_string();
+
+    let first = post_newsletter_with_key(&app, newsletter_body(), &key).await;
+    let first_status = first.status();
+    let first_body = first.text().await.unwrap();
+    let second = post_newsletter_with_key(&app, newsletter_body(), &key).await;
+
+    assert_eq!(StatusCode::ACCEPTED, first_status.as_u16());
+    assert_eq!(first_status, second.status());
+    assert_eq!(second.headers()["Content-Type"], "application/json");
+    assert_eq!(first_body, second.text().await.unwrap());
+    assert_eq!(issue_count(&app).await, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn different_keys_publish_different_issues() {
+    let app = spawn_app().await;
+
+    for key in ["first", "second"] {
+        let resp = post_newsletter_with_key(&app, newsletter_body(), key).await;
+        assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    }
+
+    assert_eq!(issue_count(&app).await, 2);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn duplicates_of_an_in_flight_request_are_rejected_with_a_409() {
+    let app = spawn_app().await;
+    // The first request reserved the key and is still running
+    let user_id = app.test_user.user_id.to_string();
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, 'in-flight', datetime('now'))
+        "#,
+        user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let resp =
+        post_newsletter_with_key(&app, newsletter_body(), "in-flight").await;
+
+    assert_eq!(StatusCode::CONFLICT, resp.status().as_u16());
+    assert_eq!(issue_count(&app).await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn keys_are_forgotten_once_expired() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.idempotency.key_ttl_hours = 0;
+    })
+    .await;
+
+    for _ in 0..2 {
+        let resp =
+            post_newsletter_with_key(&app, newsletter_body(), "key").await;
+        assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    }
+
+    assert_eq!(issue_count(&app).await, 2);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn failed_requests_do_not_use_up_their_key() {
+    let app = spawn_app().await;
+    let mut invalid = newsletter_body();
+    invalid["translations"] = serde_json::json!({
+        "tlh": {"title": "Qapla'", "content": {"text": "a", "html": "a"}}
+    });
+
+    let resp = post_newsletter_with_key(&app, invalid, "key").await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    let resp = post_newsletter_with_key(&app, newsletter_body(), "key").await;
+
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    assert_eq!(issue_count(&app).await, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn invalid_keys_are_rejected() {
+    let app = spawn_app().await;
+
+    let resp =
+        post_newsletter_with_key(&app, newsletter_body(), &"a".repeat(51))
+            .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    assert_eq!(issue_count(&app).await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
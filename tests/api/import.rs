@@ -0,0 +1,154 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use sqlx::{Connection, SqliteConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const CSV: &str = "Full Name,E-mail,Source\n\
+    Ursula Le Guin,ursula@example.com,old tool\n\
+    Octavia Butler,octavia@example.com,old tool\n\
+    Bad Row,not-an-email,old tool\n\
+    Ursula Again,URSULA@example.com,old tool\n";
+
+async fn post_import(
+    app: &TestApp,
+    mode: &str,
+    consent_note: &str,
+) -> reqwest::Response {
+    let form = reqwest::multipart::Form::new()
+        .text("mode", mode.to_string())
+        .text("name_column", "full name")
+        .text("email_column", "e-mail")
+        .text("consent_note", consent_note.to_string())
+        .part(
+            "file",
+            reqwest::multipart::Part::text(CSV).file_name("contacts.csv"),
+        );
+    reqwest::Client::new()
+        .post(format!("http://{}/admin/import", &app.addr))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn saved_subscribers(app: &TestApp) -> Vec<(String, String)> {
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&mut connection)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn a_dry_run_reports_row_errors_and_stores_nothing() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = post_import(&app, "dry_run", "").await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("Would import 2 of 4 rows."));
+    assert!(html.contains("<td>4</td>"));
+    assert!(html.contains("is already subscribed"));
+    assert!(html.contains("<td>5</td>"));
+    assert!(saved_subscribers(&app).await.is_empty());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_pending_import_sends_confirmations() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = post_import(&app, "pending", "").await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![
+            ("octavia@example.com".into(), "pending_confirmation".into()),
+            ("ursula@example.com".into(), "pending_confirmation".into()),
+        ]
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_confirmed_import_requires_a_consent_note() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = post_import(&app, "confirmed", " ").await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    assert!(saved_subscribers(&app).await.is_empty());
+
+    let resp = post_import(&app, "confirmed", "Opted in on the old tool").await;
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    let saved = sqlx::query!("SELECT status, consent_note FROM subscriptions")
+        .fetch_all(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "confirmed"
+        && s.consent_note.as_deref() == Some("Opted in on the old tool")));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn importing_requires_authentication() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/import", &app.addr))
+        .multipart(reqwest::multipart::Form::new().text("mode", "pending"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,463 @@
+use crate::helpers::{
+    cleanup_test_db, spawn_app, spawn_app_with_settings, TestApp,
+};
+use axum::http::StatusCode;
+use wiremock::matchers::{any, body_partial_json, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_issue(app: &TestApp) {
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Plain body", "html": "<p>HTML body</p>"}
+        }))
+        .await;
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+}
+
+async fn delivery_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT s.email, q.status FROM issue_delivery_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        ORDER BY s.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, r.status))
+    .collect()
+}
+
+async fn issue_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn publishing_only_queues_the_issue() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    let sent_before = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+
+    let sent = app.email_server.received_requests().await.unwrap().len();
+    assert_eq!(sent, sent_before);
+    assert_eq!(
+        delivery_statuses(&app).await,
+        vec![("ursula@example.com".into(), "queued".into())]
+    );
+    assert_eq!(issue_status(&app).await, "publishing");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_failed_recipient_does_not_stop_the_others() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber_as("Amelie", "amelie@example.com")
+        .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(body_partial_json(serde_json::json!({
+            "To": "amelie@example.com"
+        })))
+        // Invalid recipient, not worth retrying
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(body_partial_json(serde_json::json!({
+            "To": "ursula@example.com"
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        delivery_statuses(&app).await,
+        vec![
+            ("amelie@example.com".into(), "failed".into()),
+            ("ursula@example.com".into(), "sent".into()),
+        ]
+    );
+    let error = sqlx::query!(
+        "SELECT error FROM issue_delivery_queue WHERE status = 'failed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .error;
+    assert!(error.is_some());
+    assert_eq!(issue_status(&app).await, "published");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn recipients_with_an_invalid_locale_get_the_default_one() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    sqlx::query!("UPDATE subscriptions SET locale = 'klingon'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(body_partial_json(serde_json::json!({
+            "To": "ursula@example.com",
+            "Subject": "Newsletter title"
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        delivery_statuses(&app).await,
+        vec![("ursula@example.com".into(), "sent".into())]
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn subscribers_who_left_after_publishing_are_skipped() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        delivery_statuses(&app).await,
+        vec![("ursula@example.com".into(), "skipped".into())]
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn claims_are_only_taken_over_once_they_time_out() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+
+    // Another worker is sending it
+    sqlx::query!(
+        r#"UPDATE issue_delivery_queue
+        SET status = 'sending', claimed_at = datetime('now')"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        delivery_statuses(&app).await,
+        vec![("ursula@example.com".into(), "sending".into())]
+    );
+
+    // That worker died
+    sqlx::query!(
+        r#"UPDATE issue_delivery_queue
+        SET claimed_at = datetime('now', '-1 day')"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        delivery_statuses(&app).await,
+        vec![("ursula@example.com".into(), "sent".into())]
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn issues_without_recipients_are_published_straight_away() {
+    let app = spawn_app().await;
+
+    publish_issue(&app).await;
+
+    assert_eq!(issue_status(&app).await, "published");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+async fn attempts(app: &TestApp) -> (String, i64, Option<String>) {
+    let row = sqlx::query!(
+        "SELECT status, n_attempts, execute_after FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    (row.status, row.n_attempts, row.execute_after)
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.delivery.base_backoff_seconds = 0;
+    })
+    .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let (status, n_attempts, _) = attempts(&app).await;
+    assert_eq!(status, "sent");
+    assert_eq!(n_attempts, 2);
+    assert_eq!(issue_status(&app).await, "published");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn retries_stop_after_the_maximum_attempts() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.delivery.base_backoff_seconds = 0;
+        settings.delivery.max_attempts = 3;
+    })
+    .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let (status, n_attempts, _) = attempts(&app).await;
+    assert_eq!(status, "failed");
+    assert_eq!(n_attempts, 3);
+    assert_eq!(issue_status(&app).await, "failed");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn deliveries_that_fail_before_sending_are_retried_then_failed() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.delivery.base_backoff_seconds = 0;
+        settings.delivery.max_attempts = 3;
+    })
+    .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    sqlx::query!("UPDATE newsletter_issues SET translations = 'not json'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let (status, n_attempts, _) = attempts(&app).await;
+    assert_eq!(status, "failed");
+    assert_eq!(n_attempts, 3);
+    assert_eq!(issue_status(&app).await, "failed");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn rate_limited_deliveries_wait_as_asked() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.delivery.base_backoff_seconds = 0;
+    })
+    .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(
+            ResponseTemplate::new(429).insert_header("Retry-After", "3600"),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let (status, n_attempts, execute_after) = attempts(&app).await;
+    assert_eq!(status, "queued");
+    assert_eq!(n_attempts, 1);
+    let waiting = sqlx::query!(
+        r#"SELECT datetime($1) > datetime('now', '+59 minutes') AS "later!: bool""#,
+        execute_after,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .later;
+    assert!(waiting);
+    assert_eq!(issue_status(&app).await, "publishing");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn huge_retry_after_values_are_capped() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.delivery.base_backoff_seconds = 0;
+        settings.delivery.max_backoff_seconds = 60;
+    })
+    .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(
+            ResponseTemplate::new(429)
+                .insert_header("Retry-After", u64::MAX.to_string()),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let (status, n_attempts, execute_after) = attempts(&app).await;
+    assert_eq!(status, "queued");
+    assert_eq!(n_attempts, 1);
+    let capped = sqlx::query!(
+        r#"SELECT datetime($1) <= datetime('now', '+1 minutes') AS "capped!: bool""#,
+        execute_after,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .capped;
+    assert!(capped);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn permanent_failures_are_not_retried() {
+    let app = spawn_app_with_settings(|settings| {
+        settings.delivery.base_backoff_seconds = 0;
+    })
+    .await;
+    app.create_confirmed_subscriber_as("Ursula", "ursula@example.com")
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let (status, n_attempts, _) = attempts(&app).await;
+    assert_eq!(status, "failed");
+    assert_eq!(n_attempts, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
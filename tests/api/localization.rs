@@ -0,0 +1,302 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use sqlx::{Connection, SqliteConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscribe_with_language(
+    app: &TestApp,
+    accept_language: &str,
+) -> reqwest::Response {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    reqwest::Client::new()
+        .post(format!("http://{}/subscriptions", app.addr))
+        .header("Accept-Language", accept_language)
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn last_email(app: &TestApp) -> serde_json::Value {
+    let requests = app.email_server.received_requests().await.unwrap();
+    serde_json::from_slice(&requests.last().unwrap().body).unwrap()
+}
+
+#[tokio::test]
+async fn the_locale_is_detected_from_accept_language_at_signup() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+
+    subscribe_with_language(&app, "de-DE, fr-CA;q=0.9, en;q=0.5")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&mut connection)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.locale, "fr");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_sent_in_the_subscribers_language() {
+    let app = spawn_app().await;
+
+    subscribe_with_language(&app, "es").await;
+
+    let email = last_email(&app).await;
+    assert_eq!(email["Subject"], "¡Bienvenido!");
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("para confirmar tu suscripción"));
+    // The link survives the translation
+    let links = app.get_confirmation_links(
+        &app.email_server.received_requests().await.unwrap()[0],
+    );
+    assert_eq!(links.html, links.plain_text);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unsupported_languages_fall_back_to_english() {
+    let app = spawn_app().await;
+
+    subscribe_with_language(&app, "ja").await;
+
+    let email = last_email(&app).await;
+    assert_eq!(email["Subject"], "Welcome!");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn an_explicit_unsupported_locale_is_rejected() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=de".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn the_confirmation_page_uses_the_subscribers_language() {
+    let app = spawn_app().await;
+    subscribe_with_language(&app, "fr").await;
+    let links = app.get_confirmation_links(
+        &app.email_server.received_requests().await.unwrap()[0],
+    );
+
+    // The stored locale wins over the browser confirming the link
+    let html = reqwest::Client::new()
+        .get(links.html)
+        .header("Accept-Language", "es")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains(r#"<html lang="fr">"#));
+    assert!(html.contains("Abonnement confirmé"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unknown_confirmation_links_use_accept_language() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::Client::new()
+        .get(format!(
+            "http://{}/subscriptions/confirm?subscription_token=nope",
+            app.addr
+        ))
+        .header("Accept-Language", "es-MX")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Enlace de confirmación no válido"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_language_from_the_preferences_page() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    subscribe_with_language(&app, "en").await;
+    let token = sqlx::query!("SELECT preferences_token FROM subscriptions")
+        .fetch_one(&mut connection)
+        .await
+        .unwrap()
+        .preferences_token
+        .unwrap();
+
+    let resp = app
+        .post_preferences(&[("token", &token), ("locale", "es")])
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let html = app.get_preferences(&token).await.text().await.unwrap();
+    assert!(html.contains(r#"<html lang="es">"#));
+    assert!(html.contains("Guardar preferencias"));
+    let change = sqlx::query!(
+        "SELECT old_value, new_value FROM preference_changes
+        WHERE field = 'locale'"
+    )
+    .fetch_one(&mut connection)
+    .await
+    .expect("The language change was not recorded.");
+    assert_eq!(change.old_value.as_deref(), Some("en"));
+    assert_eq!(change.new_value.as_deref(), Some("es"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn newsletters_are_sent_in_each_subscribers_language() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for (name, email, locale) in [
+        ("amelie", "amelie@example.com", "fr"),
+        ("jorge", "jorge@example.com", "es"),
+    ] {
+        app.post_subscriptions(format!(
+            "name={name}&email={email}&locale={locale}"
+        ))
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&mut connection)
+        .await
+        .unwrap();
+    let sent_before = app.email_server.received_requests().await.unwrap().len();
+
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Plain body", "html": "<p>Html body</p>"},
+            "translations": {
+                "fr": {
+                    "title": "Titre de la newsletter",
+                    "content": {"text": "Corps", "html": "<p>Corps</p>"}
+                }
+            }
+        }))
+        .await;
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let subjects: std::collections::HashMap<String, String> = requests
+        [sent_before..]
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value =
+                serde_json::from_slice(&r.body).unwrap();
+            (
+                body["To"].as_str().unwrap().to_string(),
+                body["Subject"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    assert_eq!(subjects["amelie@example.com"], "Titre de la newsletter");
+    // No Spanish variant, the default is used
+    assert_eq!(subjects["jorge@example.com"], "Newsletter title");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn newsletter_variants_for_unknown_locales_are_rejected() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Plain body", "html": "<p>Html body</p>"},
+            "translations": {
+                "tlh": {
+                    "title": "Qapla'",
+                    "content": {"text": "Plain body", "html": "<p>Html body</p>"}
+                }
+            }
+        }))
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
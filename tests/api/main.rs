@@ -1,5 +1,25 @@
+mod attributes;
+mod attribution;
+mod bot_protection;
+mod data_export;
+mod domain_policy;
+mod drafts;
+mod drip;
+mod erasure;
 mod health_check;
 mod helpers;
+mod idempotency;
+mod import;
+mod issue_delivery;
+mod localization;
 mod newsletter;
+mod newsletter_issues;
+mod preferences;
+mod pruning;
+mod scheduling;
+mod signup_pages;
+mod subscriber_export;
+mod subscriber_status;
 mod subscriptions;
 mod subscriptions_confirm;
+mod templates;
@@ -84,13 +84,14 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
     let resp = app.post_newsletter(newsletter_request_body).await;
 
-    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
+    app.create_confirmed_subscriber().await;
 
     Mock::given(path("/email"))
         .respond_with(ResponseTemplate::new(200))
@@ -110,10 +111,57 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
     let resp = app.post_newsletter(newsletter_request_body).await;
 
-    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have sent the newsletter email
 }
 
+#[tokio::test]
+async fn newsletters_can_be_written_in_markdown() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let markdown = "# News\n\nRead [the post](https://example.com/post).";
+
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"markdown": markdown}
+        }))
+        .await;
+    assert_eq!(resp.status().as_u16(), 202);
+    // Explicit bodies take precedence
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"markdown": markdown, "text": "Plain override"}
+        }))
+        .await;
+    assert_eq!(resp.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let sent: Vec<serde_json::Value> = requests[requests.len() - 2..]
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    let html = sent[0]["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<h1>News</h1>"));
+    assert_eq!(
+        sent[0]["TextBody"],
+        "News\n====\n\nRead the post [1].\n\n[1] https://example.com/post"
+    );
+    assert!(sent[1]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<h1>News</h1>"));
+    assert_eq!(sent[1]["TextBody"], "Plain override");
+}
+
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     let app = spawn_app().await;
@@ -133,6 +181,13 @@ async fn newsletters_returns_400_for_invalid_data() {
             }),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {"html": "<p>Newsletter body as HTML</p>"}
+            }),
+            "missing text content",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -200,13 +255,3 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
 
     app.get_confirmation_links(email_request)
 }
-
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
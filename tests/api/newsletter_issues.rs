@@ -0,0 +1,193 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use uuid::Uuid;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Issue <1>",
+        "content": {
+            "text": "Plain body",
+            "html": "<p>HTML body</p>",
+        },
+        "translations": {
+            "fr": {
+                "title": "Numéro 1",
+                "content": { "text": "Corps", "html": "<p>Corps</p>" }
+            }
+        }
+    })
+}
+
+/// Publish an issue and return the ID from the response
+async fn publish_issue(app: &TestApp) -> Uuid {
+    let resp = app.post_newsletter(newsletter_body()).await;
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    let body: serde_json::Value = resp.json().await.unwrap();
+    Uuid::parse_str(body["issue_id"].as_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn publishing_stores_the_issue_and_returns_its_id() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_issue(&app).await.to_string();
+    app.dispatch_all_pending_emails().await;
+
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content, author_id, status,
+            published_at
+        FROM newsletter_issues WHERE id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "Issue <1>");
+    assert_eq!(issue.text_content, "Plain body");
+    assert_eq!(issue.html_content, "<p>HTML body</p>");
+    assert_eq!(issue.author_id, app.test_user.user_id.to_string());
+    assert_eq!(issue.status, "published");
+    assert!(issue.published_at.is_some());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn issues_nobody_could_be_sent_to_are_marked_failed() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue =
+        sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(issue.status, "failed");
+    assert_eq!(issue.published_at, None);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn past_issues_are_listed_in_the_admin_area() {
+    let app = spawn_app().await;
+    let issue_id = publish_issue(&app).await;
+
+    let list = app.get_admin("/admin/issues").await.text().await.unwrap();
+    assert!(list.contains(&format!(
+        r#"<a href="/admin/issues/{issue_id}">Issue &lt;1&gt;</a>"#
+    )));
+    assert!(list.contains(&app.test_user.username));
+
+    let resp = app.get_admin(&format!("/admin/issues/{issue_id}")).await;
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let detail = resp.text().await.unwrap();
+    assert!(detail.contains("&lt;p&gt;HTML body&lt;/p&gt;"));
+    assert!(detail.contains("Numéro 1 (fr)"));
+    assert!(detail.contains("published"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .get_admin(&format!("/admin/issues/{}", Uuid::new_v4()))
+        .await;
+
+    assert_eq!(StatusCode::NOT_FOUND, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn issues_require_an_admin() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!("http://{}/admin/issues", app.addr))
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn admin_forms_posted_from_another_site_are_rejected() {
+    let app = spawn_app().await;
+    let post = |origin: String| {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .post(format!("http://{}/admin/issues", app.addr))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .header("Origin", origin)
+            .form(&[
+                ("title", "Newsletter title"),
+                ("html_content", "<p>Body</p>"),
+                ("text_content", "Body"),
+                ("draft", "true"),
+            ])
+            .send()
+    };
+
+    let cross_site = post("https://evil.example.com".into()).await.unwrap();
+    let same_site = post(format!("http://{}", app.addr)).await.unwrap();
+
+    assert_eq!(StatusCode::FORBIDDEN, cross_site.status().as_u16());
+    assert_eq!(StatusCode::SEE_OTHER, same_site.status().as_u16());
+    let issues = sqlx::query!(
+        r#"SELECT count(*) AS "count!: i64" FROM newsletter_issues"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issues.count, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
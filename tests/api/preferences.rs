@@ -0,0 +1,193 @@
+use crate::helpers::{cleanup_test_db, spawn_app};
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn preferences_are_rejected_with_an_unknown_token() {
+    let app = spawn_app().await;
+
+    let resp = app.get_preferences("not-a-real-token").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn preferences_page_shows_the_subscription() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+    sqlx::query!(
+        "INSERT INTO lists (id, slug, name) VALUES ('l1', 'rust', 'Rust news')",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let resp = app.get_preferences(&token).await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("ursula_le_guin@gmail.com"));
+    assert!(html.contains("Rust news"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn changing_preferences_is_persisted_and_recorded() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+    sqlx::query!(
+        "INSERT INTO lists (id, slug, name) VALUES ('l1', 'rust', 'Rust news')",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let resp = app
+        .post_preferences(&[
+            ("token", &token),
+            ("name", "Ursula K. Le Guin"),
+            ("delivery_frequency", "weekly"),
+            ("list", "l1"),
+        ])
+        .await;
+
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let saved = sqlx::query!(
+        "SELECT name, delivery_frequency, status FROM subscriptions",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.delivery_frequency, "weekly");
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let lists = sqlx::query!("SELECT list_id FROM subscription_lists")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(lists.len(), 1);
+
+    let changes = sqlx::query!(
+        "SELECT field, changed_at FROM preference_changes ORDER BY id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let fields: Vec<_> = changes.iter().map(|c| c.field.as_str()).collect();
+    assert_eq!(fields, vec!["name", "delivery_frequency", "list:l1"]);
+    assert!(changes.iter().all(|c| !c.changed_at.is_empty()));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn an_invalid_name_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+
+    let resp = app
+        .post_preferences(&[("token", &token), ("name", "<script>")])
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "le guin");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unsubscribing_from_everything_clears_memberships() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+    sqlx::query!(
+        "INSERT INTO lists (id, slug, name) VALUES ('l1', 'rust', 'Rust news')",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.post_preferences(&[("token", &token), ("list", "l1")])
+        .await;
+
+    let resp = app
+        .post_preferences(&[
+            ("token", &token),
+            ("list", "l1"),
+            ("unsubscribe_all", "on"),
+        ])
+        .await;
+
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    let lists = sqlx::query!("SELECT list_id FROM subscription_lists")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(lists.is_empty());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn topics_added_from_the_admin_area_are_offered_to_subscribers() {
+    let app = spawn_app().await;
+    let token = app.create_subscriber().await.preferences_token;
+
+    let resp = app
+        .post_admin_form(
+            "/admin/topics",
+            &[
+                ("slug", "async"),
+                ("name", "Async Rust"),
+                ("description", "Futures and executors."),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let html = app.get_preferences(&token).await.text().await.unwrap();
+    assert!(html.contains("Async Rust"));
+    let html = app.get_admin("/admin/topics").await.text().await.unwrap();
+    assert!(html.contains("Futures and executors."));
+
+    let resp = app
+        .post_admin_form("/admin/topics", &[("slug", "async"), ("name", "")])
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,286 @@
+use crate::helpers::{cleanup_test_db, spawn_app_with_settings, TestApp};
+use axum::http::StatusCode;
+use reqwest::Url;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::settings::{AppSettings, PendingAction};
+
+fn prune_immediately(settings: &mut AppSettings) {
+    settings.pruning.pending_max_age_days = 0;
+    settings.pruning.inactive_after_issues = 1;
+    settings.pruning.reengagement_grace_days = 0;
+}
+
+async fn mock_email(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect()
+}
+
+/// First link of an email's HTML body going to `route`, pointed at the
+/// test server
+fn find_link(app: &TestApp, email: &serde_json::Value, route: &str) -> Url {
+    let html =
+        htmlescape::decode_html(email["HtmlBody"].as_str().unwrap()).unwrap();
+    let link = linkify::LinkFinder::new()
+        .links(&html)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains(route))
+        .unwrap_or_else(|| panic!("No {} link in {}", route, html));
+    let mut link = Url::parse(&link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn run_pruning(app: &TestApp) {
+    let resp = app.post_admin_form("/admin/pruning", &[]).await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+}
+
+async fn publish_issue(app: &TestApp) {
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Plain body",
+                "html": r#"<p><a href="https://example.com/post">Read</a></p>"#,
+            }
+        }))
+        .await;
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_are_reminded_once() {
+    let app = spawn_app_with_settings(prune_immediately).await;
+    mock_email(&app).await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    run_pruning(&app).await;
+    run_pruning(&app).await;
+
+    let emails = sent_emails(&app).await;
+    assert_eq!(emails.len(), 2);
+    // The reminder carries a fresh, working confirmation link
+    let reminder = &app.email_server.received_requests().await.unwrap()[1];
+    let links = app.get_confirmation_links(reminder);
+    let resp = reqwest::get(links.html).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_can_be_deleted_instead() {
+    let app = spawn_app_with_settings(|settings| {
+        prune_immediately(settings);
+        settings.pruning.pending_action = PendingAction::Delete;
+    })
+    .await;
+    mock_email(&app).await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    run_pruning(&app).await;
+
+    let remaining = sqlx::query!(
+        r#"SELECT
+            (SELECT count(*) FROM subscriptions) AS "subscriptions!: i64",
+            (SELECT count(*) FROM subscriber_status_history) AS "history!: i64""#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.history, 0);
+    let report = app.get_admin("/admin/pruning").await.text().await.unwrap();
+    assert!(
+        report.contains("<td>0</td><td>1</td><td>0</td><td>0</td><td>0</td>")
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn inactive_subscribers_are_asked_then_unsubscribed() {
+    let app = spawn_app_with_settings(prune_immediately).await;
+    mock_email(&app).await;
+    app.create_confirmed_subscriber().await;
+    publish_issue(&app).await;
+
+    run_pruning(&app).await;
+    let emails = sent_emails(&app).await;
+    assert_eq!(
+        emails.last().unwrap()["Subject"],
+        "Do you still want to hear from us?"
+    );
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+
+    // No answer within the grace period
+    run_pruning(&app).await;
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+    let report = app.get_admin("/admin/pruning").await.text().await.unwrap();
+    // Reminded, deleted, re-engaged, unsubscribed and failed
+    assert!(
+        report.contains("<td>0</td><td>0</td><td>0</td><td>1</td><td>0</td>")
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn answering_the_reengagement_email_keeps_the_subscription() {
+    let app = spawn_app_with_settings(prune_immediately).await;
+    mock_email(&app).await;
+    app.create_confirmed_subscriber().await;
+    publish_issue(&app).await;
+    run_pruning(&app).await;
+    let emails = sent_emails(&app).await;
+    let keep_link = find_link(&app, emails.last().unwrap(), "/engagement/keep");
+
+    let resp = reqwest::get(keep_link).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+
+    run_pruning(&app).await;
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(sent_emails(&app).await.len(), emails.len());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn opening_an_issue_counts_as_engagement() {
+    let app = spawn_app_with_settings(prune_immediately).await;
+    mock_email(&app).await;
+    app.create_confirmed_subscriber().await;
+    publish_issue(&app).await;
+    let emails = sent_emails(&app).await;
+    let pixel = find_link(&app, emails.last().unwrap(), "/engagement/open");
+
+    let resp = reqwest::get(pixel).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(resp.headers()["Content-Type"], "image/gif");
+
+    run_pruning(&app).await;
+    assert_eq!(sent_emails(&app).await.len(), emails.len());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn clicks_are_recorded_and_redirected() {
+    let app = spawn_app_with_settings(prune_immediately).await;
+    mock_email(&app).await;
+    app.create_confirmed_subscriber().await;
+    publish_issue(&app).await;
+    let emails = sent_emails(&app).await;
+    let click = find_link(&app, emails.last().unwrap(), "/engagement/click");
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let resp = client.get(click.clone()).send().await.unwrap();
+
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    assert_eq!(resp.headers()["Location"], "https://example.com/post");
+    let event = sqlx::query!("SELECT kind, url FROM engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.kind, "click");
+    assert_eq!(event.url.as_deref(), Some("https://example.com/post"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn click_links_to_other_destinations_are_rejected() {
+    let app = spawn_app_with_settings(prune_immediately).await;
+    mock_email(&app).await;
+    app.create_confirmed_subscriber().await;
+    publish_issue(&app).await;
+    let emails = sent_emails(&app).await;
+    let mut click =
+        find_link(&app, emails.last().unwrap(), "/engagement/click");
+    let tampered: Vec<(String, String)> = click
+        .query_pairs()
+        .map(|(k, v)| match k.as_ref() {
+            "url" => (k.into_owned(), "https://evil.example.com".to_string()),
+            _ => (k.into_owned(), v.into_owned()),
+        })
+        .collect();
+    click.query_pairs_mut().clear().extend_pairs(tampered);
+
+    let resp = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(click)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
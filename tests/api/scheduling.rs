@@ -0,0 +1,233 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Schedule an issue a day from now and return its ID
+async fn schedule_issue(app: &TestApp) -> String {
+    let send_at = (chrono::Utc::now() + chrono::Duration::days(1))
+        .with_timezone(&chrono::FixedOffset::east_opt(2 * 3600).unwrap())
+        .to_rfc3339();
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Later",
+            "content": {"text": "Plain body", "html": "<p>HTML body</p>"},
+            "send_at": send_at,
+        }))
+        .await;
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["issue_id"].as_str().unwrap().to_string()
+}
+
+/// Move the send time of an issue into the past
+async fn make_due(app: &TestApp, issue_id: &str) {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = datetime('now', '-1 minute') WHERE id = $1
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_sent_once_due() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule_issue(&app).await;
+
+    assert_eq!(app.issue_status(&issue_id).await, "scheduled");
+    assert_eq!(app.enqueue_due_issues().await, 0);
+    assert_eq!(app.queued_deliveries().await, 0);
+    let send_at = sqlx::query!(
+        r#"
+        SELECT send_at AS "send_at!" FROM newsletter_issues WHERE id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .send_at;
+    // Stored in UTC
+    let expected = chrono::Utc::now() + chrono::Duration::days(1);
+    assert!(send_at.starts_with(&expected.format("%Y-%m-%d").to_string()));
+
+    make_due(&app, &issue_id).await;
+    assert_eq!(app.enqueue_due_issues().await, 1);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.issue_status(&issue_id).await, "published");
+    // Only queued once
+    assert_eq!(app.enqueue_due_issues().await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn invalid_send_times_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = [
+        ("tomorrow", "a free form time"),
+        ("2026-10-20T09:00:00", "a time without timezone"),
+    ];
+
+    for (send_at, description) in test_cases {
+        let resp = app
+            .post_newsletter(serde_json::json!({
+                "title": "Later",
+                "content": {"text": "Plain body", "html": "<p>HTML body</p>"},
+                "send_at": send_at,
+            }))
+            .await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            resp.status().as_u16(),
+            "The API did not reject {}.",
+            description
+        );
+    }
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    let app = spawn_app().await;
+    let issue_id = schedule_issue(&app).await;
+
+    let resp = app
+        .post_admin_form(
+            &format!("/admin/issues/{issue_id}/reschedule"),
+            &[("send_at", "2020-01-01T09:00:00-05:00")],
+        )
+        .await;
+
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let send_at = sqlx::query!(
+        "SELECT send_at FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .send_at;
+    assert_eq!(send_at.as_deref(), Some("2020-01-01 14:00:00"));
+    assert_eq!(app.enqueue_due_issues().await, 1);
+    assert_eq!(app.issue_status(&issue_id).await, "published");
+
+    // Too late to change now
+    let resp = app
+        .post_admin_form(
+            &format!("/admin/issues/{issue_id}/reschedule"),
+            &[("send_at", "2030-01-01T09:00:00Z")],
+        )
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_sent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = schedule_issue(&app).await;
+
+    let resp = app
+        .post_admin_form(&format!("/admin/issues/{issue_id}/cancel"), &[])
+        .await;
+
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    assert_eq!(app.issue_status(&issue_id).await, "cancelled");
+    make_due(&app, &issue_id).await;
+    assert_eq!(app.enqueue_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.queued_deliveries().await, 0);
+
+    let resp = app
+        .post_admin_form(&format!("/admin/issues/{issue_id}/cancel"), &[])
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn issues_can_be_scheduled_from_the_admin_area() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_admin_form(
+            "/admin/issues",
+            &[
+                ("title", "From the composer"),
+                ("html_content", "<p>HTML body</p>"),
+                ("text_content", "Plain body"),
+                ("send_at", "2099-01-01T09:00:00+01:00"),
+            ],
+        )
+        .await;
+
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let location = resp.headers()["location"].to_str().unwrap().to_string();
+    let html = app.get_admin(&location).await.text().await.unwrap();
+    assert!(html.contains("From the composer"));
+    assert!(html.contains("2099-01-01 08:00:00"));
+    assert!(html.contains("Reschedule"));
+
+    // Without a send time it goes out straight away
+    let resp = app
+        .post_admin_form(
+            "/admin/issues",
+            &[
+                ("title", "Right now"),
+                ("html_content", "<p>HTML body</p>"),
+                ("text_content", "Plain body"),
+                ("send_at", ""),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let location = resp.headers()["location"].to_str().unwrap().to_string();
+    let html = app.get_admin(&location).await.text().await.unwrap();
+    assert!(html.contains("published"));
+    assert!(!html.contains("Reschedule"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
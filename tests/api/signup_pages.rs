@@ -0,0 +1,248 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_signup_form(
+    app: &TestApp,
+    body: &[(&str, &str)],
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/subscriptions", &app.addr))
+        .header("Accept", "text/html")
+        .form(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn create_list(app: &TestApp) {
+    let resp = app
+        .post_admin_form(
+            "/admin/lists/save",
+            &[
+                ("slug", "rust"),
+                ("name", "Rust news"),
+                ("description", "Everything Rust."),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_home_page_has_a_signup_form() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!("http://{}/", &app.addr))
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(r#"<form method="post" action="/subscriptions">"#));
+    assert!(html.contains(r#"name="form_token""#));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn invalid_signups_from_a_browser_show_the_error_inline() {
+    let app = spawn_app().await;
+
+    let resp = post_signup_form(
+        &app,
+        &[("name", "le guin"), ("email", "definitely-not-an-email")],
+    )
+    .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(r#"<p role="alert">"#));
+    assert!(html.contains(r#"value="le guin""#));
+    assert!(html.contains(r#"value="definitely-not-an-email""#));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn valid_signups_from_a_browser_show_a_check_your_inbox_page() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = post_signup_form(
+        &app,
+        &[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")],
+    )
+    .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert!(resp.text().await.unwrap().contains("Check your inbox"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn lists_have_their_own_signup_page() {
+    let app = spawn_app().await;
+    create_list(&app).await;
+
+    let resp = reqwest::get(format!("http://{}/subscribe/rust", &app.addr))
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("<h1>Subscribe to Rust news</h1>"));
+    assert!(html.contains("<p>Everything Rust.</p>"));
+    assert!(html.contains(r#"<input type="hidden" name="list" value="rust">"#));
+
+    let resp = reqwest::get(format!("http://{}/subscribe/nope", &app.addr))
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::NOT_FOUND, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn list_signup_copy_is_customized_from_the_admin_area() {
+    let app = spawn_app().await;
+    create_list(&app).await;
+
+    let resp = app
+        .post_admin_form(
+            "/admin/lists",
+            &[
+                ("slug", "rust"),
+                ("signup_heading", "Crab mail"),
+                ("signup_intro", "Weekly, no spam."),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let html = reqwest::get(format!("http://{}/subscribe/rust", &app.addr))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<h1>Crab mail</h1>"));
+    assert!(html.contains("<p>Weekly, no spam.</p>"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn lists_are_edited_from_the_admin_area() {
+    let app = spawn_app().await;
+    create_list(&app).await;
+
+    let resp = app
+        .post_admin_form(
+            "/admin/lists/save",
+            &[
+                ("slug", "rust"),
+                ("name", "Crab news"),
+                ("description", "Still Rust."),
+                ("opt_in", "single"),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let saved = sqlx::query!("SELECT name, description, opt_in FROM lists")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Crab news");
+    assert_eq!(saved.description, "Still Rust.");
+    assert_eq!(saved.opt_in.as_deref(), Some("single"));
+    let html = app.get_admin("/admin/lists").await.text().await.unwrap();
+    assert!(html.contains(r#"<option value="single" selected>"#));
+
+    // Back to the global setting
+    app.post_admin_form(
+        "/admin/lists/save",
+        &[("slug", "rust"), ("name", "Crab news"), ("opt_in", "")],
+    )
+    .await;
+    let saved = sqlx::query!("SELECT opt_in FROM lists")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.opt_in, None);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn invalid_lists_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let cases = [
+        (
+            [
+                ("slug", "Rust News!"),
+                ("name", "Rust news"),
+                ("opt_in", ""),
+            ],
+            "an invalid slug",
+        ),
+        (
+            [("slug", "rust"), ("name", " "), ("opt_in", "")],
+            "an empty name",
+        ),
+        (
+            [
+                ("slug", "rust"),
+                ("name", "Rust news"),
+                ("opt_in", "triple"),
+            ],
+            "an unknown opt-in mode",
+        ),
+    ];
+
+    for (body, description) in cases {
+        let resp = app.post_admin_form("/admin/lists/save", &body).await;
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            resp.status().as_u16(),
+            "The list was not rejected with {description}."
+        );
+    }
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,119 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use sqlx::{Connection, SqliteConnection};
+
+async fn seed_subscribers(app: &TestApp) {
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions
+            (id, email, email_canonical, name, subscribed_at, status)
+        VALUES
+            ('a', 'ursula@example.com', 'ursula@example.com', 'Ursula',
+                '2024-01-15 10:00:00', 'confirmed'),
+            ('b', 'octavia@example.com', 'octavia@example.com',
+                'Octavia, "E."', '2024-03-01 10:00:00', 'confirmed'),
+            ('c', 'iain@example.com', 'iain@example.com', 'Iain',
+                '2024-03-02 10:00:00', 'pending_confirmation');
+        INSERT INTO lists (id, slug, name) VALUES ('l1', 'rust', 'Rust news');
+        INSERT INTO subscription_lists (subscriber_id, list_id, subscribed_at)
+            VALUES ('b', 'l1', '2024-03-01 10:00:00');
+        "#,
+    )
+    .execute(&mut connection)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn all_subscribers_are_exported_as_csv() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+
+    let resp = app.get_admin("/admin/subscribers/export").await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let body = resp.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("id,email,name,status"));
+    assert!(lines[1].starts_with("a,ursula@example.com,Ursula,confirmed"));
+    assert!(lines[2].contains(r#""Octavia, ""E.""""#));
+    assert!(lines[2].contains(",rust,"));
+    assert!(lines[2].contains("2024-03-01 10:00:00"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn filtered_subscribers_are_exported_as_ndjson() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+
+    let ids = |body: String| -> Vec<String> {
+        body.lines()
+            .map(|l| {
+                let v: serde_json::Value = serde_json::from_str(l).unwrap();
+                v["id"].as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+    let export = |query: &'static str| {
+        let app = &app;
+        async move {
+            let resp = app
+                .get_admin(&format!(
+                    "/admin/subscribers/export?format=ndjson&{query}"
+                ))
+                .await;
+            assert_eq!(StatusCode::OK, resp.status().as_u16());
+            resp.text().await.unwrap()
+        }
+    };
+
+    assert_eq!(ids(export("status=confirmed").await), vec!["a", "b"]);
+    assert_eq!(ids(export("list=rust").await), vec!["b"]);
+    assert_eq!(
+        ids(export("since=2024-03-01&until=2024-03-01&status=").await),
+        vec!["b"]
+    );
+
+    let resp = app
+        .get_admin("/admin/subscribers/export?since=yesterday")
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn exporting_requires_authentication() {
+    let app = spawn_app().await;
+
+    let resp =
+        reqwest::get(format!("http://{}/admin/subscribers/export", app.addr))
+            .await
+            .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -0,0 +1,133 @@
+use crate::helpers::{cleanup_test_db, spawn_app};
+use axum::http::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn status_changes_are_recorded_in_the_history() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let history = sqlx::query!(
+        "SELECT from_status, to_status FROM subscriber_status_history
+        ORDER BY id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let transitions: Vec<_> = history
+        .iter()
+        .map(|h| (h.from_status.as_deref(), h.to_status.as_str()))
+        .collect();
+    assert_eq!(
+        transitions,
+        vec![
+            (None, "pending_confirmation"),
+            (Some("pending_confirmation"), "confirmed")
+        ]
+    );
+    let saved = sqlx::query!("SELECT status_changed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.status_changed_at.is_some());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unknown_statuses_are_rejected_by_the_database() {
+    let app = spawn_app().await;
+
+    let result = sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, status)
+        VALUES ('a', 'a@example.com', 'A', 'deleted')"
+    )
+    .execute(&app.db_pool)
+    .await;
+
+    assert!(result.is_err());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn old_confirmation_links_do_not_resubscribe() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let token = sqlx::query!("SELECT preferences_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .preferences_token
+        .unwrap();
+    app.post_preferences(&[("token", &token), ("unsubscribe_all", "on")])
+        .await;
+
+    let resp = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn subscriber_export_rejects_an_unknown_status() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .get_admin("/admin/subscribers/export?format=csv&status=deleted")
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
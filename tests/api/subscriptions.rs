@@ -1,8 +1,11 @@
-use crate::helpers::{cleanup_test_db, spawn_app};
+use crate::helpers::{
+    cleanup_test_db, spawn_app, spawn_app_with_settings, TestApp,
+};
 use axum::http::StatusCode;
 use sqlx::{Connection, SqliteConnection};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::domain::OptIn;
 
 /// Subscribe valid data
 ///
@@ -23,10 +26,11 @@ async fn subscribe_returns_200_for_valid_form_data() {
 
     assert_eq!(StatusCode::OK, resp.status().as_u16());
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 #[tokio::test]
@@ -56,10 +60,11 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.name, "bird and boy");
     assert_eq!(saved.status, "pending_confirmation");
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 /// Subscribe missing data
@@ -88,10 +93,11 @@ async fn subscribe_returns_400_for_missing_form_data() {
         );
     }
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 /// Subscribe missing fields
@@ -120,10 +126,11 @@ async fn subscribe_returns_422_for_missing_form_fields() {
         );
     }
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 #[tokio::test]
@@ -144,10 +151,11 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     // Assert
     // Mock asserts on drop
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 #[tokio::test]
@@ -182,13 +190,14 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         links[0].as_str().to_owned()
     };
 
-    let html_link = get_link(&body["HtmlBody"].as_str().unwrap());
-    let text_link = get_link(&body["TextBody"].as_str().unwrap());
+    let html_link = get_link(body["HtmlBody"].as_str().unwrap());
+    let text_link = get_link(body["TextBody"].as_str().unwrap());
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 
     // The two links should be identical
     assert_eq!(html_link, text_link);
@@ -213,10 +222,370 @@ async fn subscription_fails_if_there_is_a_fatal_database_error() {
 
     let resp = app.post_subscriptions(body.into()).await;
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 
     assert_eq!(resp.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn double_opt_in_is_recorded_on_the_subscription() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+
+    let saved = sqlx::query!("SELECT status, opt_in FROM subscriptions",)
+        .fetch_one(&mut connection)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.opt_in, "double");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn single_opt_in_confirms_immediately_and_sends_a_welcome_email() {
+    let app = spawn_app_with_settings(|s| {
+        s.subscriptions.opt_in = OptIn::Single;
+        s.subscriptions.send_welcome_email = true;
+    })
+    .await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let saved = sqlx::query!("SELECT status, opt_in FROM subscriptions",)
+        .fetch_one(&mut connection)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.opt_in, "single");
+
+    // The welcome email must not ask for a confirmation
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert!(!body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("subscriptions/confirm"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn single_opt_in_without_welcome_email_sends_nothing() {
+    let app = spawn_app_with_settings(|s| {
+        s.subscriptions.opt_in = OptIn::Single;
+        s.subscriptions.send_welcome_email = false;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn a_list_opt_in_mode_overrides_the_global_setting() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    let resp = app
+        .post_admin_form(
+            "/admin/lists/save",
+            &[
+                ("slug", "rust"),
+                ("name", "Rust news"),
+                ("opt_in", "single"),
+            ],
+        )
+        .await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&list=rust".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let saved = sqlx::query!(
+        "SELECT s.status, s.opt_in, l.slug
+        FROM subscriptions s
+        JOIN subscription_lists sl ON sl.subscriber_id = s.id
+        JOIN lists l ON l.id = sl.list_id",
+    )
+    .fetch_one(&mut connection)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.opt_in, "single");
+    assert_eq!(saved.slug, "rust");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&list=nope".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn subscribing_twice_with_a_differently_cased_email_reuses_the_subscriber(
+) {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The second attempt re-sends the confirmation email
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app
+        .post_subscriptions("name=le%20guin&email=Ursula%40Example.COM".into())
+        .await;
+    let second = app
+        .post_subscriptions(
+            "name=le%20guin&email=%20ursula%40example.com%20".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, first.status().as_u16());
+    assert_eq!(StatusCode::OK, second.status().as_u16());
+    let saved =
+        sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+            .fetch_all(&mut connection)
+            .await
+            .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@Example.COM");
+    assert_eq!(
+        saved[0].email_canonical.as_deref(),
+        Some("ursula@example.com")
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+/// Click the link of the last email sent
+async fn click_last_confirmation_link(app: &TestApp) {
+    let requests = app.email_server.received_requests().await.unwrap();
+    let links = app.get_confirmation_links(requests.last().unwrap());
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn emails_sent(app: &TestApp) -> usize {
+    app.email_server.received_requests().await.unwrap().len()
+}
+
+#[tokio::test]
+async fn an_existing_subscriber_confirms_before_joining_another_list() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    // Single opt-in must not let someone else add the address either
+    sqlx::query!(
+        "INSERT INTO lists (id, slug, name, opt_in)
+        VALUES ('l1', 'rust', 'Rust news', 'single')",
+    )
+    .execute(&mut connection)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+    click_last_confirmation_link(&app).await;
+
+    let resp = app
+        .post_subscriptions(
+            "name=mallory&email=ursula_le_guin%40gmail.com&list=rust".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(emails_sent(&app).await, 2);
+    let lists = || {
+        sqlx::query!(
+            r#"SELECT count(*) AS "count!: i64" FROM subscription_lists"#
+        )
+    };
+    assert_eq!(lists().fetch_one(&mut connection).await.unwrap().count, 0);
+
+    click_last_confirmation_link(&app).await;
+
+    assert_eq!(lists().fetch_one(&mut connection).await.unwrap().count, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn an_unsubscribed_subscriber_confirms_before_coming_back() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    click_last_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&mut connection)
+        .await
+        .unwrap();
+
+    let resp = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(emails_sent(&app).await, 2);
+    let status = || sqlx::query!("SELECT status FROM subscriptions");
+    assert_eq!(
+        status().fetch_one(&mut connection).await.unwrap().status,
+        "unsubscribed"
+    );
+
+    click_last_confirmation_link(&app).await;
+
+    assert_eq!(
+        status().fetch_one(&mut connection).await.unwrap().status,
+        "confirmed"
+    );
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn signing_up_again_when_nothing_would_change_is_quietly_ignored() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    click_last_confirmation_link(&app).await;
+
+    let resp = app.post_subscriptions(body.into()).await;
+
+    // Same answer as for an address we do not know
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert_eq!(emails_sent(&app).await, 1);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
@@ -11,17 +11,18 @@ async fn confirmations_without_token_are_rejected_with_a_400() {
     let app = spawn_app().await;
 
     let resp = reqwest::Client::new()
-        .get(&format!("http://{}/subscriptions/confirm", app.addr))
+        .get(format!("http://{}/subscriptions/confirm", app.addr))
         .send()
         .await
         .expect("Failed to execute request");
 
     assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 #[tokio::test]
@@ -38,14 +39,15 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 #[tokio::test]
@@ -62,16 +64,17 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     let resp = reqwest::get(confirmation_links.html).await.unwrap();
 
     assert_eq!(resp.status().as_u16(), 200);
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
 
 #[tokio::test]
@@ -91,7 +94,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     reqwest::get(confirmation_links.html)
         .await
@@ -108,8 +111,166 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 
-    cleanup_test_db(app.db_name.clone()).await.expect(&format!(
-        "Failure to delete test database {}",
-        app.db_name.as_str()
-    ));
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn confirming_renders_a_page_with_next_steps() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    let resp = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("Subscription confirmed"));
+    assert!(html.contains("/preferences?token="));
+
+    // Following the link again is harmless
+    let resp = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    assert!(resp.text().await.unwrap().contains("Already confirmed"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn confirmation_outcome_is_json_when_requested() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    let resp = reqwest::Client::new()
+        .get(confirmation_links.html)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let json: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(json["status"], "confirmed");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn unknown_tokens_render_an_invalid_link_page() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!(
+        "http://{}/subscriptions/confirm?subscription_token=nope",
+        app.addr
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("Invalid confirmation link"));
+    assert!(html.contains("Sign up again"));
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn expired_links_can_be_resent() {
+    let app = spawn_app().await;
+    let mut connection = SqliteConnection::connect(&app.db_name)
+        .await
+        .expect("Failed to connect to database.");
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let expired_link = app.get_confirmation_links(email_request).html;
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = '2020-01-01 00:00:00'"
+    )
+    .execute(&mut connection)
+    .await
+    .unwrap();
+
+    let resp = reqwest::get(expired_link.clone()).await.unwrap();
+    assert_eq!(StatusCode::GONE, resp.status().as_u16());
+    assert!(resp.text().await.unwrap().contains("Send me a new link"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let token = expired_link
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/subscriptions/confirm/resend", app.addr))
+        .form(&[("subscription_token", token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let fresh_link = app.get_confirmation_links(email_request).html;
+    assert_ne!(fresh_link, expired_link);
+    reqwest::get(fresh_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
 }
@@ -0,0 +1,186 @@
+use crate::helpers::{cleanup_test_db, spawn_app, TestApp};
+use axum::http::StatusCode;
+use reqwest::Url;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn save_layout(app: &TestApp, text_footer: &str) -> reqwest::Response {
+    app.post_admin_form(
+        "/admin/layouts",
+        &[
+            ("name", "default"),
+            ("html_header", "<h1>The newsletter</h1>"),
+            (
+                "html_footer",
+                r#"<p><a href="{{ unsubscribe_url }}">Unsubscribe</a></p>"#,
+            ),
+            ("text_header", ""),
+            ("text_footer", text_footer),
+        ],
+    )
+    .await
+}
+
+async fn stored_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "n!: i64" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn undefined_variables_fail_the_publish() {
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({
+                "title": "Hi {{ nickname }}",
+                "content": {"text": "Hi", "html": "<p>Hi</p>"}
+            }),
+            "an unknown variable in the title",
+        ),
+        (
+            serde_json::json!({
+                "title": "Hi",
+                "content": {
+                    "text": "Hi {{ attributes.plan }}",
+                    "html": "<p>Hi</p>"
+                }
+            }),
+            "an unknown attribute",
+        ),
+        (
+            serde_json::json!({
+                "title": "Hi",
+                "content": {"text": "Hi {% if %}", "html": "<p>Hi</p>"}
+            }),
+            "a syntax error",
+        ),
+        (
+            serde_json::json!({
+                "title": "Hi",
+                "content": {"text": "Hi", "html": "<p>Hi</p>"},
+                "translations": {
+                    "fr": {
+                        "title": "Salut {{ nom }}",
+                        "content": {"text": "Salut", "html": "<p>Salut</p>"}
+                    }
+                }
+            }),
+            "an unknown variable in a translation",
+        ),
+        (
+            serde_json::json!({
+                "title": "Hi",
+                "content": {"text": "Hi", "html": "<p>Hi</p>"},
+                "layout": "missing"
+            }),
+            "an unknown layout",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let resp = app.post_newsletter(body).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            resp.status().as_u16(),
+            "The API did not reject {}.",
+            description
+        );
+    }
+    assert_eq!(stored_issues(&app).await, 0);
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn issues_are_rendered_for_every_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let resp = save_layout(&app, "\n\nRead online: {{ web_view_url }}").await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "News for {{ name }}",
+            "content": {
+                "text": "Hi {{ name }}",
+                "html": "<p>Hi {{ name }}</p>",
+            },
+            "layout": "default"
+        }))
+        .await;
+    assert_eq!(StatusCode::ACCEPTED, resp.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(body["Subject"], "News for le guin");
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<h1>The newsletter</h1><p>Hi le guin</p>"));
+    let text = body["TextBody"].as_str().unwrap();
+    let (text, web_view_url) = text.split_once("\n\nRead online: ").unwrap();
+    assert_eq!(text, "Hi le guin");
+
+    let mut web_view_url = Url::parse(web_view_url).unwrap();
+    web_view_url.set_port(Some(app.port)).unwrap();
+    let resp = reqwest::get(web_view_url.clone()).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status().as_u16());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("<p>Hi le guin</p>"));
+    assert!(html.contains("/preferences?token="));
+
+    // Nobody else can read it
+    web_view_url.set_query(Some("token=not-a-token"));
+    let resp = reqwest::get(web_view_url).await.unwrap();
+    assert_eq!(StatusCode::NOT_FOUND, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}
+
+#[tokio::test]
+async fn layouts_are_validated_and_kept_while_in_use() {
+    let app = spawn_app().await;
+
+    let resp = save_layout(&app, "{{ web_view }}").await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    let resp = save_layout(&app, "").await;
+    assert_eq!(StatusCode::SEE_OTHER, resp.status().as_u16());
+    let resp = app
+        .post_newsletter(serde_json::json!({
+            "title": "Hi",
+            "content": {"text": "Hi", "html": "<p>Hi</p>"},
+            "layout": "default",
+            "draft": true
+        }))
+        .await;
+    assert_eq!(StatusCode::CREATED, resp.status().as_u16());
+
+    let resp = app
+        .post_admin_form("/admin/layouts/delete", &[("name", "default")])
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, resp.status().as_u16());
+
+    cleanup_test_db(app.db_name.clone())
+        .await
+        .unwrap_or_else(|_| {
+            panic!("Failure to delete test database {}", app.db_name.as_str())
+        });
+}